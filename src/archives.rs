@@ -1,4 +1,4 @@
-use std::{path::PathBuf, collections::BTreeSet, io::{stdout, Write}};
+use std::{path::{PathBuf, Path}, collections::BTreeSet, io::{stdout, Write}};
 
 use colored::Colorize;
 use tempfile::{Builder, TempDir};
@@ -6,9 +6,9 @@
 use walkdir::WalkDir;
 use zip_extensions::zip_extract;
 
-use crate::{error::{CleanerResult, CleanerError}, cleaner::clean_files};
+use crate::{error::{CleanerResult, CleanerError}, cleaner::clean_files, options::Options, library::Library, name_patterns::NameFields};
 
-pub fn process_archives(path: &PathBuf, output_path: &PathBuf, quality: u8) {
+pub fn process_archives(path: &PathBuf, output_path: &Path, options: &Options, library: &mut Library) {
     if path.is_dir() {
         println!("Processing archives in {} to {}...\n",
             path.to_string_lossy().bright_yellow().bold(),
@@ -36,8 +36,15 @@ pub fn process_archives(path: &PathBuf, output_path: &PathBuf, quality: u8) {
         match extract_archive(&archive) {
             Ok(temp_dir) => {
                 println!("{}", "OK".bright_green().bold());
+
+                // The archive name is only used for values that the extracted files have no other source for
+                let archive_fields = options.path_patterns.parse_archive_name(&archive);
+                if options.show_patterns {
+                    println!("  Parse {} {}", format_archive_fields(&archive_fields).dimmed(), archive_fields.pattern.as_deref().unwrap_or("no pattern matched").dimmed());
+                }
+
                 let temp_path = temp_dir.path().to_path_buf();
-                clean_files(&temp_path, output_path, quality);
+                clean_files(&temp_path, output_path, options, library, Some(&archive), Some(&archive_fields));
             },
             Err(err) => println!("{} {}\n", "ERROR".bright_red().bold(), err.to_string().red())
         }
@@ -47,9 +54,19 @@ pub fn process_archives(path: &PathBuf, output_path: &PathBuf, quality: u8) {
 
 }
 
+fn format_archive_fields(archive_fields: &NameFields) -> String {
+    let year = archive_fields.year.map(|y| y.to_string());
+    [archive_fields.artist.as_deref(), archive_fields.album.as_deref(), year.as_deref(), archive_fields.catalogue.as_deref()]
+        .iter()
+        .flatten()
+        .copied()
+        .collect::<Vec<&str>>()
+        .join(" / ")
+}
+
 fn get_archives(path: &PathBuf) -> BTreeSet<PathBuf> {
     if path.is_dir() {
-        let walker = WalkDir::new(&path)
+        let walker = WalkDir::new(path)
             .min_depth(1)
             .max_depth(1);
 
@@ -96,7 +113,7 @@ fn extract_zip_archive(archive_path: &PathBuf, output_path: &PathBuf) -> Cleaner
     Ok(())
 }
 
-fn extract_rar_archive(archive_path: &PathBuf, output_path: &PathBuf) -> CleanerResult<()> {
+fn extract_rar_archive(archive_path: &Path, output_path: &Path) -> CleanerResult<()> {
     let archive_name = archive_path.to_str().unwrap().to_string();
     let output_name = output_path.to_str().unwrap().to_string();
     Archive::new(archive_name)
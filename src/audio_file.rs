@@ -1,114 +1,214 @@
-use std::path::PathBuf;
+use std::{path::{Path, PathBuf}, cell::OnceCell};
 
-use lazy_static::lazy_static;
-use lofty::{Accessor, TaggedFileExt, Tag, ItemKey};
-use regex::Regex;
+use lofty::{Accessor, TaggedFileExt, ItemKey};
 
-use crate::{media_file::MediaFile, audio_file_meta::{AudioFileMeta, AudioFileType}, tagger::get_tagged_file};
+use crate::{error::CleanerResult, media_file::MediaFile, audio_file_meta::{AudioFileMeta, AudioFileType}, tagger::get_tagged_file, decoder::hash_audio_stream, fingerprint::{Fingerprint, calculate_fingerprint}, name_patterns::NameFields, options::Options, metadata_field::MetadataField, metadata_source::MetadataSource, cue_sheet::read_cue_sheet, provenance::Provenance, tag_fields::{ALBUM_ARTIST_FIELD, CATALOGUE_FIELD}, multi_value::Values};
 
 pub struct AudioFile {
     path: PathBuf,
-    meta: AudioFileMeta
+    meta: AudioFileMeta,
+    filename_pattern: Option<String>,
+    directory_pattern: Option<String>,
+    side: Option<u32>,
+    audio_hash: OnceCell<Option<String>>,
+    fingerprint: OnceCell<Option<Fingerprint>>
 }
 
 impl AudioFile {
-    pub fn new(root_path: &PathBuf, path: PathBuf) -> AudioFile {
-        let meta = Self::build_meta(root_path, &path);
-
-        AudioFile {
+    pub fn new(root_path: &PathBuf, path: PathBuf, options: &Options, archive_fields: Option<&NameFields>) -> CleanerResult<AudioFile> {
+        let filename_fields = options.path_patterns.parse_file_name(&path);
+        let directory_fields = options.path_patterns.parse_directories(root_path, &path);
+        let filename_pattern = filename_fields.pattern.to_owned();
+        let directory_pattern = directory_fields.pattern.to_owned();
+        let side = filename_fields.side;
+        let meta = Self::build_meta(&path, options, directory_fields, filename_fields, archive_fields)?;
+
+        // A side only matters where the track number, counted within it, was taken from the file name
+        let side = side.filter(|_| meta.provenance().source(MetadataField::Track) == Some(MetadataSource::Filename));
+
+        Ok(AudioFile {
             path,
-            meta
-        }
+            meta,
+            filename_pattern,
+            directory_pattern,
+            side,
+            audio_hash: OnceCell::new(),
+            fingerprint: OnceCell::new()
+        })
     }
 
     pub fn get_meta(&self) -> &AudioFileMeta {
         &self.meta
     }
 
-    fn build_meta(root_path: &PathBuf, path: &PathBuf) -> AudioFileMeta {
-        let path_artist_name = decompose_artist_path(root_path, path);
-
-        let (
-            path_album_title,
-            path_album_year
-        ) = decompose_album_path(root_path, path);
-
-        let (
-            path_track_number,
-            path_track_title,
-            audio_file_type
-        ) = decompose_file_path(path);
-
-        let tagged_file = get_tagged_file(&path).expect("Failed to get tagged file");
-
-        let album_artist_name: Option<String>;
-        let artist_name: Option<String>;
-        let album_title: Option<String>;
-        let year: Option<u32>;
-        let track_number: Option<u32>;
-        let track_title: Option<String>;
-        let genre: Option<String>;
-
-        if let Some(tag) = tagged_file.primary_tag() {
-            // Album artist name from album artist tag, artist tag, or artist directory name
-            album_artist_name = Self::get_album_artist(tag)
-                .or_else(|| tag.artist().map(|s| s.trim().to_string()))
-                .or_else(|| path_artist_name.as_ref().map(|s| s.to_string()));
-
-            // Artist name from artist tag, album artist tag, or artist directory name
-            artist_name = tag.artist()
-                .map(|s| s.trim().to_string())
-                .or_else(|| Self::get_album_artist(tag))
-                .or_else(|| path_artist_name.as_ref().map(|s| s.to_owned()));
-
-            // Album title from tag, or album directory name
-            album_title = tag.album()
-                .map(|s| s.trim().to_string())
-                .or_else(|| path_album_title.map(|s| s.to_owned()));
-
-            // Year from tag, or album directory name
-            year = tag.year()
-                .or_else(|| path_album_year);
-
-            // Track number from tag, or file name
-            track_number = tag.track()
-                .or_else(|| path_track_number);
-
-            // Track title from tag, or file name
-            track_title = tag.title()
-                .map(|s| s.trim().to_string())
-                .or_else(|| path_track_title.map(|s| s.to_owned()));
-
-            // Genre from tag, no fallback available
-            genre = tag.genre()
-                .map(|s| s.trim().to_string());
-        } else {
-            album_artist_name = path_artist_name.as_ref().map(|s| s.to_owned());
-            artist_name = path_artist_name.as_ref().map(|s| s.to_owned());
-            album_title = path_album_title.map(|s| s.to_owned());
-            year = path_album_year;
-            track_number = path_track_number;
-            track_title = path_track_title.map(|s| s.to_owned());
-            genre = None;
-        }
-
-        AudioFileMeta::new(
+    /// The pattern that the file name was parsed with, if any matched
+    pub fn filename_pattern(&self) -> Option<&str> {
+        self.filename_pattern.as_deref()
+    }
+
+    /// The pattern that the album directory name was parsed with, if any matched
+    pub fn directory_pattern(&self) -> Option<&str> {
+        self.directory_pattern.as_deref()
+    }
+
+    /// The vinyl side given by the file name, where the track number was taken from it
+    pub fn side(&self) -> Option<u32> {
+        self.side
+    }
+
+    pub fn set_track_number(&mut self, track_number: u32) {
+        self.meta.set_track_number(Some(track_number));
+    }
+
+    /// Hash of the audio stream only, ignoring tags, calculated the first time it is needed
+    pub fn audio_hash(&self) -> Option<&str> {
+        self.audio_hash
+            .get_or_init(|| hash_audio_stream(&self.path).ok())
+            .as_deref()
+    }
+
+    /// Acoustic fingerprint of the decoded audio, calculated the first time it is needed
+    pub fn fingerprint(&self) -> Option<&Fingerprint> {
+        self.fingerprint
+            .get_or_init(|| calculate_fingerprint(&self.path).ok())
+            .as_ref()
+    }
+
+    fn build_meta(path: &PathBuf, options: &Options, directory_fields: NameFields, filename_fields: NameFields, archive_fields: Option<&NameFields>) -> CleanerResult<AudioFileMeta> {
+        let audio_file_type = get_audio_file_type(path);
+        let precedence = &options.precedence;
+        let tag_fields = &options.tag_fields;
+
+        let tagged_file = get_tagged_file(path)?;
+        let tag = tagged_file.primary_tag();
+
+        let cue_sheet = path
+            .parent()
+            .filter(|_| precedence.uses(MetadataSource::Sidecar))
+            .and_then(read_cue_sheet);
+        let cue_sheet = cue_sheet.as_ref();
+
+        let default_text = |field: MetadataField| precedence.default_value(field).map(|s| s.to_string());
+        let default_number = |field: MetadataField| precedence.default_value(field).and_then(|s| s.parse::<u32>().ok());
+
+        // Placeholder values from tags and sidecars, e.g. "Unknown Album", or an album title that is just the file name,
+        // are treated as missing so that other sources are used
+        let file_stem = path.file_stem().and_then(|s| s.to_str());
+        let real = |value: Option<String>| options.placeholders.filter(value, None);
+        let real_named = |value: Option<String>| options.placeholders.filter(value, file_stem);
+
+        // Fields with several values, e.g. "Artist feat. Other", are split at their delimiters, then each value is checked
+        let split = |field: MetadataField, values: Vec<String>| {
+            let values = options.delimiters.split(field, values).into_iter().filter_map(|v| real(Some(v))).collect::<Vec<String>>();
+            Some(Values(values)).filter(|v| !v.0.is_empty())
+        };
+        // Multi-valued Vorbis fields, and ID3v2.4 frames with values separated by terminators, are read as several items
+        let tag_values = |key: ItemKey| tag.map(|t| t.get_strings(&key).map(|s| s.to_string()).collect::<Vec<String>>()).unwrap_or_default();
+        let tag_album_artist = tag.and_then(|t| tag_fields.read(ALBUM_ARTIST_FIELD, t));
+
+        let mut provenance = Provenance::default();
+
+        // Each field is taken from the first of its sources, in order of precedence, to have a value. Prompts are made
+        // later, per album.
+
+        // Album artist tag, falling back to the first of the artists of the artist tag, e.g. Artist of "Artist feat. Other"
+        let album_artist_name = precedence.resolve(MetadataField::AlbumArtist, &mut provenance, |source| match source {
+            MetadataSource::Tag => real(tag_album_artist.to_owned()).or_else(|| split(MetadataField::Artist, tag_values(ItemKey::TrackArtist)).and_then(|v| v.0.into_iter().next())),
+            MetadataSource::Path => directory_fields.artist.to_owned(),
+            MetadataSource::Filename => filename_fields.artist.to_owned(),
+            MetadataSource::Sidecar => real(cue_sheet.and_then(|c| c.performer.to_owned())),
+            MetadataSource::Archive => archive_fields.and_then(|f| f.artist.to_owned()),
+            MetadataSource::Default => default_text(MetadataField::AlbumArtist),
+            MetadataSource::Prompt => None
+        });
+
+        let track_number = precedence.resolve(MetadataField::Track, &mut provenance, |source| match source {
+            MetadataSource::Tag => tag.and_then(|t| t.track()),
+            MetadataSource::Filename => filename_fields.track,
+            MetadataSource::Default => default_number(MetadataField::Track),
+            _ => None
+        });
+
+        // Artist tag, falling back to the album artist tag
+        let artist_names = precedence.resolve(MetadataField::Artist, &mut provenance, |source| match source {
+            MetadataSource::Tag => split(MetadataField::Artist, tag_values(ItemKey::TrackArtist)).or_else(|| split(MetadataField::Artist, tag_album_artist.iter().cloned().collect())),
+            MetadataSource::Path => split(MetadataField::Artist, directory_fields.artist.iter().cloned().collect()),
+            MetadataSource::Filename => split(MetadataField::Artist, filename_fields.artist.iter().cloned().collect()),
+            MetadataSource::Sidecar => split(MetadataField::Artist, cue_sheet.zip(track_number).and_then(|(c, n)| c.track_performer(n).map(|s| s.to_string())).into_iter().collect()),
+            MetadataSource::Archive => split(MetadataField::Artist, archive_fields.and_then(|f| f.artist.to_owned()).into_iter().collect()),
+            MetadataSource::Default => split(MetadataField::Artist, default_text(MetadataField::Artist).into_iter().collect()),
+            MetadataSource::Prompt => None
+        }).map(|v| v.0).unwrap_or_default();
+
+        let album_title = precedence.resolve(MetadataField::Album, &mut provenance, |source| match source {
+            MetadataSource::Tag => real_named(tag.and_then(|t| t.album()).map(|s| s.trim().to_string())),
+            MetadataSource::Path => directory_fields.album.to_owned(),
+            MetadataSource::Filename => filename_fields.album.to_owned(),
+            MetadataSource::Sidecar => real(cue_sheet.and_then(|c| c.title.to_owned())),
+            MetadataSource::Archive => archive_fields.and_then(|f| f.album.to_owned()),
+            MetadataSource::Default => default_text(MetadataField::Album),
+            MetadataSource::Prompt => None
+        });
+
+        let year = precedence.resolve(MetadataField::Year, &mut provenance, |source| match source {
+            MetadataSource::Tag => tag.and_then(|t| t.year()),
+            MetadataSource::Path => directory_fields.year,
+            MetadataSource::Filename => filename_fields.year,
+            MetadataSource::Sidecar => cue_sheet.and_then(|c| c.year),
+            MetadataSource::Archive => archive_fields.and_then(|f| f.year),
+            MetadataSource::Default => default_number(MetadataField::Year),
+            MetadataSource::Prompt => None
+        });
+
+        let disc_number = precedence.resolve(MetadataField::Disc, &mut provenance, |source| match source {
+            MetadataSource::Tag => tag.and_then(|t| t.disk()),
+            MetadataSource::Path => directory_fields.disc,
+            MetadataSource::Filename => filename_fields.disc,
+            MetadataSource::Default => default_number(MetadataField::Disc),
+            _ => None
+        });
+
+        let track_title = precedence.resolve(MetadataField::Title, &mut provenance, |source| match source {
+            MetadataSource::Tag => real(tag.and_then(|t| t.title()).map(|s| s.trim().to_string())),
+            MetadataSource::Filename => filename_fields.title.to_owned(),
+            MetadataSource::Sidecar => real(cue_sheet.zip(track_number).and_then(|(c, n)| c.track_title(n).map(|s| s.to_string()))),
+            MetadataSource::Default => default_text(MetadataField::Title),
+            _ => None
+        });
+
+        let genres = precedence.resolve(MetadataField::Genre, &mut provenance, |source| match source {
+            MetadataSource::Tag => split(MetadataField::Genre, tag_values(ItemKey::Genre)),
+            MetadataSource::Sidecar => split(MetadataField::Genre, cue_sheet.and_then(|c| c.genre.to_owned()).into_iter().collect()),
+            MetadataSource::Default => split(MetadataField::Genre, default_text(MetadataField::Genre).into_iter().collect()),
+            _ => None
+        }).map(|v| v.0).unwrap_or_default();
+
+        let catalogue_number = precedence.resolve(MetadataField::Catalogue, &mut provenance, |source| match source {
+            MetadataSource::Tag => real(tag.and_then(|t| tag_fields.read(CATALOGUE_FIELD, t))),
+            MetadataSource::Sidecar => cue_sheet.and_then(|c| c.catalogue.to_owned()),
+            MetadataSource::Archive => archive_fields.and_then(|f| f.catalogue.to_owned()),
+            MetadataSource::Default => default_text(MetadataField::Catalogue),
+            _ => None
+        });
+
+        // Custom fields are only read from tags, and written back with the keys they are mapped to
+        let custom_fields = tag.map(|t| tag_fields.read_custom(t)).unwrap_or_default();
+
+        Ok(AudioFileMeta {
             tagged_file,
             album_artist_name,
-            artist_name,
+            artist_names,
             album_title,
             year,
             track_number,
+            disc_number,
             track_title,
-            genre,
-            audio_file_type
-        )
-    }
-
-    fn get_album_artist(tag: &Tag) -> Option<String> {
-        tag
-            .get_string(&ItemKey::AlbumArtist)
-            .map(|s| s.trim().to_string())
+            genres,
+            catalogue_number,
+            tag_fields: custom_fields,
+            audio_file_type,
+            provenance
+        })
     }
 }
 
@@ -118,76 +218,10 @@ fn path(&self) -> &PathBuf {
     }
 }
 
-fn decompose_artist_path(root_path: &PathBuf, path: &PathBuf) -> Option<String> {
+fn get_audio_file_type(path: &Path) -> Option<AudioFileType> {
     path
-        .parent()
-        .and_then(|p| p.parent())
-        .filter(|p| p != root_path)
-        .and_then(|p| p.file_name())
-        .and_then(|s| s.to_str())
-        .map(|s| s.trim().to_string())
-}
-
-fn decompose_album_path(root_path: &PathBuf, path: &PathBuf) -> (Option<String>, Option<u32>) {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"^(.+)\s\(?(\d{4})\)?$").unwrap();
-    }
-
-    let album_path_name = path
-        .parent()
-        .filter(|p| p != root_path)
-        .and_then(|p| p.file_name())
-        .and_then(|s| s.to_str());
-
-    if let Some(album_path_name) = album_path_name {
-        if let Some(captures) = RE.captures(album_path_name) {
-            let album_title = captures
-                .get(1)
-                .map(|m| m.as_str())
-                .map(|s| s.trim().to_string());
-
-            let album_year = captures
-                .get(2)
-                .map(|m| m.as_str())
-                .and_then(|s| s.parse::<u32>().ok());
-
-            return (album_title, album_year);
-        }
-    }
-
-    (None::<String>, None::<u32>)
-}
-
-fn decompose_file_path(path: &PathBuf) -> (Option<u32>, Option<String>, Option<AudioFileType>) {
-    lazy_static! {
-        static ref RE: Regex = Regex::new(r"^(?:(\d+)\.?(?:\s\-\s|\s))?(.+)$").unwrap();
-    }
-
-    let audio_file_type = path
         .extension()
         .and_then(|e| e.to_str())
         .map(|s| s.to_lowercase())
-        .and_then(|s| Some(s.parse::<AudioFileType>()))
-        .and_then(|r| r.ok());
-
-    if let Some(file_stem) = path
-        .file_stem()
-        .and_then(|s| s.to_str()) {
-            if let Some(captures) = RE.captures(file_stem) {
-                let track_number = captures
-                    .get(1)
-                    .map(|m| m.as_str())
-                    .and_then(|s| s.parse::<u32>().ok());
-
-                let track_name = captures
-                    .get(2)
-                    .map(|m| m.as_str())
-                    .map(|s| s.trim().to_string());
-
-                return (track_number, track_name, audio_file_type);
-            }
-            return (None::<u32>, Some(file_stem.to_string()), audio_file_type);
-        }
-
-    (None::<u32>, None::<String>, audio_file_type)
+        .and_then(|s| s.parse::<AudioFileType>().ok())
 }
@@ -1,47 +1,34 @@
-use lofty::TaggedFile;
+use std::{time::Duration, collections::BTreeMap};
 
+use lofty::{TaggedFile, AudioFile};
+
+use crate::{provenance::Provenance, multi_value::join_values};
+
+/// Metadata of an audio file, with the value of each field taken from the first of its sources to have one
 pub struct AudioFileMeta {
-    tagged_file: TaggedFile,
-    album_artist_name: Option<String>,
-    artist_name: Option<String>,
-    album_title: Option<String>,
-    year: Option<u32>,
-    track_number: Option<u32>,
-    track_title: Option<String>,
-    genre: Option<String>,
-    audio_file_type: Option<AudioFileType>
+    pub tagged_file: TaggedFile,
+    pub album_artist_name: Option<String>,
+    pub artist_names: Vec<String>,
+    pub album_title: Option<String>,
+    pub year: Option<u32>,
+    pub track_number: Option<u32>,
+    pub disc_number: Option<u32>,
+    pub track_title: Option<String>,
+    pub genres: Vec<String>,
+    pub catalogue_number: Option<String>,
+    /// Values of custom fields, by name
+    pub tag_fields: BTreeMap<String, String>,
+    pub audio_file_type: Option<AudioFileType>,
+    pub provenance: Provenance
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum AudioFileType {
     Flac,
     Mp3
 }
 
 impl AudioFileMeta {
-    pub fn new(
-        tagged_file: TaggedFile,
-        album_artist_name: Option<String>,
-        artist_name: Option<String>,
-        album_title: Option<String>,
-        year: Option<u32>,
-        track_number: Option<u32>,
-        track_title: Option<String>,
-        genre: Option<String>,
-        audio_file_type: Option<AudioFileType>
-    ) -> AudioFileMeta {
-        AudioFileMeta {
-            tagged_file,
-            album_artist_name,
-            artist_name,
-            album_title,
-            year,
-            track_number,
-            track_title,
-            genre,
-            audio_file_type
-        }
-    }
-
     pub fn tagged_file(&self) -> &TaggedFile {
         &self.tagged_file
     }
@@ -58,8 +45,13 @@ pub fn year(&self) -> Option<u32> {
         self.year
     }
 
-    pub fn artist_name(&self) -> Option<&str> {
-        self.artist_name.as_deref()
+    pub fn artist_names(&self) -> &[String] {
+        &self.artist_names
+    }
+
+    /// The artists joined into one value, for formats and names with room for only one
+    pub fn artist_name(&self) -> Option<String> {
+        join_values(&self.artist_names)
     }
 
     pub fn track_title(&self) -> Option<&str> {
@@ -70,23 +62,105 @@ pub fn track_number(&self) -> Option<u32> {
         self.track_number
     }
 
-    pub fn genre(&self) -> Option<&str> {
-        self.genre.as_deref()
+    pub fn set_track_number(&mut self, track_number: Option<u32>) {
+        self.track_number = track_number;
+    }
+
+    pub fn disc_number(&self) -> Option<u32> {
+        self.disc_number
+    }
+
+    pub fn genres(&self) -> &[String] {
+        &self.genres
+    }
+
+    /// The genres joined into one value, for formats and names with room for only one
+    pub fn genre(&self) -> Option<String> {
+        join_values(&self.genres)
+    }
+
+    pub fn catalogue_number(&self) -> Option<&str> {
+        self.catalogue_number.as_deref()
+    }
+
+    /// Values of the custom fields of the tag mapping, by name
+    pub fn tag_fields(&self) -> &BTreeMap<String, String> {
+        &self.tag_fields
+    }
+
+    /// Where each value came from
+    pub fn provenance(&self) -> &Provenance {
+        &self.provenance
     }
 
     pub fn audio_file_type(&self) -> Option<&AudioFileType> {
         self.audio_file_type.as_ref()
     }
+
+    pub fn duration(&self) -> Duration {
+        self.tagged_file.properties().duration()
+    }
+
+    /// Audio bitrate in kbps, falling back to the overall bitrate
+    pub fn bitrate(&self) -> Option<u32> {
+        let properties = self.tagged_file.properties();
+        properties.audio_bitrate()
+            .or_else(|| properties.overall_bitrate())
+    }
+
+    /// Sample rate in Hz
+    pub fn sample_rate(&self) -> Option<u32> {
+        self.tagged_file.properties().sample_rate()
+    }
+
+    pub fn bit_depth(&self) -> Option<u8> {
+        self.tagged_file.properties().bit_depth()
+    }
+
+    pub fn channels(&self) -> Option<u8> {
+        self.tagged_file.properties().channels()
+    }
+
+    /// Short description of the audio resolution, e.g. "24/96", or "44.1kHz" if there is no bit depth (lossy files)
+    pub fn resolution(&self) -> Option<String> {
+        match (self.bit_depth(), self.sample_rate()) {
+            (Some(bit_depth), Some(sample_rate)) => Some(format!("{}/{}", bit_depth, format_sample_rate(sample_rate))),
+            (None, Some(sample_rate)) => Some(format!("{}kHz", format_sample_rate(sample_rate))),
+            _ => None
+        }
+    }
+}
+
+fn format_sample_rate(sample_rate: u32) -> String {
+    if sample_rate.is_multiple_of(1000) {
+        (sample_rate / 1000).to_string()
+    } else {
+        format!("{:.1}", sample_rate as f32 / 1000.0)
+    }
 }
 
 impl AudioFileType {
 
-    pub fn to_extension(&self) -> &str {
-        match *self {
+    pub fn to_extension(self) -> &'static str {
+        match self {
             AudioFileType::Flac => "flac",
             AudioFileType::Mp3 => "mp3"
         }
     }
+
+    pub fn to_name(self) -> &'static str {
+        match self {
+            AudioFileType::Flac => "FLAC",
+            AudioFileType::Mp3 => "MP3"
+        }
+    }
+
+    pub fn is_lossless(self) -> bool {
+        match self {
+            AudioFileType::Flac => true,
+            AudioFileType::Mp3 => false
+        }
+    }
 }
 
 impl std::str::FromStr for AudioFileType {
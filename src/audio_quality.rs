@@ -0,0 +1,52 @@
+use crate::audio_file::AudioFile;
+
+/// Average bitrate, in kbps, at or above which lossy audio is considered high quality
+const HIGH_BITRATE: u32 = 224;
+
+/// Quality ranking for a set of tracks, lossless is better than high bitrate lossy, which is better than low bitrate
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AudioQuality {
+    LowBitrate,
+    HighBitrate,
+    Lossless { bit_depth: u8, sample_rate: u32 },
+}
+
+impl AudioQuality {
+
+    /// The quality of a set of tracks, using the lowest resolution of any lossless track or the average lossy bitrate
+    pub fn of_tracks(audio_files: &Vec<&AudioFile>) -> AudioQuality {
+        let metas = audio_files
+            .iter()
+            .map(|f| f.get_meta())
+            .collect::<Vec<_>>();
+
+        if !metas.is_empty() && metas.iter().all(|m| m.audio_file_type().is_some_and(|t| t.is_lossless())) {
+            return AudioQuality::Lossless {
+                bit_depth: metas.iter().filter_map(|m| m.bit_depth()).min().unwrap_or(0),
+                sample_rate: metas.iter().filter_map(|m| m.sample_rate()).min().unwrap_or(0)
+            };
+        }
+
+        let bitrates = metas
+            .iter()
+            .filter_map(|m| m.bitrate())
+            .collect::<Vec<u32>>();
+        let average_bitrate = bitrates.iter().sum::<u32>() / bitrates.len().max(1) as u32;
+
+        if average_bitrate >= HIGH_BITRATE {
+            AudioQuality::HighBitrate
+        } else {
+            AudioQuality::LowBitrate
+        }
+    }
+
+    /// Short description of the resolution for lossless audio better than CD quality, e.g. "24-96"
+    pub fn resolution(&self) -> Option<String> {
+        match *self {
+            AudioQuality::Lossless { bit_depth, sample_rate } if bit_depth > 16 || sample_rate > 48000 => {
+                Some(format!("{}-{}", bit_depth, sample_rate / 1000))
+            },
+            _ => None
+        }
+    }
+}
@@ -1,131 +1,634 @@
-use std::{path::PathBuf, io::{stdout, Write, stdin}, fs, cmp::max, collections::BTreeMap};
+use std::{path::{PathBuf, Path}, io::{stdout, Write, stdin}, fs, cmp::max, collections::{BTreeMap, BTreeSet}};
 
 use colored::Colorize;
+use image::DynamicImage;
+use walkdir::WalkDir;
 
-use crate::{error::CleanerResult, art::{get_cover_art_from_file, get_cover_art_from_tag, write_image_to_buffer, write_image_to_file}, tagger::clean_tags, media_files::MediaFiles, audio_file::AudioFile, media_file::MediaFile, image_file::ImageFile};
+use crate::{error::CleanerResult, art::{get_cover_art_from_file, get_cover_art_from_tag, write_image_to_buffer, write_image_to_file}, tagger::{write_tagged_file, AlbumTags, TrackTags, TagReport}, media_files::MediaFiles, audio_file::AudioFile, audio_file_meta::AudioFileMeta, media_file::MediaFile, image_file::ImageFile, loudness::{ReplayGain, analyse_album, get_album_replay_gain}, options::Options, replay_gain_mode::ReplayGainMode, library::Library, duplicate_policy::DuplicatePolicy, editions::{SelectedEdition, select_editions, remove_replaced_editions}, spectral::{SpectralAnalysis, analyse_spectrum}, spectrogram::{render_spectrogram, render_album_sheet}, spectrogram_mode::SpectrogramMode, tempo_key::{TempoKey, analyse_tempo_key, get_tempo_key}, tempo_key_mode::TempoKeyMode, name_patterns::NameFields, metadata_field::MetadataField, explain::{AlbumFallback, ALBUM_SOURCE_NAME, print_explanation, write_explanation}, mpeg::VbrHeaderRepair, multi_value::join_values, stray_tags::StrayTag, preview::preview_album, tag_backup::{backup_tags, restore_tags, get_backup_path, get_backed_up_path, get_temporary_path, is_backup}};
 
 const UNKNOWN_ARTIST_NAME: &str = "[unknown]";
 const UNKNOWN_ALBUM_TITLE: &str = "[unknown]";
 
-pub fn clean_files(root_path: &PathBuf, output_path: &PathBuf, quality: u8) {
-    let files = MediaFiles::new(root_path.into());
+const SPECTROGRAM_DIRECTORY_NAME: &str = "spectrograms";
+const ALBUM_SHEET_NAME: &str = "album.png";
+
+/// Clean all files of a directory, or of an archive extracted to it, whose path is then recorded as their origin
+pub fn clean_files(root_path: &PathBuf, output_path: &Path, options: &Options, library: &mut Library, archive_path: Option<&PathBuf>, archive_fields: Option<&NameFields>) {
+    let files = MediaFiles::new(root_path.into(), options, archive_fields);
+    print_unreadable_files(&files);
 
     let audio_file_map = files.get_audio_file_map();
     let image_file_map = files.get_image_file_map();
 
     for (source_path, audio_files_in_path) in audio_file_map {
         let audio_files_by_artist = get_audio_files_by_artist(&audio_files_in_path);
-        clean_by_artist(&source_path, output_path, quality, &image_file_map, &audio_files_by_artist);
+        clean_by_artist(&source_path, output_path, options, library, archive_path, &image_file_map, &audio_files_by_artist);
     }
 }
 
-fn clean_by_artist(source_path: &PathBuf, output_path: &PathBuf, quality: u8, image_file_map: &BTreeMap<PathBuf, Vec<&ImageFile>>, audio_files_by_artist: &BTreeMap<&str, BTreeMap<&str, Vec<&AudioFile>>>) {
+fn clean_by_artist(source_path: &PathBuf, output_path: &Path, options: &Options, library: &mut Library, archive_path: Option<&PathBuf>, image_file_map: &BTreeMap<PathBuf, Vec<&ImageFile>>, audio_files_by_artist: &BTreeMap<&str, BTreeMap<&str, Vec<&AudioFile>>>) {
     for (artist_name, audio_files_by_album) in audio_files_by_artist {
-        print!(" Artist {} ", artist_name.bright_blue().bold());
+        println!(" Artist {}", artist_name.bright_blue().bold());
 
+        // The artist directory is created with the first album written to it, as albums may be skipped when previewing
         let artist_output_path = output_path.join(artist_name);
-        match fs::create_dir_all(&artist_output_path) {
-            Ok(_) => println!("{}", "OK".bright_green().bold()),
-            Err(err) => {
-                println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red());
-                continue;
-            }
-        }
-
-        clean_by_album(source_path, &artist_output_path, quality, image_file_map, audio_files_by_album);
+        clean_by_album(source_path, &artist_output_path, options, library, archive_path, image_file_map, audio_files_by_album);
     }
 }
 
-fn clean_by_album(source_path: &PathBuf, artist_output_path: &PathBuf, quality: u8, image_file_map: &BTreeMap<PathBuf, Vec<&ImageFile>>, audio_files_by_album: &BTreeMap<&str, Vec<&AudioFile>>) {
+fn clean_by_album(source_path: &PathBuf, artist_output_path: &Path, options: &Options, library: &mut Library, archive_path: Option<&PathBuf>, image_file_map: &BTreeMap<PathBuf, Vec<&ImageFile>>, audio_files_by_album: &BTreeMap<&str, Vec<&AudioFile>>) {
     for (album_title, audio_files_in_album) in audio_files_by_album {
-        print!("  Album {} ", album_title.bright_cyan().bold());
-
         let album_output_path = artist_output_path.join(album_title);
-        match fs::create_dir_all(&album_output_path) {
+        for edition in select_editions(&album_output_path, audio_files_in_album, options) {
+            clean_album(source_path, &edition, options, library, archive_path, image_file_map);
+        }
+    }
+}
+
+fn clean_album(source_path: &PathBuf, edition: &SelectedEdition, options: &Options, library: &mut Library, archive_path: Option<&PathBuf>, image_file_map: &BTreeMap<PathBuf, Vec<&ImageFile>>) {
+    let album_output_path = &edition.album_output_path;
+    print!("  Album {} ", get_album_name(album_output_path).bright_cyan().bold());
+    stdout().flush().expect("Failed to flush terminal output");
+
+    let sorted_audio_files = sort_tracks(&edition.audio_files);
+
+    let spectral_analyses = if options.check_lossless {
+        get_spectral_analyses(&sorted_audio_files)
+    } else {
+        Vec::new()
+    };
+
+    let suspect_count = spectral_analyses.iter().filter(|a| a.is_suspect()).count();
+    let is_suspect = suspect_count * 2 > spectral_analyses.len();
+
+    let review_output_path = get_review_output_path(album_output_path, is_suspect, options);
+    print_album_status(review_output_path.as_ref());
+
+    let several_discs = has_several_discs(&sorted_audio_files);
+    let track_width = get_max_track_num_length(&sorted_audio_files);
+    let title_width = get_max_disc_prefix_length(&sorted_audio_files, several_discs) + track_width + 1 + get_max_title_length(&sorted_audio_files) + 1 + get_max_extension_length(&sorted_audio_files);
+
+    if !spectral_analyses.is_empty() {
+        print!("  Check {:title_width$} ", "spectrum".bright_white().bold());
+        if suspect_count > 0 {
+            println!("{} {}", "SUSPECT".bright_red().bold(), format_spectral_summary(&spectral_analyses, suspect_count).red());
+        } else {
+            println!("{}", "OK".bright_green().bold());
+        }
+    }
+
+    let cover_art_image = get_album_cover_art(source_path, image_file_map, &sorted_audio_files);
+    let (mut album_tags, album_fallbacks) = get_album_tags(&sorted_audio_files, cover_art_image.as_ref(), options);
+
+    let replay_gains = get_replay_gains(&sorted_audio_files, options.replay_gain, title_width);
+    let tempo_keys = get_tempo_keys(&sorted_audio_files, options.tempo_key, title_width);
+    let track_tags = get_track_tags(&sorted_audio_files, replay_gains.as_ref(), &tempo_keys, archive_path, options);
+
+    // Nothing is written, not even the album directory, until the changes are confirmed
+    if !confirm_album(&sorted_audio_files, &track_tags, &mut album_tags, options) {
+        return;
+    }
+
+    // The directories are named from the album artist and album title edited when previewing, if any
+    let edited_output_path = get_edited_album_path(album_output_path, &sorted_audio_files, &album_tags);
+    let is_edited = &edited_output_path != album_output_path;
+    let review_output_path = if is_edited {
+        let review_output_path = get_review_output_path(&edited_output_path, is_suspect, options);
+        print!("  Album {} ", get_album_name(&edited_output_path).bright_cyan().bold());
+        print_album_status(review_output_path.as_ref());
+        review_output_path
+    } else {
+        review_output_path
+    };
+    let album_output_path = review_output_path.as_ref().unwrap_or(&edited_output_path);
+
+    if let Err(err) = fs::create_dir_all(album_output_path) {
+        println!("  Album {} {} {}", get_album_name(album_output_path).bright_cyan().bold(), "ERROR".bright_red().bold(), err.to_string().red());
+        return;
+    }
+
+    // A disc number edited when previewing puts every track on the same disc
+    let several_discs = several_discs && album_tags.get_override(MetadataField::Disc).is_none();
+
+    print!("  Cover {:title_width$} ", "cover.jpg".bright_white().bold());
+
+    let target_image_path = &album_output_path.join("cover.jpg");
+    if let Some(image) = &cover_art_image {
+        match write_image_to_file(image, target_image_path, options.quality) {
             Ok(_) => println!("{}", "OK".bright_green().bold()),
-            Err(err) => {
-                println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red());
-                continue;
-            }
+            Err(err) => println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red()),
         }
+    } else {
+        println!("{}", "MISSING".bright_red().bold());
+    }
 
-        let mut sorted_audio_files = audio_files_in_album.clone();
-        sorted_audio_files.sort_unstable_by_key(|k| k.get_meta().track_number());
+    let mut written_files = Vec::<(&AudioFile, PathBuf)>::new();
 
-        let mut default_year = sorted_audio_files.iter().find_map(|f| f.get_meta().year());
-        if default_year.is_none() {
-            default_year = get_year_input();
+    for (index, (audio_file, track_tags)) in sorted_audio_files.iter().zip(&track_tags).enumerate() {
+        let meta = audio_file.get_meta();
+
+        let track_file_name = get_track_file_name(meta, track_width, several_discs).expect("Must have a track number, title and file type");
+
+        let target_file_path = &album_output_path.join(track_file_name);
+
+        let target_file_name = target_file_path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .map(|s| s.to_string())
+            .expect("Missing file name");
+
+        if options.show_patterns && index == 0 {
+            let source_directory_name = audio_file.path().parent().and_then(|p| p.file_name()).map(|s| s.to_string_lossy()).unwrap_or_default();
+            println!("  Parse {:title_width$} {}", source_directory_name.dimmed(), audio_file.directory_pattern().unwrap_or("no pattern matched").dimmed());
+        }
+        if options.show_patterns {
+            let source_file_name = audio_file.path().file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+            println!("  Parse {:title_width$} {}", source_file_name.dimmed(), audio_file.filename_pattern().unwrap_or("no pattern matched").dimmed());
         }
 
-        let mut default_genre = sorted_audio_files.iter().find_map(|f| f.get_meta().genre()).map(|s| s.to_owned());
-        if default_genre.is_none() {
-            default_genre = get_genre_input();
+        explain_track(audio_file, target_file_path, &album_fallbacks, options);
+        print_track(&target_file_name, meta, title_width);
+
+        let (target_file_path, replaced_path) = match resolve_duplicate(audio_file, target_file_path, options.duplicates, library) {
+            Some(resolved) => resolved,
+            None => continue
+        };
+
+        match clean_audio_file(audio_file, &album_tags, track_tags, options, album_output_path, &target_file_path, replaced_path.as_ref()) {
+            Ok(tag_report) => {
+                println!("{} {}", "OK".bright_green().bold(), format_tag_report(&tag_report, track_tags.tempo_key));
+                if let Some(audio_hash) = audio_file.audio_hash().filter(|_| options.duplicates.is_some()) {
+                    library.add_track(audio_hash, &target_file_path);
+                }
+                if options.fingerprint {
+                    check_fingerprint(audio_file, &target_file_path, library);
+                }
+                written_files.push((audio_file, target_file_path));
+            },
+            Err(err) => println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red()),
         }
+    }
 
-        let track_width = get_max_track_num_length(&sorted_audio_files);
-        let title_width = track_width + 1 + get_max_title_length(&sorted_audio_files) + 1 + get_max_extension_length(&sorted_audio_files);
+    // Existing editions were compared in the album directory found before previewing, so are not replaced from another
+    if !edition.replaced.is_empty() && !is_edited {
+        remove_replaced_editions(edition, &written_files.iter().map(|(_, p)| p).collect::<Vec<&PathBuf>>());
+    }
 
-        print!("  Cover {:title_width$} ", "cover.jpg".bright_white().bold());
+    if let Some(spectrogram_mode) = options.spectrograms {
+        let spectrogram_path = options.spectrogram_path
+            .as_ref()
+            .and_then(|spectrogram_path| get_equivalent_album_path(spectrogram_path, album_output_path))
+            .unwrap_or_else(|| album_output_path.join(SPECTROGRAM_DIRECTORY_NAME));
+
+        print!("  Graph {:title_width$} ", "spectrograms".bright_white().bold());
+        stdout().flush().expect("Failed to flush terminal output");
 
-        // Prefer art from an image file in the samae directory, fallback to art embedded in any of the audio files
-        let cover_art_image = image_file_map
-            .get(source_path)
-            .and_then(|image_files| get_cover_art_from_file(&image_files, &sorted_audio_files))
-            .or_else(|| get_cover_art_from_tag(&sorted_audio_files));
+        match write_spectrograms(&written_files, spectrogram_mode, &spectrogram_path) {
+            Ok(_) => println!("{}", "OK".bright_green().bold()),
+            Err(err) => println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red()),
+        }
+    }
+    println!();
+}
 
-        let target_image_path = &album_output_path.join("cover.jpg");
-        if let Some(image) = &cover_art_image {
-            match write_image_to_file(image, target_image_path, quality) {
-                Ok(_) => println!("{}", "OK".bright_green().bold()),
-                Err(err) => println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red()),
+/// Fix the tags of all files where they are, without copying or renaming them, first backing up their original tags to
+/// the backup directory so that the fix can be reverted
+pub fn fix_files(root_path: &Path, backup_path: &Path, options: &Options) {
+    let files = MediaFiles::new(root_path.into(), options, None);
+    print_unreadable_files(&files);
+
+    let audio_file_map = files.get_audio_file_map();
+    let image_file_map = files.get_image_file_map();
+
+    for (source_path, audio_files_in_path) in audio_file_map {
+        for audio_files_by_album in get_audio_files_by_artist(&audio_files_in_path).values() {
+            for audio_files in audio_files_by_album.values() {
+                fix_album(root_path, &source_path, backup_path, options, &image_file_map, audio_files);
             }
-        } else {
-            println!("{}", "MISSING".bright_red().bold());
         }
+    }
+}
 
-        let cover_art_buffer = cover_art_image
-            .as_ref()
-            .and_then(|image| write_image_to_buffer(&image, quality).ok());
+fn fix_album(root_path: &Path, source_path: &PathBuf, backup_path: &Path, options: &Options, image_file_map: &BTreeMap<PathBuf, Vec<&ImageFile>>, audio_files_in_album: &Vec<&AudioFile>) {
+    let album_name = source_path.strip_prefix(root_path).unwrap_or(source_path).to_string_lossy();
+    println!("  Album {} {}", album_name.bright_cyan().bold(), "OK".bright_green().bold());
 
-        let total_tracks: u32 = sorted_audio_files.len().try_into().expect("Failed to get number of tracks");
+    let sorted_audio_files = sort_tracks(audio_files_in_album);
 
-        for audio_file in sorted_audio_files {
-            let meta = audio_file.get_meta();
+    let title_width = sorted_audio_files
+        .iter()
+        .filter_map(|f| f.path().file_name())
+        .map(|s| s.to_string_lossy().chars().count())
+        .max()
+        .unwrap_or(0);
 
-            let track_number = meta.track_number().unwrap();
-            let track_title = meta.track_title()
-                .map(|s| s.replace("/", "-"))
-                .unwrap();
+    // The cover is only embedded, the directory is left as it is
+    let cover_art_image = get_album_cover_art(source_path, image_file_map, &sorted_audio_files);
+    let (mut album_tags, album_fallbacks) = get_album_tags(&sorted_audio_files, cover_art_image.as_ref(), options);
 
-            let track_file_name = format!("{:0track_width$} {}.{}", track_number, track_title, meta.audio_file_type().expect("Must have a file type").to_extension());
+    let replay_gains = get_replay_gains(&sorted_audio_files, options.replay_gain, title_width);
+    let tempo_keys = get_tempo_keys(&sorted_audio_files, options.tempo_key, title_width);
+    let track_tags = get_track_tags(&sorted_audio_files, replay_gains.as_ref(), &tempo_keys, None, options);
 
-            let target_file_path = &album_output_path.join(track_file_name);
+    if !confirm_album(&sorted_audio_files, &track_tags, &mut album_tags, options) {
+        return;
+    }
 
-            let target_file_name = target_file_path
-                .file_name()
-                .and_then(|s| s.to_str())
-                .map(|s| s.to_string())
-                .expect("Missing file name");
+    for (audio_file, track_tags) in sorted_audio_files.iter().zip(&track_tags) {
+        let meta = audio_file.get_meta();
+        let file_name = audio_file.path().file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
 
-            print!("  Track {:title_width$} ", target_file_name.white().bold());
-            stdout().flush().expect("Failed to flush terminal output");
+        explain_track(audio_file, audio_file.path(), &album_fallbacks, options);
+        print_track(&file_name, meta, title_width);
 
-            match clean_audio_file(audio_file, &default_year, &default_genre.as_deref(), total_tracks, &cover_art_buffer, &album_output_path, target_file_path) {
-                Ok(_) => println!("{}", "OK".bright_green().bold()),
-                Err(err) => println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red()),
-            }
+        let file_backup_path = get_backup_path(root_path, audio_file.path(), backup_path);
+        match fix_audio_file(audio_file, &album_tags, track_tags, options, &file_backup_path) {
+            Ok((tag_report, is_backed_up)) => {
+                let backed_up = if is_backed_up { " backed up" } else { "" };
+                println!("{} {}{}", "OK".bright_green().bold(), format_tag_report(&tag_report, track_tags.tempo_key), backed_up.dimmed());
+            },
+            Err(err) => println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red()),
         }
+    }
+    println!();
+}
+
+/// Restore the original tags of all files with a backup in the backup directory
+pub fn revert_files(root_path: &Path, backup_path: &Path) {
+    let walker = WalkDir::new(backup_path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && is_backup(e.path()));
+
+    for entry in walker {
+        let file_backup_path = entry.path().to_path_buf();
+        let path = match get_backed_up_path(backup_path, &file_backup_path, root_path) {
+            Some(path) => path,
+            None => continue
+        };
+
+        print!("  Track {} ", path.strip_prefix(root_path).unwrap_or(&path).to_string_lossy().white().bold());
+        stdout().flush().expect("Failed to flush terminal output");
+
+        match restore_tags(&path, &file_backup_path) {
+            Ok(_) => println!("{}", "REVERTED".bright_green().bold()),
+            Err(err) => println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red()),
+        }
+    }
+    println!();
+}
+
+/// Report the audio files that could not be read, which are left as they are
+fn print_unreadable_files(files: &MediaFiles) {
+    let unreadable_file_map = files.get_unreadable_file_map();
+    for (source_path, unreadable_files) in &unreadable_file_map {
+        for unreadable_file in unreadable_files {
+            let file_name = unreadable_file.path().file_name().map(|s| source_path.join(s)).unwrap_or_default();
+            println!("  Track {} {} {}", file_name.to_string_lossy().white().bold(), "ERROR".bright_red().bold(), unreadable_file.error().to_string().red());
+        }
+    }
+
+    if !unreadable_file_map.is_empty() {
         println!();
     }
 }
 
+/// Cover art of an album, preferring an image file in the same directory, falling back to art embedded in any of the
+/// audio files
+fn get_album_cover_art(source_path: &PathBuf, image_file_map: &BTreeMap<PathBuf, Vec<&ImageFile>>, sorted_audio_files: &Vec<&AudioFile>) -> Option<DynamicImage> {
+    image_file_map
+        .get(source_path)
+        .and_then(|image_files| get_cover_art_from_file(image_files, sorted_audio_files))
+        .or_else(|| get_cover_art_from_tag(sorted_audio_files))
+}
+
+/// Values shared by the tracks of an album, with the year and genre for tracks without their own from the other tracks
+/// of the album or prompted for, and the same values with their sources, for explanations
+fn get_album_tags(sorted_audio_files: &Vec<&AudioFile>, cover_art_image: Option<&DynamicImage>, options: &Options) -> (AlbumTags, Vec<AlbumFallback>) {
+    let mut default_year = sorted_audio_files.iter().find_map(|f| f.get_meta().year());
+    let mut default_year_source = ALBUM_SOURCE_NAME;
+    if default_year.is_none() && options.precedence.allows_prompt(MetadataField::Year) {
+        default_year = get_year_input();
+        default_year_source = "prompt";
+    }
+
+    let mut default_genres = sorted_audio_files.iter().map(|f| f.get_meta().genres()).find(|g| !g.is_empty()).map(|g| g.to_vec()).unwrap_or_default();
+    let mut default_genre_source = ALBUM_SOURCE_NAME;
+    if default_genres.is_empty() && options.precedence.allows_prompt(MetadataField::Genre) {
+        default_genres = options.delimiters.split(MetadataField::Genre, get_genre_input().into_iter().collect());
+        default_genre_source = "prompt";
+    }
+
+    let album_fallbacks = [
+        default_year.map(|year| AlbumFallback { field: MetadataField::Year, value: year.to_string(), source: default_year_source.to_string() }),
+        join_values(&default_genres).map(|genre| AlbumFallback { field: MetadataField::Genre, value: genre, source: default_genre_source.to_string() }),
+    ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<AlbumFallback>>();
+
+    let album_tags = AlbumTags {
+        default_year,
+        default_genres,
+        total_tracks: sorted_audio_files.len().try_into().expect("Failed to get number of tracks"),
+        cover_image: cover_art_image.and_then(|image| write_image_to_buffer(image, options.quality).ok()),
+        overrides: Vec::new()
+    };
+
+    (album_tags, album_fallbacks)
+}
+
+/// Values written to the tags of each track besides its metadata, in the same order, found before previewing so that the
+/// preview shows them
+fn get_track_tags<'a>(sorted_audio_files: &Vec<&'a AudioFile>, replay_gains: Option<&'a Vec<ReplayGain>>, tempo_keys: &'a [Option<TempoKey>], archive_path: Option<&'a PathBuf>, options: &Options) -> Vec<TrackTags<'a>> {
+    sorted_audio_files
+        .iter()
+        .zip(tempo_keys)
+        .enumerate()
+        .map(|(index, (audio_file, tempo_key))| TrackTags {
+            replay_gain: replay_gains.and_then(|g| g.get(index)),
+            tempo_key: tempo_key.as_ref(),
+            // Files extracted from an archive come from the archive, not from the temporary directory they are in
+            origin_source: archive_path.map(|p| p.as_path()).or_else(|| audio_file.path().parent()).filter(|_| options.origin_tags)
+        })
+        .collect()
+}
+
+/// Show the changes to the tags of an album and ask whether to write them, when previewing, returning false if the album
+/// is skipped
+fn confirm_album(sorted_audio_files: &Vec<&AudioFile>, track_tags: &[TrackTags], album_tags: &mut AlbumTags, options: &Options) -> bool {
+    if !options.preview || preview_album(sorted_audio_files, track_tags, album_tags, options) {
+        return true;
+    }
+
+    println!("  {}\n", "SKIPPED".bright_yellow().bold());
+    false
+}
+
+/// Show, or write to the explanation file, where each metadata value of a track came from
+fn explain_track(audio_file: &AudioFile, target_file_path: &Path, album_fallbacks: &[AlbumFallback], options: &Options) {
+    let meta = audio_file.get_meta();
+    if options.explain {
+        print_explanation(meta, album_fallbacks);
+    }
+    if let Some(explain_path) = &options.explain_path {
+        if let Err(err) = write_explanation(explain_path, audio_file.path(), target_file_path, meta, album_fallbacks) {
+            println!("  {} {}", "ERROR".bright_red().bold(), err.to_string().red());
+        }
+    }
+}
+
+/// Start the status line of a track, finished once its tags have been written
+fn print_track(file_name: &str, meta: &AudioFileMeta, title_width: usize) {
+    print!("  Track {:title_width$} {} ", file_name.white().bold(), format_audio_properties(meta).dimmed());
+    stdout().flush().expect("Failed to flush terminal output");
+}
+
+/// Write a spectrogram image for each track, named after the track, or a single album sheet
+fn write_spectrograms(written_files: &Vec<(&AudioFile, PathBuf)>, spectrogram_mode: SpectrogramMode, spectrogram_path: &PathBuf) -> CleanerResult<()> {
+    fs::create_dir_all(spectrogram_path)?;
+
+    let mut images = Vec::new();
+    for (audio_file, target_file_path) in written_files {
+        let image = render_spectrogram(audio_file.path(), audio_file.get_meta().duration())?;
+        match spectrogram_mode {
+            SpectrogramMode::Tracks => {
+                let file_name = target_file_path.with_extension("png");
+                image.save(spectrogram_path.join(file_name.file_name().expect("Missing file name")))?;
+            },
+            SpectrogramMode::Album => images.push(image)
+        }
+    }
+
+    if spectrogram_mode == SpectrogramMode::Album && !images.is_empty() {
+        render_album_sheet(&images)?.save(spectrogram_path.join(ALBUM_SHEET_NAME))?;
+    }
+
+    Ok(())
+}
+
+/// Analyse the spectrum of each lossless track, lossy tracks are not analysed
+fn get_spectral_analyses(audio_files: &Vec<&AudioFile>) -> Vec<SpectralAnalysis> {
+    audio_files
+        .iter()
+        .filter(|f| f.get_meta().audio_file_type().is_some_and(|t| t.is_lossless()))
+        .filter_map(|f| analyse_spectrum(f.path()).ok())
+        .collect()
+}
+
+fn format_spectral_summary(spectral_analyses: &[SpectralAnalysis], suspect_count: usize) -> String {
+    let mut cutoffs = spectral_analyses
+        .iter()
+        .filter(|a| a.is_suspect())
+        .map(|a| a.cutoff())
+        .collect::<Vec<f32>>();
+    cutoffs.sort_by(|a, b| a.total_cmp(b));
+
+    let median_cutoff = cutoffs.get(cutoffs.len() / 2).copied().unwrap_or_default();
+
+    format!("{}/{} tracks likely lossy or upsampled, cutoff {:.1}kHz", suspect_count, spectral_analyses.len(), median_cutoff / 1000.0)
+}
+
+/// The directory in the review directory, if there is one, that a suspect album is written to instead
+fn get_review_output_path(album_output_path: &Path, is_suspect: bool, options: &Options) -> Option<PathBuf> {
+    options.review_path
+        .as_ref()
+        .filter(|_| is_suspect)
+        .and_then(|review_path| get_equivalent_album_path(review_path, album_output_path))
+}
+
+/// Finish the status line of an album, with the review directory it is written to instead, if any
+fn print_album_status(review_output_path: Option<&PathBuf>) {
+    match review_output_path {
+        Some(review_output_path) => println!("{} {}", "REVIEW".bright_yellow().bold(), review_output_path.to_string_lossy().yellow()),
+        None => println!("{}", "OK".bright_green().bold()),
+    }
+}
+
+/// The album directory with the album artist and album title edited when previewing, keeping the suffix of a separate
+/// edition, e.g. " [FLAC 24-96]"
+fn get_edited_album_path(album_output_path: &Path, sorted_audio_files: &Vec<&AudioFile>, album_tags: &AlbumTags) -> PathBuf {
+    let mut album_name = get_album_name(album_output_path);
+    if let Some(album_title) = album_tags.get_override(MetadataField::Album) {
+        let old_album_title = sorted_audio_files.first().and_then(|f| f.get_meta().album_title()).unwrap_or(UNKNOWN_ALBUM_TITLE);
+        let edition_suffix = album_name.strip_prefix(old_album_title).unwrap_or_default();
+        album_name = format!("{}{}", album_title, edition_suffix);
+    }
+
+    let artist_output_path = album_output_path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    match album_tags.get_override(MetadataField::AlbumArtist) {
+        Some(artist_name) => artist_output_path.with_file_name(artist_name).join(album_name),
+        None => artist_output_path.join(album_name)
+    }
+}
+
+fn get_album_name(album_output_path: &Path) -> String {
+    album_output_path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// The equivalent artist and album directory under a different base directory
+fn get_equivalent_album_path(base_path: &Path, album_output_path: &Path) -> Option<PathBuf> {
+    let album_name = album_output_path.file_name()?;
+    let artist_name = album_output_path.parent().and_then(|p| p.file_name())?;
+    Some(base_path.join(artist_name).join(album_name))
+}
+
+/// Tempo and key of each track, in the same order, none if they are not written
+fn get_tempo_keys(audio_files: &Vec<&AudioFile>, tempo_key_mode: TempoKeyMode, title_width: usize) -> Vec<Option<TempoKey>> {
+    if tempo_key_mode == TempoKeyMode::Off {
+        return audio_files.iter().map(|_| None).collect();
+    }
+
+    print!("  Tempo {:title_width$} ", "tempo and key".bright_white().bold());
+    stdout().flush().expect("Failed to flush terminal output");
+
+    let tempo_keys = audio_files
+        .iter()
+        .map(|audio_file| get_track_tempo_key(audio_file, tempo_key_mode))
+        .collect::<Vec<Option<TempoKey>>>();
+    println!("{}", "OK".bright_green().bold());
+
+    tempo_keys
+}
+
+fn get_track_tempo_key(audio_file: &AudioFile, tempo_key_mode: TempoKeyMode) -> Option<TempoKey> {
+    let existing = match tempo_key_mode {
+        TempoKeyMode::Off => return None,
+        TempoKeyMode::Analyse => TempoKey { bpm: None, key: None },
+        TempoKeyMode::Keep => get_tempo_key(audio_file)
+    };
+
+    if existing.is_complete() {
+        return Some(existing);
+    }
+
+    match analyse_tempo_key(audio_file.path()) {
+        Ok(analysed) => Some(existing.or(analysed)),
+        Err(_) => Some(existing)
+    }
+}
+
+fn format_tempo_key(tempo_key: &TempoKey) -> String {
+    let bpm = tempo_key.bpm.map(|b| format!("{} BPM", b));
+    [bpm.as_deref(), tempo_key.key.as_deref()]
+        .iter()
+        .flatten()
+        .copied()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+fn get_replay_gains(audio_files: &Vec<&AudioFile>, replay_gain_mode: ReplayGainMode, title_width: usize) -> Option<Vec<ReplayGain>> {
+    if replay_gain_mode == ReplayGainMode::Off {
+        return None;
+    }
+
+    print!("   Gain {:title_width$} ", "replaygain".bright_white().bold());
+    stdout().flush().expect("Failed to flush terminal output");
+
+    if replay_gain_mode == ReplayGainMode::Keep {
+        if let Some(replay_gains) = get_album_replay_gain(audio_files) {
+            println!("{} {}", "KEPT".bright_green().bold(), format_album_gain(&replay_gains));
+            return Some(replay_gains);
+        }
+    }
+
+    match analyse_album(audio_files) {
+        Ok(replay_gains) => {
+            println!("{} {}", "OK".bright_green().bold(), format_album_gain(&replay_gains));
+            Some(replay_gains)
+        },
+        Err(err) => {
+            println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red());
+            None
+        }
+    }
+}
+
+fn format_album_gain(replay_gains: &[ReplayGain]) -> String {
+    replay_gains
+        .first()
+        .map(|g| format!("{} peak {}", g.album_gain_text(), g.album_peak_text()))
+        .unwrap_or_default()
+        .dimmed()
+        .to_string()
+}
+
+/// Check whether the audio of a track is already in the library, returning the path to write the track to along with
+/// any existing track it replaces, or None if the track should be skipped
+fn resolve_duplicate(audio_file: &AudioFile, target_file_path: &PathBuf, duplicate_policy: Option<DuplicatePolicy>, library: &mut Library) -> Option<(PathBuf, Option<PathBuf>)> {
+    let (duplicate_policy, audio_hash) = match (duplicate_policy, audio_file.audio_hash()) {
+        (Some(duplicate_policy), Some(audio_hash)) => (duplicate_policy, audio_hash),
+        _ => return Some((target_file_path.to_owned(), None))
+    };
+
+    let existing_path = match library.find_duplicate(audio_hash) {
+        Some(existing_path) => existing_path.to_owned(),
+        None => return Some((target_file_path.to_owned(), None))
+    };
+
+    match duplicate_policy {
+        DuplicatePolicy::Skip => {
+            println!("{} {}", "DUPLICATE".bright_yellow().bold(), existing_path.to_string_lossy().yellow());
+            None
+        },
+        DuplicatePolicy::Suffix => Some((get_suffixed_path(target_file_path), None)),
+        // The existing track is only removed once the new one has been written
+        DuplicatePolicy::Replace => Some((target_file_path.to_owned(), Some(existing_path)))
+    }
+}
+
+/// Report any track in the library with the same fingerprint, flagging it if the titles differ, then add the track to
+/// the library
+fn check_fingerprint(audio_file: &AudioFile, target_file_path: &PathBuf, library: &mut Library) {
+    let fingerprint = match audio_file.fingerprint() {
+        Some(fingerprint) => fingerprint,
+        None => return
+    };
+
+    let track_title = audio_file.get_meta().track_title();
+
+    if let Some(similar) = library.find_similar(fingerprint, target_file_path) {
+        let similar_path = similar.path.to_string_lossy();
+        if is_same_title(track_title, similar.title.as_deref()) {
+            println!("        {} {}", "Same recording as".yellow(), similar_path.yellow());
+        } else {
+            println!("        {} '{}' {}", "Possibly mislabelled, matches".bright_red().bold(), similar.title.as_deref().unwrap_or_default().red(), similar_path.red());
+        }
+    }
+
+    library.add_fingerprint(target_file_path, track_title, fingerprint.to_owned());
+}
+
+fn is_same_title(a: Option<&str>, b: Option<&str>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.trim().to_lowercase() == b.trim().to_lowercase(),
+        _ => false
+    }
+}
+
+/// Get the first unused path of the form "01 Title (2).mp3"
+fn get_suffixed_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or_default();
+    let extension = path.extension().and_then(|s| s.to_str()).unwrap_or_default();
+
+    (2..)
+        .map(|n| path.with_file_name(format!("{} ({}).{}", stem, n, extension)))
+        .find(|p| !p.exists())
+        .expect("Must find an unused file name")
+}
+
 fn get_year_input() -> Option<u32> {
     loop {
         print!("   {}>", "Year".bright_red().bold());
         stdout().flush().expect("Failed to flush terminal output");
         if let Some(Ok(line)) = stdin().lines().next() {
             let trimmed = line.trim();
-            if trimmed == "" {
+            if trimmed.is_empty() {
                 return None;
             }
             match trimmed.parse::<u32>() {
@@ -142,7 +645,7 @@ fn get_genre_input() -> Option<String> {
         stdout().flush().expect("Failed to flush terminal output");
         if let Some(Ok(line)) = stdin().lines().next() {
             let trimmed = line.trim();
-            if trimmed == "" {
+            if trimmed.is_empty() {
                 return None;
             }
             return Some(trimmed.to_string());
@@ -150,13 +653,93 @@ fn get_genre_input() -> Option<String> {
     }
 }
 
-fn clean_audio_file(audio_file: &AudioFile, default_year: &Option<u32>, default_genre: &Option<&str>, total_tracks: u32, cover_art_buffer: &Option<Vec<u8>>, target_directory_path: &PathBuf, target_file_path: &PathBuf) -> CleanerResult<()> {
+/// Write the cleaned copy of a track, replacing a duplicate of it in the library only once the copy has been written
+fn clean_audio_file(audio_file: &AudioFile, album_tags: &AlbumTags, track_tags: &TrackTags, options: &Options, target_directory_path: &PathBuf, target_file_path: &PathBuf, replaced_path: Option<&PathBuf>) -> CleanerResult<TagReport> {
     fs::create_dir_all(target_directory_path)?;
-    fs::copy(audio_file.path(), &target_file_path)?;
 
-    clean_tags(&target_file_path, audio_file.get_meta(), default_year, default_genre, total_tracks, &cover_art_buffer)?;
+    let replaced_path = match replaced_path {
+        Some(replaced_path) => replaced_path,
+        None => return write_tagged_file(audio_file.path(), target_file_path, audio_file.get_meta(), album_tags, track_tags, options)
+    };
+
+    // Written next to the target and renamed over it, so that the duplicate is kept if writing fails
+    let temporary_path = get_temporary_path(target_file_path);
+    let tag_report = match write_tagged_file(audio_file.path(), &temporary_path, audio_file.get_meta(), album_tags, track_tags, options) {
+        Ok(tag_report) => tag_report,
+        Err(err) => {
+            let _ = fs::remove_file(&temporary_path);
+            return Err(err);
+        }
+    };
+    fs::rename(&temporary_path, target_file_path)?;
+    if replaced_path != target_file_path {
+        fs::remove_file(replaced_path)?;
+    }
+
+    Ok(tag_report)
+}
 
-    Ok(())
+/// Rewrite the tags of a file where it is, after backing up its original tags, returning whether a new backup was written
+fn fix_audio_file(audio_file: &AudioFile, album_tags: &AlbumTags, track_tags: &TrackTags, options: &Options, backup_path: &Path) -> CleanerResult<(TagReport, bool)> {
+    let is_backed_up = backup_tags(audio_file.path(), backup_path)?;
+
+    // Written next to the original and renamed over it, so the original is never left half written
+    let temporary_path = get_temporary_path(audio_file.path());
+    let tag_report = write_tagged_file(audio_file.path(), &temporary_path, audio_file.get_meta(), album_tags, track_tags, options);
+    match tag_report {
+        Ok(tag_report) => {
+            fs::rename(&temporary_path, audio_file.path())?;
+            Ok((tag_report, is_backed_up))
+        },
+        Err(err) => {
+            let _ = fs::remove_file(&temporary_path);
+            Err(err)
+        }
+    }
+}
+
+/// What was found or changed while writing the tags of a track, besides the cleaned metadata
+fn format_tag_report(tag_report: &TagReport, tempo_key: Option<&TempoKey>) -> String {
+    format!("{}{}{}{}",
+        tempo_key.map(format_tempo_key).unwrap_or_default().dimmed(),
+        format_vbr_header_repair(&tag_report.vbr_header_repair).dimmed(),
+        format_stray_tags(&tag_report.stray_tags).dimmed(),
+        format_truncated_fields(&tag_report.truncated_fields).yellow()
+    )
+}
+
+fn format_vbr_header_repair(repair: &VbrHeaderRepair) -> &'static str {
+    match repair {
+        VbrHeaderRepair::Unchanged => "",
+        VbrHeaderRepair::Updated => " VBR header rebuilt",
+        VbrHeaderRepair::Added => " VBR header added",
+    }
+}
+
+fn format_stray_tags(stray_tags: &[StrayTag]) -> String {
+    if stray_tags.is_empty() {
+        return String::new();
+    }
+
+    format!(" stripped {}", stray_tags.iter().map(ToString::to_string).collect::<Vec<String>>().join(", "))
+}
+
+fn format_truncated_fields(truncated_fields: &[MetadataField]) -> String {
+    if truncated_fields.is_empty() {
+        return String::new();
+    }
+
+    format!(" ID3v1 {} truncated", truncated_fields.iter().map(ToString::to_string).collect::<Vec<String>>().join(", "))
+}
+
+fn format_audio_properties(meta: &AudioFileMeta) -> String {
+    let resolution = meta.resolution().unwrap_or_default();
+    let bitrate = meta.bitrate().map(|b| format!("{}kbps", b)).unwrap_or_default();
+    let channels = meta.channels().map(|c| format!("{}ch", c)).unwrap_or_default();
+    let seconds = meta.duration().as_secs();
+    let duration = format!("{}:{:02}", seconds / 60, seconds % 60);
+
+    format!("{:>8} {:>8} {:>3} {:>6}", resolution, bitrate, channels, duration)
 }
 
 fn get_audio_files_by_artist<'a>(audio_files: &'a Vec<&AudioFile>) -> BTreeMap<&'a str, BTreeMap<&'a str, Vec<&'a AudioFile>>> {
@@ -171,7 +754,7 @@ fn get_audio_files_by_artist<'a>(audio_files: &'a Vec<&AudioFile>) -> BTreeMap<&
                     .unwrap_or(UNKNOWN_ARTIST_NAME);
 
                 let album_files = acc.entry(artist_name)
-                    .or_insert_with(|| BTreeMap::<&str, Vec<&AudioFile>>::new());
+                    .or_insert_with(BTreeMap::<&str, Vec<&AudioFile>>::new);
 
                 let album_title = audio_file
                     .get_meta()
@@ -179,7 +762,7 @@ fn get_audio_files_by_artist<'a>(audio_files: &'a Vec<&AudioFile>) -> BTreeMap<&
                     .unwrap_or(UNKNOWN_ALBUM_TITLE);
 
                 album_files.entry(album_title)
-                    .or_insert_with(|| Vec::new())
+                    .or_insert_with(Vec::new)
                     .push(audio_file);
 
                 acc
@@ -187,7 +770,53 @@ fn get_audio_files_by_artist<'a>(audio_files: &'a Vec<&AudioFile>) -> BTreeMap<&
         )
 }
 
-fn get_max_track_num_length(audio_files: &Vec<&AudioFile>) -> usize {
+/// The artist and album directories an album is written to, relative to the output directory
+pub fn get_album_directory(meta: &AudioFileMeta) -> PathBuf {
+    let artist_name = meta.album_artist_name().unwrap_or(UNKNOWN_ARTIST_NAME);
+    let album_title = meta.album_title().unwrap_or(UNKNOWN_ALBUM_TITLE);
+    PathBuf::from(artist_name).join(album_title)
+}
+
+/// Tracks in the order they are on the album, by disc, then by track number
+pub fn sort_tracks<'a>(audio_files: &[&'a AudioFile]) -> Vec<&'a AudioFile> {
+    let mut sorted_audio_files = audio_files.to_vec();
+    sorted_audio_files.sort_by_key(|f| (f.get_meta().disc_number().unwrap_or(1), f.get_meta().track_number()));
+    sorted_audio_files
+}
+
+/// Whether the tracks of an album are on more than one disc, so that their names need the disc number to be told apart
+pub fn has_several_discs(audio_files: &Vec<&AudioFile>) -> bool {
+    audio_files
+        .iter()
+        .map(|f| f.get_meta().disc_number().unwrap_or(1))
+        .collect::<BTreeSet<u32>>()
+        .len() > 1
+}
+
+/// The name a track is written with, e.g. "01 Title.mp3", or "1-01 Title.mp3" for an album of several discs, with the
+/// track number padded to the given width
+pub fn get_track_file_name(meta: &AudioFileMeta, track_width: usize, several_discs: bool) -> Option<String> {
+    let disc_prefix = if several_discs { format!("{}-", meta.disc_number().unwrap_or(1)) } else { String::new() };
+    let track_number = meta.track_number()?;
+    let track_title = meta.track_title()?.replace("/", "-");
+    let extension = meta.audio_file_type()?.to_extension();
+
+    Some(format!("{}{:0track_width$} {}.{}", disc_prefix, track_number, track_title, extension))
+}
+
+fn get_max_disc_prefix_length(audio_files: &Vec<&AudioFile>, several_discs: bool) -> usize {
+    if !several_discs {
+        return 0;
+    }
+
+    audio_files
+        .iter()
+        .map(|f| f.get_meta().disc_number().unwrap_or(1).to_string().chars().count() + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+pub fn get_max_track_num_length(audio_files: &[&AudioFile]) -> usize {
     max(2, audio_files.len().to_string().chars().count())
 }
 
@@ -208,3 +837,65 @@ fn get_max_extension_length(audio_files: &Vec<&AudioFile>) -> usize {
         .max()
         .unwrap_or(0)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::Builder;
+
+    use super::*;
+
+    // MPEG-1 layer III, 128kbps, 44.1kHz, joint stereo
+    const FRAME_HEADER: [u8; 4] = [0xff, 0xfb, 0x90, 0x40];
+    const FRAME_LENGTH: usize = 417;
+
+    /// Untagged MP3 files of silent frames, so that their metadata comes from their names
+    fn write_temp_mp3s(file_names: &[&str]) -> tempfile::TempDir {
+        let dir = Builder::new().prefix("cleaner").tempdir().unwrap();
+
+        let mut frame = vec![0u8; FRAME_LENGTH];
+        frame[..4].copy_from_slice(&FRAME_HEADER);
+        let data = frame.repeat(20);
+
+        for file_name in file_names {
+            fs::write(dir.path().join(file_name), &data).unwrap();
+        }
+        dir
+    }
+
+    fn get_track_file_names(dir: &tempfile::TempDir) -> Vec<String> {
+        let files = MediaFiles::new(dir.path().to_path_buf(), &Options::default(), None);
+        let audio_files = files.get_audio_file_map().into_values().flatten().collect::<Vec<&AudioFile>>();
+
+        let sorted_audio_files = sort_tracks(&audio_files);
+        let several_discs = has_several_discs(&sorted_audio_files);
+        let track_width = get_max_track_num_length(&sorted_audio_files);
+
+        sorted_audio_files
+            .iter()
+            .map(|f| get_track_file_name(f.get_meta(), track_width, several_discs).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn tracks_of_several_discs_are_sorted_and_named_by_disc() {
+        let dir = write_temp_mp3s(&["2-01 Reprise.mp3", "1-02 Second.mp3", "2-02 Coda.mp3", "1-01 First.mp3"]);
+
+        assert_eq!(get_track_file_names(&dir), ["1-01 First.mp3", "1-02 Second.mp3", "2-01 Reprise.mp3", "2-02 Coda.mp3"]);
+    }
+
+    #[test]
+    fn tracks_of_one_disc_are_named_without_disc() {
+        let dir = write_temp_mp3s(&["1-02 Second.mp3", "1-01 First.mp3"]);
+
+        assert_eq!(get_track_file_names(&dir), ["01 First.mp3", "02 Second.mp3"]);
+    }
+
+    #[test]
+    fn tracks_of_vinyl_sides_are_numbered_in_order_on_one_disc() {
+        let dir = write_temp_mp3s(&["B1 Third.mp3", "A2 Second.mp3", "A1 First.mp3", "B2 Fourth.mp3"]);
+
+        assert_eq!(get_track_file_names(&dir), ["01 First.mp3", "02 Second.mp3", "03 Third.mp3", "04 Fourth.mp3"]);
+    }
+}
@@ -0,0 +1,54 @@
+use std::{path::PathBuf, fs, collections::BTreeMap};
+
+use serde::Deserialize;
+
+use crate::{error::CleanerResult, metadata_field::MetadataField, metadata_source::MetadataSource, precedence::deserialize_orders, tag_fields::FieldKeys};
+
+const CONFIG_DIRECTORY_NAME: &str = "cleaner";
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Settings read from a TOML configuration file, any of which may be overridden per run on the command line
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct Config {
+    /// Regular expressions for parsing file names, tried in order
+    pub filename_patterns: Option<Vec<String>>,
+    /// Regular expressions for parsing album directory names, tried in order
+    pub directory_patterns: Option<Vec<String>>,
+    /// Regular expressions for quality and source markers to remove from directory names before parsing them
+    pub marker_patterns: Option<Vec<String>>,
+    /// Regular expressions for placeholder tag values, e.g. "Unknown Artist", that are treated as missing
+    pub placeholder_patterns: Option<Vec<String>>,
+    /// Delimiters that separate several artists in one value, e.g. "feat."
+    pub artist_delimiters: Option<Vec<String>>,
+    /// Delimiters that separate several genres in one value, e.g. "/"
+    pub genre_delimiters: Option<Vec<String>>,
+    /// Tag items carried over from the source file, by name or by key, or * for all
+    pub keep_tag_items: Option<Vec<String>>,
+    /// Tag items not carried over from the source file, even if kept
+    pub drop_tag_items: Option<Vec<String>>,
+    /// Order in which sources are tried, by field, for fields that should not use the built in order
+    #[serde(deserialize_with = "deserialize_orders")]
+    pub precedence: BTreeMap<MetadataField, Vec<MetadataSource>>,
+    /// Values, by field, for the default source
+    pub defaults: BTreeMap<MetadataField, String>,
+    /// Keys of each tag format, by field name, for custom fields and fields that should not use the built in keys
+    pub tag_fields: BTreeMap<String, FieldKeys>,
+}
+
+impl Config {
+
+    /// Load the configuration from the given file, or from the user's configuration directory if there is one there
+    pub fn load(path: Option<&PathBuf>) -> CleanerResult<Config> {
+        let path = match path {
+            Some(path) => path.to_owned(),
+            None => match dirs::config_dir().map(|d| d.join(CONFIG_DIRECTORY_NAME).join(CONFIG_FILE_NAME)).filter(|p| p.is_file()) {
+                Some(path) => path,
+                None => return Ok(Config::default())
+            }
+        };
+
+        let text = fs::read_to_string(path)?;
+        Ok(toml::from_str(&text)?)
+    }
+}
@@ -0,0 +1,94 @@
+use std::{path::Path, fs, collections::BTreeMap};
+
+use walkdir::WalkDir;
+
+/// Album and track details from a CUE sheet, the only kind of sidecar file read
+#[derive(Default)]
+pub struct CueSheet {
+    pub performer: Option<String>,
+    pub title: Option<String>,
+    pub year: Option<u32>,
+    pub genre: Option<String>,
+    pub catalogue: Option<String>,
+    tracks: BTreeMap<u32, CueTrack>,
+}
+
+#[derive(Default)]
+struct CueTrack {
+    performer: Option<String>,
+    title: Option<String>,
+}
+
+impl CueSheet {
+
+    /// Performer of a track, or of the album where the track has none
+    pub fn track_performer(&self, track_number: u32) -> Option<&str> {
+        self.tracks
+            .get(&track_number)
+            .and_then(|t| t.performer.as_deref())
+            .or(self.performer.as_deref())
+    }
+
+    pub fn track_title(&self, track_number: u32) -> Option<&str> {
+        self.tracks
+            .get(&track_number)
+            .and_then(|t| t.title.as_deref())
+    }
+}
+
+/// Read the first CUE sheet, by name, in a directory
+pub fn read_cue_sheet(directory: &Path) -> Option<CueSheet> {
+    let cue_path = WalkDir::new(directory)
+        .min_depth(1)
+        .max_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .find(|p| p.extension().and_then(|e| e.to_str()).is_some_and(|e| e.eq_ignore_ascii_case("cue")))?;
+
+    let bytes = fs::read(cue_path).ok()?;
+    Some(parse_cue_sheet(&String::from_utf8_lossy(&bytes)))
+}
+
+fn parse_cue_sheet(text: &str) -> CueSheet {
+    let mut cue_sheet = CueSheet::default();
+    let mut track_number = None;
+
+    for line in text.lines() {
+        let line = line.trim().trim_start_matches('\u{feff}');
+        let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let value = unquote(rest);
+
+        match (command.to_ascii_uppercase().as_str(), track_number) {
+            ("TRACK", _) => {
+                track_number = rest.split_whitespace().next().and_then(|n| n.parse::<u32>().ok());
+                if let Some(track_number) = track_number {
+                    cue_sheet.tracks.entry(track_number).or_default();
+                }
+            },
+            ("PERFORMER", None) => cue_sheet.performer = value,
+            ("TITLE", None) => cue_sheet.title = value,
+            ("CATALOG", None) => cue_sheet.catalogue = value,
+            ("PERFORMER", Some(n)) => cue_sheet.tracks.entry(n).or_default().performer = value,
+            ("TITLE", Some(n)) => cue_sheet.tracks.entry(n).or_default().title = value,
+            ("REM", None) => {
+                let (key, rest) = rest.trim().split_once(char::is_whitespace).unwrap_or((rest.trim(), ""));
+                match key.to_ascii_uppercase().as_str() {
+                    "DATE" => cue_sheet.year = rest.trim().get(..4).and_then(|y| y.parse::<u32>().ok()),
+                    "GENRE" => cue_sheet.genre = unquote(rest),
+                    _ => {}
+                }
+            },
+            _ => {}
+        }
+    }
+
+    cue_sheet
+}
+
+fn unquote(s: &str) -> Option<String> {
+    let s = s.trim();
+    let s = s.strip_prefix('"').and_then(|s| s.strip_suffix('"')).unwrap_or(s).trim();
+    Some(s.to_string()).filter(|s| !s.is_empty())
+}
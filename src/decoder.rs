@@ -0,0 +1,86 @@
+use std::{path::PathBuf, fs::File, io::ErrorKind};
+
+use sha2::{Sha256, Digest};
+use symphonia::core::{audio::SampleBuffer, codecs::{DecoderOptions, CodecParameters}, errors::Error, formats::{FormatOptions, FormatReader, Packet}, io::MediaSourceStream, meta::MetadataOptions, probe::Hint};
+
+use crate::error::{CleanerResult, CleanerError};
+
+/// Decode the audio stream of a file, passing each block of interleaved samples to the given function along with the
+/// sample rate and number of channels
+pub fn decode_audio<F>(path: &PathBuf, mut f: F) -> CleanerResult<()>
+where
+    F: FnMut(u32, usize, &[f32])
+{
+    let (mut format, track_id, codec_params) = open_audio(path)?;
+
+    let mut decoder = symphonia::default::get_codecs().make(&codec_params, &DecoderOptions::default())?;
+    let mut sample_buffer = None::<SampleBuffer<f32>>;
+
+    while let Some(packet) = next_packet(&mut format, track_id)? {
+        match decoder.decode(&packet) {
+            Ok(decoded) => {
+                let spec = *decoded.spec();
+                let required = decoded.capacity() * spec.channels.count();
+                if sample_buffer.as_ref().is_none_or(|b| b.capacity() < required) {
+                    sample_buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+                }
+                if let Some(buffer) = sample_buffer.as_mut() {
+                    buffer.copy_interleaved_ref(decoded);
+                    f(spec.rate, spec.channels.count(), buffer.samples());
+                }
+            },
+            // Corrupt frames are skipped, as a player would
+            Err(Error::DecodeError(_)) => continue,
+            Err(err) => return Err(err.into())
+        }
+    }
+
+    Ok(())
+}
+
+/// Hash the encoded audio stream of a file, excluding any tags or other metadata, so that the same audio with different
+/// tags gives the same hash
+pub fn hash_audio_stream(path: &PathBuf) -> CleanerResult<String> {
+    let (mut format, track_id, _) = open_audio(path)?;
+
+    let mut hasher = Sha256::new();
+    while let Some(packet) = next_packet(&mut format, track_id)? {
+        hasher.update(&packet.data);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+fn open_audio(path: &PathBuf) -> CleanerResult<(Box<dyn FormatReader>, u32, CodecParameters)> {
+    let source = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+
+    let mut hint = Hint::new();
+    if let Some(ext) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+
+    let probed = symphonia::default::get_probe().format(&hint, source, &FormatOptions::default(), &MetadataOptions::default())?;
+    let format = probed.format;
+
+    let track = format.default_track().ok_or(CleanerError::MissingAudioTrack)?;
+    let track_id = track.id;
+    let codec_params = track.codec_params.clone();
+
+    Ok((format, track_id, codec_params))
+}
+
+/// Get the next packet for the given track, or None at the end of the stream
+fn next_packet(format: &mut Box<dyn FormatReader>, track_id: u32) -> CleanerResult<Option<Packet>> {
+    loop {
+        match format.next_packet() {
+            Ok(packet) if packet.track_id() == track_id => return Ok(Some(packet)),
+            Ok(_) => continue,
+            Err(Error::IoError(err)) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into())
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum DuplicatePolicy {
+    /// Do not write a track if it is a duplicate
+    Skip,
+    /// Write the duplicate track alongside the existing one, with a numbered suffix
+    Suffix,
+    /// Replace the existing track with the duplicate
+    Replace,
+}
+
+impl Display for DuplicatePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DuplicatePolicy::Skip => f.write_str("skip"),
+            DuplicatePolicy::Suffix => f.write_str("suffix"),
+            DuplicatePolicy::Replace => f.write_str("replace"),
+        }
+    }
+}
@@ -0,0 +1,20 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum EditionPolicy {
+    /// Keep only the best quality edition of an album
+    Best,
+    /// Keep every edition of an album, each in a directory suffixed with the format
+    Separate,
+}
+
+impl Display for EditionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EditionPolicy::Best => f.write_str("best"),
+            EditionPolicy::Separate => f.write_str("separate"),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::{path::{PathBuf, Path}, collections::BTreeMap, fs, cmp::Reverse};
+
+use colored::Colorize;
+use walkdir::WalkDir;
+
+use crate::{tagger::get_tagged_file, audio_file::AudioFile, audio_file_meta::AudioFileType, audio_quality::AudioQuality, edition_policy::EditionPolicy, media_file::MediaFile, options::Options};
+
+/// The tracks of an album in a single format
+pub struct Edition<'a> {
+    file_type: AudioFileType,
+    quality: AudioQuality,
+    audio_files: Vec<&'a AudioFile>,
+}
+
+impl<'a> Edition<'a> {
+
+    fn new(file_type: AudioFileType, audio_files: Vec<&'a AudioFile>) -> Edition<'a> {
+        Edition {
+            file_type,
+            quality: AudioQuality::of_tracks(&audio_files),
+            audio_files
+        }
+    }
+
+    /// Name of the edition, e.g. "FLAC 24-96" or "MP3"
+    pub fn name(&self) -> String {
+        match self.quality.resolution() {
+            Some(resolution) => format!("{} {}", self.file_type.to_name(), resolution),
+            None => self.file_type.to_name().to_string()
+        }
+    }
+
+    fn is_same(&self, other: &Edition) -> bool {
+        self.file_type == other.file_type && self.quality == other.quality
+    }
+}
+
+/// An edition of an album to write, where to write it, and the existing editions it replaces
+pub struct SelectedEdition<'a> {
+    pub album_output_path: PathBuf,
+    pub audio_files: Vec<&'a AudioFile>,
+    /// Names and files of existing editions, removed only once this edition has been written
+    pub replaced: Vec<(String, Vec<PathBuf>)>,
+}
+
+impl<'a> SelectedEdition<'a> {
+
+    fn new(album_output_path: PathBuf, audio_files: Vec<&'a AudioFile>) -> SelectedEdition<'a> {
+        SelectedEdition {
+            album_output_path,
+            audio_files,
+            replaced: Vec::new()
+        }
+    }
+}
+
+/// Choose which editions of an album to write, and where, when the same album arrives in more than one format or is
+/// already in the output directory in a different format
+pub fn select_editions<'a>(album_output_path: &PathBuf, audio_files: &Vec<&'a AudioFile>, options: &Options) -> Vec<SelectedEdition<'a>> {
+    let edition_policy = match options.editions {
+        Some(edition_policy) => edition_policy,
+        None => return vec![SelectedEdition::new(album_output_path.to_owned(), audio_files.to_owned())]
+    };
+
+    let editions = get_editions(audio_files);
+
+    let existing_files = get_existing_audio_files(album_output_path, options);
+    let existing_editions = get_editions(&existing_files.iter().collect());
+
+    match edition_policy {
+        EditionPolicy::Best => select_best_edition(album_output_path, editions, existing_editions),
+        EditionPolicy::Separate => separate_editions(album_output_path, editions, existing_editions),
+    }
+}
+
+fn select_best_edition<'a>(album_output_path: &PathBuf, editions: Vec<Edition<'a>>, existing_editions: Vec<Edition>) -> Vec<SelectedEdition<'a>> {
+    let album_name = get_album_name(album_output_path);
+
+    let mut editions = editions.into_iter();
+    let best = match editions.next() {
+        Some(best) => best,
+        None => return Vec::new()
+    };
+
+    for edition in editions {
+        println!("  Album {} {} {} {}", album_name.bright_cyan().bold(), "SKIPPED".bright_yellow().bold(), edition.name().yellow(), format!("(keeping {})", best.name()).yellow());
+    }
+
+    if let Some(existing_best) = existing_editions.first().filter(|e| !e.is_same(&best)) {
+        if existing_best.quality >= best.quality {
+            println!("  Album {} {} {} {}", album_name.bright_cyan().bold(), "SKIPPED".bright_yellow().bold(), best.name().yellow(), format!("(already have {})", existing_best.name()).yellow());
+            return Vec::new();
+        }
+    }
+
+    let replaced = existing_editions
+        .iter()
+        .filter(|e| !e.is_same(&best))
+        .map(|e| (e.name(), e.audio_files.iter().map(|f| f.path().to_owned()).collect()))
+        .collect();
+
+    vec![SelectedEdition { album_output_path: album_output_path.to_owned(), audio_files: best.audio_files, replaced }]
+}
+
+/// Remove the files of the editions replaced by a new edition, once all of its tracks have been written and can be read
+/// back, otherwise keep them. Files that the new edition was written over are not removed.
+pub fn remove_replaced_editions(edition: &SelectedEdition, written_paths: &[&PathBuf]) {
+    let album_name = get_album_name(&edition.album_output_path);
+    let is_complete = written_paths.len() == edition.audio_files.len() && written_paths.iter().all(|p| get_tagged_file(p).is_ok());
+
+    for (name, paths) in &edition.replaced {
+        print!("  Album {} {} {} ", album_name.bright_cyan().bold(), "REPLACE".bright_yellow().bold(), name.yellow());
+
+        if !is_complete {
+            println!("{} {}", "KEPT".bright_yellow().bold(), "not every track of the new edition was written".yellow());
+            continue;
+        }
+
+        match paths.iter().filter(|p| !written_paths.contains(p)).try_for_each(fs::remove_file) {
+            Ok(_) => println!("{}", "OK".bright_green().bold()),
+            Err(err) => println!("{} {}", "ERROR".bright_red().bold(), err.to_string().red()),
+        }
+    }
+}
+
+fn separate_editions<'a>(album_output_path: &PathBuf, editions: Vec<Edition<'a>>, existing_editions: Vec<Edition>) -> Vec<SelectedEdition<'a>> {
+    let only_edition = editions.len() == 1 && existing_editions.iter().all(|e| e.is_same(&editions[0]));
+
+    editions
+        .into_iter()
+        .map(|edition| {
+            // Keep writing to the album directory without a suffix if it already contains this edition
+            if only_edition || existing_editions.iter().any(|e| e.is_same(&edition)) {
+                SelectedEdition::new(album_output_path.to_owned(), edition.audio_files)
+            } else {
+                let suffixed_name = format!("{} [{}]", get_album_name(album_output_path), edition.name());
+                SelectedEdition::new(album_output_path.with_file_name(suffixed_name), edition.audio_files)
+            }
+        })
+        .collect()
+}
+
+/// Split the tracks of an album into one edition per file format, best quality first
+fn get_editions<'a>(audio_files: &Vec<&'a AudioFile>) -> Vec<Edition<'a>> {
+    let mut editions = audio_files
+        .iter()
+        .filter_map(|f| f.get_meta().audio_file_type().map(|t| (*t, *f)))
+        .fold(
+            BTreeMap::<AudioFileType, Vec<&AudioFile>>::new(),
+            |mut acc, (file_type, audio_file)| {
+                acc.entry(file_type)
+                    .or_insert_with(Vec::new)
+                    .push(audio_file);
+                acc
+            }
+        )
+        .into_iter()
+        .map(|(file_type, audio_files)| Edition::new(file_type, audio_files))
+        .collect::<Vec<Edition>>();
+
+    editions.sort_by_key(|e| Reverse(e.quality));
+    editions
+}
+
+/// Audio files already in the album directory, read with the same settings as the new files. Files that cannot be
+/// read are reported, and left out, so are neither compared nor replaced.
+fn get_existing_audio_files(album_output_path: &PathBuf, options: &Options) -> Vec<AudioFile> {
+    let album_name = get_album_name(album_output_path);
+
+    WalkDir::new(album_output_path)
+        .min_depth(1)
+        .max_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir())
+        .filter(|e| matches!(e.path().extension().and_then(|e| e.to_str()), Some("mp3" | "flac")))
+        .filter_map(|e| match AudioFile::new(album_output_path, e.path().to_path_buf(), options, None) {
+            Ok(audio_file) => Some(audio_file),
+            Err(err) => {
+                println!("  Album {} {} {} {}", album_name.bright_cyan().bold(), "ERROR".bright_red().bold(), e.file_name().to_string_lossy().yellow(), err.to_string().red());
+                None
+            }
+        })
+        .collect()
+}
+
+fn get_album_name(album_output_path: &Path) -> String {
+    album_output_path
+        .file_name()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
@@ -6,6 +6,9 @@
 
 #[derive(Debug, Error)]
 pub enum CleanerError {
+    #[error(transparent)]
+    Config(#[from] toml::de::Error),
+
     #[error(transparent)]
     Image(#[from] image::ImageError),
 
@@ -15,6 +18,9 @@ pub enum CleanerError {
     #[error(transparent)]
     Lofty(#[from] lofty::LoftyError),
 
+    #[error(transparent)]
+    Symphonia(#[from] symphonia::core::errors::Error),
+
     #[error("failed to process rar")]
     Unrar,
 
@@ -26,6 +32,30 @@ pub enum CleanerError {
 
     #[error("unexpected file extension")]
     UnexpectedFileExtension,
+
+    #[error("no audio track found")]
+    MissingAudioTrack,
+
+    #[error("invalid pattern '{0}': {1}")]
+    InvalidPattern(String, String),
+
+    #[error("invalid MPEG audio stream")]
+    InvalidMpegStream,
+
+    #[error("invalid FLAC stream")]
+    InvalidFlacStream,
+
+    #[error("invalid ID3v2 tag")]
+    InvalidId3v2Tag,
+
+    #[error("invalid tag backup")]
+    InvalidTagBackup,
+
+    #[error("gapless playback information changed while writing tags")]
+    GaplessInfoChanged,
+
+    #[error("ReplayGain is not supported for Opus files, which use R128 gain tags")]
+    UnsupportedReplayGain,
 }
 
 impl<T> From<unrar::error::UnrarError<T>> for CleanerError {
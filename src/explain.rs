@@ -0,0 +1,99 @@
+use std::{path::Path, fs::OpenOptions, io::Write};
+
+use clap::ValueEnum;
+use colored::Colorize;
+use serde_json::{json, Map, Value};
+
+use crate::{audio_file_meta::AudioFileMeta, error::CleanerResult, metadata_field::MetadataField};
+
+/// Source name for values taken from another track of the same album
+pub const ALBUM_SOURCE_NAME: &str = "album";
+
+/// A value used for a track that has none of its own, with the name of where it came from
+pub struct AlbumFallback {
+    pub field: MetadataField,
+    pub value: String,
+    pub source: String,
+}
+
+/// Print where each metadata value of a track came from, and the values that were ignored
+pub fn print_explanation(meta: &AudioFileMeta, fallbacks: &[AlbumFallback]) {
+    for field in MetadataField::value_variants() {
+        let (value, source) = get_value_and_source(meta, *field, fallbacks);
+        let ignored = meta.provenance()
+            .ignored(*field)
+            .iter()
+            .map(|(source, value)| format!("{} \"{}\"", source, value))
+            .collect::<Vec<String>>();
+
+        let value = format!("{:30}", value.as_deref().unwrap_or("-"));
+        print!("        {:12} {} {:8}", field.to_string().dimmed(), value.white(), source.unwrap_or_default().bright_blue());
+        if !ignored.is_empty() {
+            print!(" {} {}", "ignored".dimmed(), ignored.join(", ").dimmed());
+        }
+        println!();
+    }
+}
+
+/// Append the explanation of a track, as a line of JSON, to a file
+pub fn write_explanation(path: &Path, source_path: &Path, target_path: &Path, meta: &AudioFileMeta, fallbacks: &[AlbumFallback]) -> CleanerResult<()> {
+    let fields = MetadataField::value_variants()
+        .iter()
+        .map(|field| {
+            let (value, source) = get_value_and_source(meta, *field, fallbacks);
+            let ignored = meta.provenance()
+                .ignored(*field)
+                .iter()
+                .map(|(source, value)| json!({ "source": source.to_string(), "value": value }))
+                .collect::<Vec<Value>>();
+            (field.to_string(), json!({ "value": value, "source": source, "ignored": ignored }))
+        })
+        .collect::<Map<String, Value>>();
+
+    let line = json!({
+        "source": source_path.to_string_lossy(),
+        "target": target_path.to_string_lossy(),
+        "fields": fields,
+        "audio": get_audio_properties(meta)
+    });
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)?;
+
+    Ok(())
+}
+
+/// Audio properties of a track, as JSON: sample rate in Hz, bit depth, channels, bitrate in kbps and duration in seconds,
+/// null where the file has none, e.g. the bit depth of lossy files
+pub fn get_audio_properties(meta: &AudioFileMeta) -> Value {
+    json!({
+        "sample_rate": meta.sample_rate(),
+        "bit_depth": meta.bit_depth(),
+        "channels": meta.channels(),
+        "bitrate": meta.bitrate(),
+        "duration": meta.duration().as_secs_f64()
+    })
+}
+
+fn get_value_and_source(meta: &AudioFileMeta, field: MetadataField, fallbacks: &[AlbumFallback]) -> (Option<String>, Option<String>) {
+    let value = match field {
+        MetadataField::AlbumArtist => meta.album_artist_name().map(|s| s.to_string()),
+        MetadataField::Artist => meta.artist_name(),
+        MetadataField::Album => meta.album_title().map(|s| s.to_string()),
+        MetadataField::Year => meta.year().map(|y| y.to_string()),
+        MetadataField::Track => meta.track_number().map(|n| n.to_string()),
+        MetadataField::Disc => meta.disc_number().map(|n| n.to_string()),
+        MetadataField::Title => meta.track_title().map(|s| s.to_string()),
+        MetadataField::Genre => meta.genre(),
+        MetadataField::Catalogue => meta.catalogue_number().map(|s| s.to_string()),
+    };
+
+    match value {
+        Some(value) => (Some(value), meta.provenance().source(field).map(|s| s.to_string())),
+        None => fallbacks
+            .iter()
+            .find(|f| f.field == field)
+            .map(|f| (Some(f.value.to_owned()), Some(f.source.to_owned())))
+            .unwrap_or((None, None))
+    }
+}
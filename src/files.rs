@@ -1,10 +1,10 @@
-use std::{path::PathBuf};
+use std::path::{Path, PathBuf};
 
 use colored::Colorize;
 
-use crate::cleaner::clean_files;
+use crate::{cleaner::clean_files, options::Options, library::Library};
 
-pub fn process_files(path: &PathBuf, output_path: &PathBuf, quality: u8) {
+pub fn process_files(path: &PathBuf, output_path: &Path, options: &Options, library: &mut Library) {
     println!("Processing files in {} to {}...\n",
         path.to_string_lossy().bright_yellow().bold(),
         output_path.to_string_lossy().bright_yellow().bold()
@@ -15,7 +15,7 @@ pub fn process_files(path: &PathBuf, output_path: &PathBuf, quality: u8) {
         return
     }
 
-    clean_files(path, output_path, quality);
+    clean_files(path, output_path, options, library, None, None);
 
     println!("Finished.");
 }
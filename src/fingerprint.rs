@@ -0,0 +1,140 @@
+use std::path::PathBuf;
+
+use crate::{decoder::decode_audio, error::CleanerResult, spectrum::{Stft, Downsampler, mix_to_mono, to_pitch_class}};
+
+// A Chromaprint-style fingerprint, built from the chroma (energy per pitch class) of overlapping frames of the audio,
+// which is largely independent of the encoding
+
+const SAMPLE_RATE: u32 = 11025;
+const FRAME_SIZE: usize = 4096;
+const HOP_SIZE: usize = FRAME_SIZE / 3;
+
+const MIN_FREQUENCY: f32 = 28.0;
+const MAX_FREQUENCY: f32 = 3520.0;
+
+/// Fraction of matching bits required for two fingerprints to be considered the same recording
+const MATCH_THRESHOLD: f32 = 0.85;
+
+/// Maximum alignment offset (in frames, about two seconds) to allow for differences in leading silence
+const MAX_OFFSET: isize = 16;
+
+/// Maximum difference in duration, in seconds, for two fingerprints to be compared at all
+const MAX_DURATION_DIFFERENCE: u32 = 5;
+
+#[derive(Clone)]
+pub struct Fingerprint {
+    /// Duration of the fingerprinted audio in seconds
+    duration: u32,
+    values: Vec<u32>,
+}
+
+impl Fingerprint {
+
+    pub fn matches(&self, other: &Fingerprint) -> bool {
+        self.duration.abs_diff(other.duration) <= MAX_DURATION_DIFFERENCE && self.similarity(other) >= MATCH_THRESHOLD
+    }
+
+    /// Fraction of matching bits at the best alignment of the two fingerprints
+    pub fn similarity(&self, other: &Fingerprint) -> f32 {
+        (-MAX_OFFSET..=MAX_OFFSET)
+            .filter_map(|offset| {
+                let pairs = self.values
+                    .iter()
+                    .skip(offset.max(0) as usize)
+                    .zip(other.values.iter().skip((-offset).max(0) as usize));
+
+                let (count, differences) = pairs.fold((0, 0), |(count, differences), (a, b)| (count + 1, differences + (a ^ b).count_ones()));
+                if count == 0 {
+                    None
+                } else {
+                    Some(1.0 - differences as f32 / (count * 32) as f32)
+                }
+            })
+            .fold(0.0, f32::max)
+    }
+
+    pub fn to_text(&self) -> String {
+        let values = self.values
+            .iter()
+            .map(|v| format!("{:08x}", v))
+            .collect::<String>();
+        format!("{}:{}", self.duration, values)
+    }
+
+    pub fn from_text(text: &str) -> Option<Fingerprint> {
+        let (duration, values) = text.split_once(':')?;
+        let values = values
+            .as_bytes()
+            .chunks(8)
+            .map(|chunk| std::str::from_utf8(chunk).ok().and_then(|s| u32::from_str_radix(s, 16).ok()))
+            .collect::<Option<Vec<u32>>>()?;
+
+        Some(Fingerprint {
+            duration: duration.parse().ok()?,
+            values
+        })
+    }
+}
+
+pub fn calculate_fingerprint(path: &PathBuf) -> CleanerResult<Fingerprint> {
+    let mut downsampler = None::<Downsampler>;
+    let mut stft = Stft::new(FRAME_SIZE, HOP_SIZE);
+    let mut mono = Vec::new();
+    let mut downsampled = Vec::new();
+    let mut sample_count = 0;
+    let mut chromas = Vec::<[f32; 12]>::new();
+
+    decode_audio(path, |sample_rate, channels, samples| {
+        mono.clear();
+        downsampled.clear();
+        mix_to_mono(samples, channels, &mut mono);
+        downsampler
+            .get_or_insert_with(|| Downsampler::new(sample_rate, SAMPLE_RATE))
+            .process(&mono, &mut downsampled);
+        sample_count += downsampled.len();
+        stft.push(&downsampled, |magnitudes| chromas.push(get_chroma(magnitudes)));
+    })?;
+
+    let values = chromas
+        .windows(2)
+        .map(|w| get_sub_fingerprint(&w[0], &w[1]))
+        .collect();
+
+    Ok(Fingerprint {
+        duration: (sample_count / SAMPLE_RATE as usize) as u32,
+        values
+    })
+}
+
+/// Energy in each of the twelve pitch classes, normalised
+fn get_chroma(magnitudes: &[f32]) -> [f32; 12] {
+    let mut chroma = [0.0f32; 12];
+    let bin_width = SAMPLE_RATE as f32 / FRAME_SIZE as f32;
+
+    for (bin, magnitude) in magnitudes.iter().enumerate() {
+        let frequency = bin as f32 * bin_width;
+        if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+            continue;
+        }
+        chroma[to_pitch_class(frequency)] += magnitude * magnitude;
+    }
+
+    let norm = chroma.iter().map(|c| c * c).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        chroma.iter_mut().for_each(|c| *c /= norm);
+    }
+    chroma
+}
+
+/// Encode the shape of a chroma frame and how it changed from the previous frame as 32 bits
+fn get_sub_fingerprint(previous: &[f32; 12], current: &[f32; 12]) -> u32 {
+    let mut value = 0u32;
+    for i in 0..12 {
+        value |= ((current[i] > current[(i + 1) % 12]) as u32) << i;
+        value |= ((current[i] > previous[i]) as u32) << (12 + i);
+    }
+    for i in 0..8 {
+        value |= ((current[i] > current[(i + 3) % 12]) as u32) << (24 + i);
+    }
+    value
+}
@@ -0,0 +1,216 @@
+use lofty::ogg::VorbisComments;
+
+use crate::error::{CleanerResult, CleanerError};
+
+// FLAC metadata blocks, see https://xiph.org/flac/format.html#metadata_block
+
+const FLAC_MARKER: &[u8] = b"fLaC";
+const BLOCK_HEADER_LENGTH: usize = 4;
+const LAST_BLOCK_FLAG: u8 = 0x80;
+const BLOCK_TYPE_MASK: u8 = 0x7f;
+const MAX_BLOCK_LENGTH: usize = 0xff_ffff;
+
+const PADDING_BLOCK_TYPE: u8 = 1;
+const VORBIS_COMMENT_BLOCK_TYPE: u8 = 4;
+const PICTURE_BLOCK_TYPE: u8 = 6;
+
+/// The type and body of a metadata block
+type Block<'a> = (u8, &'a [u8]);
+
+/// Build a FLAC file from the stream of an existing one, keeping its stream information, seek table, application and
+/// cue sheet blocks, with new Vorbis comment and picture blocks, and a padding block of the given length
+pub fn build_flac_file(data: &[u8], comments: &VorbisComments, padding: usize) -> CleanerResult<Vec<u8>> {
+    let (metadata_blocks, audio_start) = read_blocks(data)?;
+
+    let mut blocks = Vec::new();
+    let mut vendor = None;
+    for (block_type, body) in metadata_blocks {
+        match block_type {
+            // The vendor string of the existing comments identifies the encoder, so keep it unless there is a new one
+            VORBIS_COMMENT_BLOCK_TYPE => vendor = read_vendor(body),
+            PADDING_BLOCK_TYPE | PICTURE_BLOCK_TYPE => {},
+            _ => blocks.push((block_type, body.to_vec()))
+        }
+    }
+
+    let vendor = Some(comments.vendor()).filter(|v| !v.is_empty()).or(vendor.as_deref()).unwrap_or_default();
+    blocks.push((VORBIS_COMMENT_BLOCK_TYPE, build_comment_block(vendor, comments)));
+    for (picture, information) in comments.pictures() {
+        blocks.push((PICTURE_BLOCK_TYPE, picture.as_flac_bytes(*information, false)));
+    }
+    if padding > 0 {
+        blocks.push((PADDING_BLOCK_TYPE, vec![0; padding.min(MAX_BLOCK_LENGTH)]));
+    }
+
+    let mut output = Vec::with_capacity(data.len() + padding);
+    output.extend_from_slice(FLAC_MARKER);
+    for (i, (block_type, body)) in blocks.iter().enumerate() {
+        if body.len() > MAX_BLOCK_LENGTH {
+            return Err(CleanerError::InvalidFlacStream);
+        }
+
+        let last_block_flag = if i == blocks.len() - 1 { LAST_BLOCK_FLAG } else { 0 };
+        output.push(block_type | last_block_flag);
+        output.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+        output.extend_from_slice(body);
+    }
+    output.extend_from_slice(&data[audio_start..]);
+
+    Ok(output)
+}
+
+/// Vorbis comments are little endian, unlike the rest of FLAC
+fn build_comment_block(vendor: &str, comments: &VorbisComments) -> Vec<u8> {
+    let mut body = Vec::new();
+    body.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    body.extend_from_slice(vendor.as_bytes());
+
+    body.extend_from_slice(&(comments.items().len() as u32).to_le_bytes());
+    for (key, value) in comments.items() {
+        let comment = format!("{}={}", key, value);
+        body.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        body.extend_from_slice(comment.as_bytes());
+    }
+
+    body
+}
+
+/// The length of the marker and metadata blocks at the start of a FLAC stream, i.e. where its audio frames start
+pub fn get_metadata_length(data: &[u8]) -> CleanerResult<usize> {
+    read_blocks(data).map(|(_, audio_start)| audio_start)
+}
+
+/// The type and body of each metadata block, and the position of the audio frames after them
+fn read_blocks(data: &[u8]) -> CleanerResult<(Vec<Block<'_>>, usize)> {
+    if !data.starts_with(FLAC_MARKER) {
+        return Err(CleanerError::InvalidFlacStream);
+    }
+
+    let mut blocks = Vec::new();
+    let mut position = FLAC_MARKER.len();
+    loop {
+        let header = data.get(position..position + BLOCK_HEADER_LENGTH).ok_or(CleanerError::InvalidFlacStream)?;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let body_start = position + BLOCK_HEADER_LENGTH;
+        let body = data.get(body_start..body_start + length).ok_or(CleanerError::InvalidFlacStream)?;
+
+        blocks.push((header[0] & BLOCK_TYPE_MASK, body));
+
+        position = body_start + length;
+        if header[0] & LAST_BLOCK_FLAG != 0 {
+            return Ok((blocks, position));
+        }
+    }
+}
+
+fn read_vendor(body: &[u8]) -> Option<String> {
+    let length = u32::from_le_bytes(body.get(0..4)?.try_into().ok()?) as usize;
+    body.get(4..4 + length).map(|vendor| String::from_utf8_lossy(vendor).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use lofty::{AudioFile, ParseOptions, Picture, PictureType, PictureInformation, MimeType, flac::FlacFile};
+
+    use super::*;
+
+    const STREAM_INFO_BLOCK_TYPE: u8 = 0;
+    const SEEK_TABLE_BLOCK_TYPE: u8 = 3;
+    const AUDIO: &[u8] = b"\xff\xf8audio frames";
+
+    /// Stream information of 16 bit stereo at 44.1kHz, with 4096 samples in each block
+    fn stream_info() -> Vec<u8> {
+        let mut body = vec![0x10, 0x00, 0x10, 0x00, 0, 0, 0, 0, 0, 0];
+        body.extend_from_slice(&((44100u64 << 44) | (1 << 41) | (15 << 36)).to_be_bytes());
+        body.extend_from_slice(&[0; 16]);
+        body
+    }
+
+    /// A FLAC stream of the given metadata blocks, after its stream information, followed by audio frames
+    fn build_stream(blocks: &[Block<'_>]) -> Vec<u8> {
+        let stream_info = stream_info();
+        let blocks = [&[(STREAM_INFO_BLOCK_TYPE, stream_info.as_slice())], blocks].concat();
+
+        let mut data = FLAC_MARKER.to_vec();
+        for (i, (block_type, body)) in blocks.iter().enumerate() {
+            data.push(block_type | if i == blocks.len() - 1 { LAST_BLOCK_FLAG } else { 0 });
+            data.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+            data.extend_from_slice(body);
+        }
+        data.extend_from_slice(AUDIO);
+        data
+    }
+
+    fn picture(description: &str) -> Picture {
+        Picture::new_unchecked(PictureType::CoverFront, MimeType::Png, Some(description.to_string()), b"\x89PNG\r\n\x1a\n".to_vec())
+    }
+
+    fn comments(values: &[(&str, &str)]) -> VorbisComments {
+        let mut comments = VorbisComments::default();
+        for (key, value) in values {
+            comments.insert(key.to_string(), value.to_string(), false);
+        }
+        comments
+    }
+
+    fn block_types(data: &[u8]) -> Vec<u8> {
+        read_blocks(data).unwrap().0.iter().map(|(block_type, _)| *block_type).collect()
+    }
+
+    fn read_comments(data: &[u8]) -> VorbisComments {
+        let flac_file = FlacFile::read_from(&mut Cursor::new(data), ParseOptions::new()).unwrap();
+        flac_file.vorbis_comments().unwrap().to_owned()
+    }
+
+    #[test]
+    fn comments_and_pictures_are_replaced_and_padding_added() {
+        let old_comments = build_comment_block("reference libFLAC 1.3.2", &comments(&[("TITLE", "Old title")]));
+        let old_picture = picture("Old").as_flac_bytes(PictureInformation::default(), false);
+        let data = build_stream(&[
+            (SEEK_TABLE_BLOCK_TYPE, &[0; 18]),
+            (VORBIS_COMMENT_BLOCK_TYPE, &old_comments),
+            (PICTURE_BLOCK_TYPE, &old_picture),
+            (PADDING_BLOCK_TYPE, &[0; 100]),
+        ]);
+
+        let mut new_comments = comments(&[("TITLE", "New title"), ("ARTIST", "Artist"), ("ARTIST", "Other")]);
+        new_comments.insert_picture(picture("New"), Some(PictureInformation::default())).unwrap();
+        let output = build_flac_file(&data, &new_comments, 64).unwrap();
+
+        assert_eq!(block_types(&output), [STREAM_INFO_BLOCK_TYPE, SEEK_TABLE_BLOCK_TYPE, VORBIS_COMMENT_BLOCK_TYPE, PICTURE_BLOCK_TYPE, PADDING_BLOCK_TYPE]);
+        assert_eq!(read_blocks(&output).unwrap().0[4].1.len(), 64);
+        assert!(output.ends_with(AUDIO));
+
+        let written = read_comments(&output);
+        assert_eq!(written.vendor(), "reference libFLAC 1.3.2");
+        assert_eq!(written.get("TITLE"), Some("New title"));
+        assert_eq!(written.get_all("ARTIST").collect::<Vec<&str>>(), ["Artist", "Other"]);
+        assert_eq!(written.pictures().len(), 1);
+        assert_eq!(written.pictures()[0].0.description(), Some("New"));
+    }
+
+    #[test]
+    fn no_padding_block_is_written_without_padding() {
+        let data = build_stream(&[(PADDING_BLOCK_TYPE, &[0; 100])]);
+
+        let output = build_flac_file(&data, &comments(&[("TITLE", "Title")]), 0).unwrap();
+
+        assert_eq!(block_types(&output), [STREAM_INFO_BLOCK_TYPE, VORBIS_COMMENT_BLOCK_TYPE]);
+        assert_eq!(get_metadata_length(&output).unwrap(), output.len() - AUDIO.len());
+        assert_eq!(read_comments(&output).get("TITLE"), Some("Title"));
+    }
+
+    #[test]
+    fn comments_larger_than_the_old_metadata_move_the_audio() {
+        let data = build_stream(&[(PADDING_BLOCK_TYPE, &[0; 16])]);
+        let lyrics = "La ".repeat(5000);
+
+        let output = build_flac_file(&data, &comments(&[("LYRICS", &lyrics)]), 16).unwrap();
+
+        assert!(output.len() > data.len() + lyrics.len());
+        assert!(output.ends_with(AUDIO));
+        assert_eq!(read_comments(&output).get("LYRICS"), Some(lyrics.as_str()));
+    }
+}
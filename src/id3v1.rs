@@ -0,0 +1,187 @@
+use lofty::{ItemKey, Tag, TagExt};
+
+use crate::{error::CleanerResult, metadata_field::MetadataField};
+
+/// Bytes of an ID3v1 tag, see https://id3.org/ID3v1
+const ID3V1_LENGTH: usize = 128;
+
+/// Bytes available for each text field of an ID3v1 tag
+const ID3V1_TEXT_LENGTH: usize = 30;
+
+/// Text fields of an ID3v1 tag, with the field each is reported as and its offset in the tag
+const ID3V1_TEXT_ITEMS: [(ItemKey, MetadataField, usize); 3] = [
+    (ItemKey::TrackTitle, MetadataField::Title, 3),
+    (ItemKey::TrackArtist, MetadataField::Artist, 33),
+    (ItemKey::AlbumTitle, MetadataField::Album, 63),
+];
+
+/// Write an ID3v1 tag with its text values encoded as ISO-8859-1, or transliterated to ASCII, and truncated to fit,
+/// returning the fields that had to be truncated. Lofty would write the UTF-8 bytes of a value, cut at any byte, so it
+/// is given values of the same length in ASCII, which are replaced once the tag is written.
+pub fn write_id3v1_tag(mut tag: Tag, ascii: bool, output: &mut Vec<u8>) -> CleanerResult<Vec<MetadataField>> {
+    let mut truncated_fields = Vec::new();
+    let mut encoded_values = Vec::new();
+
+    for (item_key, field, offset) in ID3V1_TEXT_ITEMS {
+        if let Some(value) = tag.get_string(&item_key) {
+            let mut encoded = if ascii { transliterate(value).into_bytes() } else { encode_latin1(value) };
+            if encoded.len() > ID3V1_TEXT_LENGTH {
+                truncated_fields.push(field);
+                encoded.truncate(ID3V1_TEXT_LENGTH);
+            }
+            while encoded.last() == Some(&b' ') {
+                encoded.pop();
+            }
+
+            let stand_in = encoded.iter().map(|b| if b.is_ascii() { *b as char } else { '?' }).collect::<String>();
+            tag.insert_text(item_key, stand_in);
+            encoded_values.push((offset, encoded));
+        }
+    }
+
+    let tag_start = output.len();
+    tag.dump_to(output)?;
+
+    if output.len() == tag_start + ID3V1_LENGTH {
+        for (offset, encoded) in encoded_values {
+            let start = tag_start + offset;
+            output[start..start + encoded.len()].copy_from_slice(&encoded);
+        }
+    }
+
+    Ok(truncated_fields)
+}
+
+/// Encode a value as ISO-8859-1, with characters outside of it transliterated to ASCII
+fn encode_latin1(value: &str) -> Vec<u8> {
+    value
+        .chars()
+        .flat_map(|c| match u8::try_from(c) {
+            Ok(b) => vec![b],
+            Err(_) => transliterate(&c.to_string()).into_bytes()
+        })
+        .collect()
+}
+
+/// Replace accented letters, ligatures and typographic punctuation with their nearest ASCII equivalents, and any other
+/// character with a question mark
+fn transliterate(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            c if c.is_ascii() => c.to_string(),
+            'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => "A".to_string(),
+            'à'..='å' | 'ā' | 'ă' | 'ą' => "a".to_string(),
+            'Æ' => "AE".to_string(),
+            'æ' => "ae".to_string(),
+            'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C".to_string(),
+            'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c".to_string(),
+            'Ð' | 'Ď' | 'Đ' => "D".to_string(),
+            'ð' | 'ď' | 'đ' => "d".to_string(),
+            'È'..='Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E".to_string(),
+            'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e".to_string(),
+            'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G".to_string(),
+            'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g".to_string(),
+            'Ĥ' | 'Ħ' => "H".to_string(),
+            'ĥ' | 'ħ' => "h".to_string(),
+            'Ì'..='Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I".to_string(),
+            'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i".to_string(),
+            'Ĵ' => "J".to_string(),
+            'ĵ' => "j".to_string(),
+            'Ķ' => "K".to_string(),
+            'ķ' => "k".to_string(),
+            'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L".to_string(),
+            'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l".to_string(),
+            'Ñ' | 'Ń' | 'Ņ' | 'Ň' => "N".to_string(),
+            'ñ' | 'ń' | 'ņ' | 'ň' => "n".to_string(),
+            'Ò'..='Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O".to_string(),
+            'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o".to_string(),
+            'Œ' => "OE".to_string(),
+            'œ' => "oe".to_string(),
+            'Ŕ' | 'Ŗ' | 'Ř' => "R".to_string(),
+            'ŕ' | 'ŗ' | 'ř' => "r".to_string(),
+            'Ś' | 'Ŝ' | 'Ş' | 'Š' | 'Ș' => "S".to_string(),
+            'ś' | 'ŝ' | 'ş' | 'š' | 'ș' => "s".to_string(),
+            'ß' => "ss".to_string(),
+            'Ţ' | 'Ť' | 'Ŧ' | 'Ț' => "T".to_string(),
+            'ţ' | 'ť' | 'ŧ' | 'ț' => "t".to_string(),
+            'Þ' => "TH".to_string(),
+            'þ' => "th".to_string(),
+            'Ù'..='Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U".to_string(),
+            'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u".to_string(),
+            'Ŵ' => "W".to_string(),
+            'ŵ' => "w".to_string(),
+            'Ý' | 'Ŷ' | 'Ÿ' => "Y".to_string(),
+            'ý' | 'ÿ' | 'ŷ' => "y".to_string(),
+            'Ź' | 'Ż' | 'Ž' => "Z".to_string(),
+            'ź' | 'ż' | 'ž' => "z".to_string(),
+            '‘' | '’' | '‚' | '′' | '´' => "'".to_string(),
+            '“' | '”' | '„' | '″' | '«' | '»' => "\"".to_string(),
+            '‐' | '‑' | '‒' | '–' | '—' | '―' => "-".to_string(),
+            '…' => "...".to_string(),
+            '×' => "x".to_string(),
+            '\u{a0}' => " ".to_string(),
+            _ => "?".to_string(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use lofty::TagType;
+
+    use super::*;
+
+    /// Write an ID3v1 tag of the given title, artist and album, returning its bytes and the fields that were truncated
+    fn write_tag(title: &str, artist: &str, album: &str, ascii: bool) -> (Vec<u8>, Vec<MetadataField>) {
+        let mut tag = Tag::new(TagType::ID3v1);
+        tag.insert_text(ItemKey::TrackTitle, title.to_string());
+        tag.insert_text(ItemKey::TrackArtist, artist.to_string());
+        tag.insert_text(ItemKey::AlbumTitle, album.to_string());
+
+        let mut output = Vec::new();
+        let truncated_fields = write_id3v1_tag(tag, ascii, &mut output).unwrap();
+        assert_eq!(output.len(), ID3V1_LENGTH);
+        (output, truncated_fields)
+    }
+
+    /// The bytes of a text field, without the null bytes it is padded with
+    fn text_field(output: &[u8], offset: usize) -> &[u8] {
+        let field = &output[offset..offset + ID3V1_TEXT_LENGTH];
+        let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+        &field[..end]
+    }
+
+    #[test]
+    fn text_is_written_as_iso_8859_1() {
+        let (output, truncated_fields) = write_tag("Café", "Björk", "Señor", false);
+
+        assert!(output.starts_with(b"TAG"));
+        assert_eq!(text_field(&output, 3), b"Caf\xe9");
+        assert_eq!(text_field(&output, 33), b"Bj\xf6rk");
+        assert_eq!(text_field(&output, 63), b"Se\xf1or");
+        assert!(truncated_fields.is_empty());
+    }
+
+    #[test]
+    fn text_is_transliterated_to_ascii_when_asked() {
+        let (output, _) = write_tag("Café", "Björk", "Æther", true);
+
+        assert_eq!(text_field(&output, 3), b"Cafe");
+        assert_eq!(text_field(&output, 33), b"Bjork");
+        assert_eq!(text_field(&output, 63), b"AEther");
+    }
+
+    #[test]
+    fn text_outside_of_iso_8859_1_is_transliterated_and_truncated_to_30_bytes() {
+        let title = "Ελληνικά — a title that is much too long";
+        let (output, truncated_fields) = write_tag(title, "Artist", "日本", false);
+
+        let expected = encode_latin1(title);
+        assert_eq!(text_field(&output, 3), &expected[..ID3V1_TEXT_LENGTH]);
+        assert!(text_field(&output, 3).starts_with(b"???????? - a title"));
+        assert_eq!(text_field(&output, 33), b"Artist");
+        assert_eq!(text_field(&output, 63), b"??");
+        assert!(truncated_fields == [MetadataField::Title]);
+    }
+}
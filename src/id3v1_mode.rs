@@ -0,0 +1,23 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Id3v1Mode {
+    /// Do not write ID3v1 tags
+    Off,
+    /// Write values as ISO-8859-1, with other characters transliterated, truncated to fit
+    Plain,
+    /// Transliterate values to ASCII, e.g. Björk to Bjork, then truncate them to fit
+    Ascii,
+}
+
+impl Display for Id3v1Mode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Id3v1Mode::Off => f.write_str("off"),
+            Id3v1Mode::Plain => f.write_str("plain"),
+            Id3v1Mode::Ascii => f.write_str("ascii"),
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use std::{fs::File, io::Read, path::Path};
+
+use crate::error::{CleanerResult, CleanerError};
+
+// Conversion of the ID3v2.4 tags written by lofty to ID3v2.3, and padding, see https://id3.org/id3v2.3.0 and
+// https://id3.org/id3v2.4.0-changes
+
+const HEADER_LENGTH: usize = 10;
+const FRAME_HEADER_LENGTH: usize = 10;
+
+const LATIN1_ENCODING: u8 = 0;
+const UTF16_ENCODING: u8 = 1;
+const UTF16BE_ENCODING: u8 = 2;
+const UTF8_ENCODING: u8 = 3;
+
+/// Separator for multiple values in one text frame, which ID3v2.3 has no terminator for
+const VALUE_SEPARATOR: &str = "/";
+
+/// ID3v2.4 frames renamed in ID3v2.3, where the value of a date frame is cut to the year
+const RENAMED_FRAMES: [(&str, &str); 3] = [("TDRC", "TYER"), ("TDOR", "TORY"), ("TIPL", "IPLS")];
+
+/// ID3v2.4 frames that ID3v2.3 has no equivalent for, including the sort order frames
+const DROPPED_FRAMES: [&str; 10] = ["TDEN", "TDRL", "TDTG", "TMOO", "TPRO", "TSST", "TMCL", "TSOA", "TSOP", "TSOT"];
+
+/// Convert an ID3v2.4 tag, as written by lofty, to ID3v2.3. Text is written as ISO-8859-1 where possible and otherwise
+/// as UTF-16, as ID3v2.3 has no UTF-8.
+pub fn convert_to_id3v2_3(data: &[u8]) -> CleanerResult<Vec<u8>> {
+    if data.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    // Only a tag as written by lofty is expected, without unsynchronisation, an extended header or a footer
+    if data.len() < HEADER_LENGTH || &data[0..3] != b"ID3" || data[3] != 4 || data[5] != 0 {
+        return Err(CleanerError::InvalidId3v2Tag);
+    }
+
+    let tag_end = HEADER_LENGTH + read_synchsafe(&data[6..10]).ok_or(CleanerError::InvalidId3v2Tag)?;
+    let tag = data.get(HEADER_LENGTH..tag_end).ok_or(CleanerError::InvalidId3v2Tag)?;
+
+    let mut frames = Vec::new();
+    let mut position = 0;
+    while position + FRAME_HEADER_LENGTH <= tag.len() && tag[position] != 0 {
+        let id = std::str::from_utf8(&tag[position..position + 4]).map_err(|_| CleanerError::InvalidId3v2Tag)?;
+        let size = read_synchsafe(&tag[position + 4..position + 8]).ok_or(CleanerError::InvalidId3v2Tag)?;
+        let body_start = position + FRAME_HEADER_LENGTH;
+        let body = tag.get(body_start..body_start + size).ok_or(CleanerError::InvalidId3v2Tag)?;
+
+        if let Some((id, body)) = convert_frame(id, body) {
+            frames.extend_from_slice(id.as_bytes());
+            frames.extend_from_slice(&(body.len() as u32).to_be_bytes());
+            frames.extend_from_slice(&[0, 0]);
+            frames.extend_from_slice(&body);
+        }
+        position = body_start + size;
+    }
+
+    let mut converted = Vec::with_capacity(HEADER_LENGTH + frames.len());
+    converted.extend_from_slice(&[b'I', b'D', b'3', 3, 0, 0]);
+    converted.extend_from_slice(&write_synchsafe(frames.len()));
+    converted.extend_from_slice(&frames);
+    Ok(converted)
+}
+
+/// Add padding to the end of a tag, so that it can be edited later without moving the audio data
+pub fn pad_tag(data: &mut Vec<u8>, padding: usize) -> CleanerResult<()> {
+    if data.is_empty() || padding == 0 {
+        return Ok(());
+    }
+    if data.len() < HEADER_LENGTH || &data[0..3] != b"ID3" {
+        return Err(CleanerError::InvalidId3v2Tag);
+    }
+
+    let size = read_synchsafe(&data[6..10]).ok_or(CleanerError::InvalidId3v2Tag)? + padding;
+    data[6..10].copy_from_slice(&write_synchsafe(size));
+    data.resize(data.len() + padding, 0);
+    Ok(())
+}
+
+/// Read the ID3v2 tag at the start of a file, header included, or nothing if it has none
+pub fn read_tag(path: &Path) -> CleanerResult<Vec<u8>> {
+    let mut file = File::open(path)?;
+    let mut header = [0; HEADER_LENGTH];
+    if file.read_exact(&mut header).is_err() || &header[0..3] != b"ID3" {
+        return Ok(Vec::new());
+    }
+
+    let size = read_synchsafe(&header[6..10]).ok_or(CleanerError::InvalidId3v2Tag)?;
+    let mut data = header.to_vec();
+    file.take(size as u64).read_to_end(&mut data)?;
+    Ok(data)
+}
+
+/// Values of a text frame, of which lofty only reads the first, separated by terminators in ID3v2.4 and by slashes in
+/// ID3v2.3, as written by convert_to_id3v2_3. Slashes in the names of ID3v2.3 tags, e.g. AC/DC, are separators too.
+/// Frames of tags or frames with flags that change how they are stored, e.g. unsynchronisation, are not read.
+pub fn read_text_values(data: &[u8], frame_id: &str) -> Vec<String> {
+    if data.len() < HEADER_LENGTH || &data[0..3] != b"ID3" || !matches!(data[3], 3 | 4) || data[5] != 0 {
+        return Vec::new();
+    }
+
+    let tag = match read_synchsafe(&data[6..10]).and_then(|size| data.get(HEADER_LENGTH..HEADER_LENGTH + size)) {
+        Some(tag) => tag,
+        None => return Vec::new()
+    };
+
+    let mut position = 0;
+    while position + FRAME_HEADER_LENGTH <= tag.len() && tag[position] != 0 {
+        // Frame sizes are only synchsafe from ID3v2.4
+        let size_bytes = &tag[position + 4..position + 8];
+        let size = match data[3] {
+            4 => read_synchsafe(size_bytes),
+            _ => Some(size_bytes.iter().fold(0, |size, b| (size << 8) | *b as usize))
+        };
+        let size = match size {
+            Some(size) => size,
+            None => return Vec::new()
+        };
+        let body_start = position + FRAME_HEADER_LENGTH;
+
+        if &tag[position..position + 4] == frame_id.as_bytes() && tag[position + 9] == 0 {
+            let values = match tag.get(body_start..body_start + size).and_then(|body| body.split_first()) {
+                Some((&encoding, content)) => split_values(encoding, content),
+                None => Vec::new()
+            };
+            return match data[3] {
+                4 => values,
+                _ => values.iter().flat_map(|v| v.split(VALUE_SEPARATOR)).map(|v| v.to_string()).collect()
+            };
+        }
+        position = body_start + size;
+    }
+
+    Vec::new()
+}
+
+/// Convert the ID and body of a frame, or None if it has to be dropped
+fn convert_frame(id: &str, body: &[u8]) -> Option<(String, Vec<u8>)> {
+    if DROPPED_FRAMES.contains(&id) {
+        return None;
+    }
+
+    let renamed = RENAMED_FRAMES.iter().find(|(from, _)| *from == id).map(|(_, to)| *to);
+    let (&encoding, content) = match body.split_first() {
+        Some(split) => split,
+        None => return Some((id.to_string(), body.to_vec()))
+    };
+
+    let body = match id {
+        "TXXX" => {
+            let (description, value) = read_terminated(encoding, content);
+            encode_strings(&[description, join_values(encoding, value)], &[])
+        },
+        "WXXX" => {
+            let (description, url) = read_terminated(encoding, content);
+            encode_strings(&[description], url)
+        },
+        "COMM" | "USLT" if content.len() >= 3 => {
+            let (description, text) = read_terminated(encoding, &content[3..]);
+            let mut body = encode_strings(&[description, decode(encoding, text)], &[]);
+            body.splice(1..1, content[0..3].iter().copied());
+            body
+        },
+        // Roles and people, in pairs, are kept as separate strings, as in ID3v2.4
+        "TIPL" => encode_strings(&split_values(encoding, content), &[]),
+        "APIC" => {
+            let mime_end = content.iter().position(|b| *b == 0)?;
+            let (description, picture) = read_terminated(encoding, content.get(mime_end + 2..)?);
+            let mut body = encode_strings(&[description], picture);
+            body.splice(1..1, content[0..mime_end + 2].iter().copied());
+            body
+        },
+        _ if id.starts_with('T') => {
+            let value = join_values(encoding, content);
+            let value = match renamed {
+                Some(_) if id.starts_with("TD") => value.chars().take(4).collect(),
+                _ => value
+            };
+            encode_strings(&[value], &[])
+        },
+        _ => body.to_vec()
+    };
+
+    Some((renamed.unwrap_or(id).to_string(), body))
+}
+
+/// Encode strings, all with the same encoding, each but the last terminated, followed by unencoded data
+fn encode_strings(strings: &[String], data: &[u8]) -> Vec<u8> {
+    let is_latin1 = strings.iter().all(|s| s.chars().all(|c| (c as u32) < 0x100));
+    let mut body = vec![if is_latin1 { LATIN1_ENCODING } else { UTF16_ENCODING }];
+
+    for (i, s) in strings.iter().enumerate() {
+        if is_latin1 {
+            body.extend(s.chars().map(|c| c as u8));
+        } else {
+            body.extend_from_slice(&[0xff, 0xfe]);
+            body.extend(s.encode_utf16().flat_map(|u| u.to_le_bytes()));
+        }
+
+        let is_last = i == strings.len() - 1;
+        if !is_last || !data.is_empty() {
+            body.extend_from_slice(if is_latin1 { &[0][..] } else { &[0, 0][..] });
+        }
+    }
+
+    body.extend_from_slice(data);
+    body
+}
+
+/// Decode a terminated string, returning it and the bytes after its terminator
+fn read_terminated(encoding: u8, data: &[u8]) -> (String, &[u8]) {
+    let end = match encoding {
+        UTF16_ENCODING | UTF16BE_ENCODING => data.chunks(2).position(|c| c == [0, 0]).map(|i| (i * 2, i * 2 + 2)),
+        _ => data.iter().position(|b| *b == 0).map(|i| (i, i + 1))
+    };
+
+    match end {
+        Some((end, next)) => (decode(encoding, &data[..end]), &data[next.min(data.len())..]),
+        None => (decode(encoding, data), &[])
+    }
+}
+
+/// Decode the values of a text frame, which ID3v2.4 separates with terminators, joined with a separator
+fn join_values(encoding: u8, data: &[u8]) -> String {
+    split_values(encoding, data).into_iter().filter(|v| !v.is_empty()).collect::<Vec<String>>().join(VALUE_SEPARATOR)
+}
+
+/// Decode the values of a text frame, separated by terminators
+fn split_values(encoding: u8, mut data: &[u8]) -> Vec<String> {
+    let mut values = Vec::new();
+    while !data.is_empty() {
+        let (value, rest) = read_terminated(encoding, data);
+        values.push(value);
+        data = rest;
+    }
+
+    values
+}
+
+fn decode(encoding: u8, data: &[u8]) -> String {
+    match encoding {
+        LATIN1_ENCODING => data.iter().map(|b| *b as char).collect(),
+        UTF16_ENCODING | UTF16BE_ENCODING => {
+            let little_endian = data.starts_with(&[0xff, 0xfe]);
+            let data = if data.starts_with(&[0xff, 0xfe]) || data.starts_with(&[0xfe, 0xff]) { &data[2..] } else { data };
+            let units = data
+                .chunks_exact(2)
+                .map(|c| if little_endian { u16::from_le_bytes([c[0], c[1]]) } else { u16::from_be_bytes([c[0], c[1]]) })
+                .collect::<Vec<u16>>();
+            String::from_utf16_lossy(&units)
+        },
+        UTF8_ENCODING => String::from_utf8_lossy(data).to_string(),
+        _ => String::new()
+    }
+}
+
+/// A 28 bit integer stored in 4 bytes of 7 bits each
+pub fn read_synchsafe(bytes: &[u8]) -> Option<usize> {
+    if bytes.iter().any(|b| b & 0x80 != 0) {
+        return None;
+    }
+
+    Some(bytes.iter().fold(0, |size, b| (size << 7) | *b as usize))
+}
+
+fn write_synchsafe(size: usize) -> [u8; 4] {
+    [(size >> 21) as u8 & 0x7f, (size >> 14) as u8 & 0x7f, (size >> 7) as u8 & 0x7f, size as u8 & 0x7f]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An ID3v2.4 tag of text frames, each given as an encoding and its values, separated by terminators
+    fn build_tag(frames: &[(&str, u8, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (id, encoding, content) in frames {
+            body.extend_from_slice(id.as_bytes());
+            body.extend_from_slice(&write_synchsafe(content.len() + 1));
+            body.extend_from_slice(&[0, 0, *encoding]);
+            body.extend_from_slice(content);
+        }
+
+        let mut data = vec![b'I', b'D', b'3', 4, 0, 0];
+        data.extend_from_slice(&write_synchsafe(body.len()));
+        data.extend_from_slice(&body);
+        data
+    }
+
+    /// The body of the first frame of an ID3v2.3 tag with the given ID
+    fn find_frame<'a>(data: &'a [u8], frame_id: &str) -> Option<&'a [u8]> {
+        let mut position = HEADER_LENGTH;
+        while position + FRAME_HEADER_LENGTH <= data.len() && data[position] != 0 {
+            let size = u32::from_be_bytes(data[position + 4..position + 8].try_into().ok()?) as usize;
+            let body = data.get(position + FRAME_HEADER_LENGTH..position + FRAME_HEADER_LENGTH + size)?;
+            if &data[position..position + 4] == frame_id.as_bytes() {
+                return Some(body);
+            }
+            position += FRAME_HEADER_LENGTH + size;
+        }
+
+        None
+    }
+
+    #[test]
+    fn read_text_values_of_id3v2_4_frames() {
+        let data = build_tag(&[
+            ("TIT2", UTF8_ENCODING, b"Title"),
+            ("TPE1", UTF8_ENCODING, "Artist\0Björk".as_bytes()),
+            ("TCON", LATIN1_ENCODING, b"Rock\0Pop\0"),
+        ]);
+
+        assert_eq!(read_text_values(&data, "TPE1"), vec!["Artist", "Björk"]);
+        assert_eq!(read_text_values(&data, "TCON"), vec!["Rock", "Pop"]);
+        assert_eq!(read_text_values(&data, "TIT2"), vec!["Title"]);
+        assert!(read_text_values(&data, "TALB").is_empty());
+    }
+
+    #[test]
+    fn convert_to_id3v2_3_separates_values_with_slashes() {
+        let data = build_tag(&[
+            ("TPE1", UTF8_ENCODING, b"Artist\0Other"),
+            ("TCON", UTF8_ENCODING, "Rock\0Électronique".as_bytes()),
+        ]);
+        let converted = convert_to_id3v2_3(&data).unwrap();
+
+        assert_eq!(converted[3], 3);
+        assert_eq!(find_frame(&converted, "TPE1"), Some(&b"\0Artist/Other"[..]));
+        assert_eq!(find_frame(&converted, "TCON"), Some(&b"\0Rock/\xc9lectronique"[..]));
+    }
+
+    #[test]
+    fn read_text_values_splits_id3v2_3_values_at_slashes() {
+        let data = build_tag(&[
+            ("TIT2", UTF8_ENCODING, b"Title"),
+            ("TPE1", UTF8_ENCODING, "Artist\0Björk".as_bytes()),
+            ("TCON", UTF8_ENCODING, b"Rock\0Pop"),
+        ]);
+        let converted = convert_to_id3v2_3(&data).unwrap();
+
+        assert_eq!(read_text_values(&converted, "TPE1"), vec!["Artist", "Björk"]);
+        assert_eq!(read_text_values(&converted, "TCON"), vec!["Rock", "Pop"]);
+        assert_eq!(read_text_values(&converted, "TIT2"), vec!["Title"]);
+    }
+}
@@ -0,0 +1,22 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Id3v2Version {
+    /// ID3v2.3, for older players, e.g. car stereos
+    #[value(name = "2.3")]
+    V3,
+    /// ID3v2.4
+    #[value(name = "2.4")]
+    V4,
+}
+
+impl Display for Id3v2Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Id3v2Version::V3 => f.write_str("2.3"),
+            Id3v2Version::V4 => f.write_str("2.4"),
+        }
+    }
+}
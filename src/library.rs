@@ -0,0 +1,237 @@
+use std::{path::{PathBuf, Path}, collections::{HashMap, HashSet}, fs, time::UNIX_EPOCH};
+
+use lofty::{Accessor, TaggedFileExt};
+use walkdir::WalkDir;
+
+use crate::{decoder::hash_audio_stream, error::CleanerResult, fingerprint::{Fingerprint, calculate_fingerprint}, tagger::get_tagged_file};
+
+/// Name of the file, in the root of the library, used to store the fingerprints of the tracks in the library
+const FINGERPRINT_INDEX_NAME: &str = ".cleaner-fingerprints";
+
+/// Name of the file, in the root of the library, used to store the audio stream hashes of the tracks in the library
+const AUDIO_HASH_INDEX_NAME: &str = ".cleaner-hashes";
+
+/// The output library, tracking the tracks already present and those written during the current run
+pub struct Library {
+    path: PathBuf,
+    audio_hashes: Option<HashMap<String, PathBuf>>,
+    fingerprints: Option<Vec<LibraryFingerprint>>,
+}
+
+pub struct LibraryFingerprint {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub fingerprint: Fingerprint,
+}
+
+impl Library {
+
+    pub fn new(path: PathBuf) -> Library {
+        Library {
+            path,
+            audio_hashes: None,
+            fingerprints: None
+        }
+    }
+
+    /// Find the track in the library that best matches the given fingerprint, other than the track at the given path
+    pub fn find_similar(&mut self, fingerprint: &Fingerprint, exclude_path: &PathBuf) -> Option<&LibraryFingerprint> {
+        self.get_fingerprints()
+            .iter()
+            .filter(|f| &f.path != exclude_path && f.path.exists())
+            .filter(|f| fingerprint.matches(&f.fingerprint))
+            .max_by(|a, b| fingerprint.similarity(&a.fingerprint).total_cmp(&fingerprint.similarity(&b.fingerprint)))
+    }
+
+    pub fn add_fingerprint(&mut self, path: &PathBuf, title: Option<&str>, fingerprint: Fingerprint) {
+        let fingerprints = self.get_fingerprints();
+        fingerprints.retain(|f| &f.path != path);
+        fingerprints.push(LibraryFingerprint {
+            path: path.to_owned(),
+            title: title.map(|s| s.to_string()),
+            fingerprint
+        });
+    }
+
+    /// Write the fingerprint and audio hash indexes, if they were used during this run
+    pub fn save(&self) -> CleanerResult<()> {
+        if let Some(audio_hashes) = &self.audio_hashes {
+            let index = audio_hashes
+                .iter()
+                .filter_map(|(audio_hash, p)| {
+                    let (size, modified) = get_file_version(p)?;
+                    let path = p.strip_prefix(&self.path).ok()?.to_str()?;
+                    Some(format!("{}\t{}\t{}\t{}\n", path, size, modified, audio_hash))
+                })
+                .collect::<String>();
+            fs::write(self.path.join(AUDIO_HASH_INDEX_NAME), index)?;
+        }
+
+        if let Some(fingerprints) = &self.fingerprints {
+            let index = fingerprints
+                .iter()
+                .filter_map(|f| {
+                    let path = f.path.strip_prefix(&self.path).ok()?.to_str()?;
+                    Some(format!("{}\t{}\t{}\n", path, f.title.as_deref().unwrap_or_default().replace('\t', " "), f.fingerprint.to_text()))
+                })
+                .collect::<String>();
+            fs::write(self.path.join(FINGERPRINT_INDEX_NAME), index)?;
+        }
+
+        Ok(())
+    }
+
+    /// Find an existing track with the same audio stream hash
+    pub fn find_duplicate(&mut self, audio_hash: &str) -> Option<&PathBuf> {
+        self.get_audio_hashes()
+            .get(audio_hash)
+            .filter(|p| p.exists())
+    }
+
+    pub fn add_track(&mut self, audio_hash: &str, path: &PathBuf) {
+        self.get_audio_hashes()
+            .insert(audio_hash.to_string(), path.to_owned());
+    }
+
+    /// The existing library is only scanned the first time it is needed, and only tracks missing from the audio hash
+    /// index or changed since they were indexed are hashed, since hashing every track is slow
+    fn get_audio_hashes(&mut self) -> &mut HashMap<String, PathBuf> {
+        let path = &self.path;
+        self.audio_hashes.get_or_insert_with(|| scan_audio_hashes(path))
+    }
+
+    /// The fingerprint index is loaded the first time it is needed, and any tracks in the library that are missing from
+    /// the index are fingerprinted
+    fn get_fingerprints(&mut self) -> &mut Vec<LibraryFingerprint> {
+        let path = &self.path;
+        self.fingerprints.get_or_insert_with(|| {
+            let mut fingerprints = read_fingerprint_index(path);
+            let indexed = fingerprints
+                .iter()
+                .map(|f| f.path.to_owned())
+                .collect::<HashSet<PathBuf>>();
+
+            fingerprints.extend(get_library_audio_files(path)
+                .filter(|p| !indexed.contains(p))
+                .filter_map(|p| {
+                    let fingerprint = calculate_fingerprint(&p).ok()?;
+                    let title = get_tagged_file(&p)
+                        .ok()
+                        .and_then(|f| f.primary_tag().and_then(|t| t.title()).map(|s| s.trim().to_string()));
+                    Some(LibraryFingerprint { path: p, title, fingerprint })
+                }));
+
+            fingerprints
+        })
+    }
+}
+
+fn read_fingerprint_index(path: &Path) -> Vec<LibraryFingerprint> {
+    let index = fs::read_to_string(path.join(FINGERPRINT_INDEX_NAME)).unwrap_or_default();
+    index
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let track_path = path.join(fields.next()?);
+            let title = fields.next().filter(|s| !s.is_empty()).map(|s| s.to_string());
+            let fingerprint = Fingerprint::from_text(fields.next()?)?;
+            Some(LibraryFingerprint { path: track_path, title, fingerprint })
+        })
+        .filter(|f| f.path.exists())
+        .collect()
+}
+
+fn get_library_audio_files(path: &Path) -> impl Iterator<Item = PathBuf> {
+    WalkDir::new(path)
+        .min_depth(1)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| !e.file_type().is_dir())
+        .filter(|e| matches!(e.path().extension().and_then(|e| e.to_str()), Some("mp3" | "flac")))
+        .map(|e| e.into_path())
+}
+
+/// Indexed audio hashes, with the size and modification time of each track when it was hashed, by track path
+fn read_audio_hash_index(path: &Path) -> HashMap<PathBuf, ((u64, u128), String)> {
+    let index = fs::read_to_string(path.join(AUDIO_HASH_INDEX_NAME)).unwrap_or_default();
+    index
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let track_path = path.join(fields.next()?);
+            let size = fields.next()?.parse().ok()?;
+            let modified = fields.next()?.parse().ok()?;
+            let audio_hash = fields.next()?.to_string();
+            Some((track_path, ((size, modified), audio_hash)))
+        })
+        .collect()
+}
+
+fn scan_audio_hashes(path: &Path) -> HashMap<String, PathBuf> {
+    let index = read_audio_hash_index(path);
+    get_library_audio_files(path)
+        .filter_map(|file_path| {
+            let indexed_hash = index
+                .get(&file_path)
+                .filter(|(version, _)| get_file_version(&file_path).as_ref() == Some(version))
+                .map(|(_, audio_hash)| audio_hash.to_owned());
+            let audio_hash = match indexed_hash {
+                Some(audio_hash) => audio_hash,
+                None => hash_audio_stream(&file_path).ok()?
+            };
+            Some((audio_hash, file_path))
+        })
+        .collect()
+}
+
+/// Size and modification time, in nanoseconds since the epoch, of a file, which change when the file is rewritten
+fn get_file_version(path: &Path) -> Option<(u64, u128)> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_nanos();
+    Some((metadata.len(), modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::Builder;
+
+    use super::*;
+
+    // MPEG-1 layer III, 128kbps, 44.1kHz, joint stereo
+    const FRAME_HEADER: [u8; 4] = [0xff, 0xfb, 0x90, 0x40];
+    const FRAME_LENGTH: usize = 417;
+
+    fn build_mp3(frame_count: usize) -> Vec<u8> {
+        let mut frame = vec![0u8; FRAME_LENGTH];
+        frame[..4].copy_from_slice(&FRAME_HEADER);
+        frame.repeat(frame_count)
+    }
+
+    #[test]
+    fn audio_hashes_are_read_from_the_index_until_the_track_changes() {
+        let dir = Builder::new().prefix("cleaner").tempdir().unwrap();
+        let track_path = dir.path().join("01 Title.mp3");
+        fs::write(&track_path, build_mp3(20)).unwrap();
+        let audio_hash = hash_audio_stream(&track_path).unwrap();
+
+        let mut library = Library::new(dir.path().to_path_buf());
+        assert_eq!(library.find_duplicate(&audio_hash), Some(&track_path));
+        library.save().unwrap();
+
+        // A hash only found in the index shows that the track was not hashed again
+        let index_path = dir.path().join(AUDIO_HASH_INDEX_NAME);
+        let index = fs::read_to_string(&index_path).unwrap();
+        assert!(index.starts_with("01 Title.mp3\t"));
+        fs::write(&index_path, index.replace(&audio_hash, "indexed")).unwrap();
+
+        let mut library = Library::new(dir.path().to_path_buf());
+        assert_eq!(library.find_duplicate("indexed"), Some(&track_path));
+
+        fs::write(&track_path, build_mp3(30)).unwrap();
+        let changed_audio_hash = hash_audio_stream(&track_path).unwrap();
+
+        let mut library = Library::new(dir.path().to_path_buf());
+        assert_eq!(library.find_duplicate("indexed"), None);
+        assert_eq!(library.find_duplicate(&changed_audio_hash), Some(&track_path));
+    }
+}
@@ -0,0 +1,307 @@
+use std::{path::{PathBuf, Path}, fs::OpenOptions, io::Write, collections::{BTreeMap, BTreeSet}, fmt};
+
+use colored::Colorize;
+use serde_json::json;
+
+use crate::{error::CleanerResult, options::Options, media_files::MediaFiles, media_file::MediaFile, audio_file::AudioFile, image_file::ImageFile, art::{get_cover_art_from_file, get_cover_art_from_tag}, cleaner::{get_track_file_name, get_max_track_num_length, get_album_directory, sort_tracks, has_several_discs}, metadata_field::MetadataField, metadata_source::MetadataSource, unreadable_file::UnreadableFile, explain::get_audio_properties};
+
+/// What a problem found in the library is about
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum Check {
+    MissingYear,
+    MissingGenre,
+    MissingCover,
+    SmallCover,
+    InconsistentAlbumArtist,
+    TrackGap,
+    DuplicateTrack,
+    FileName,
+    DirectoryName,
+    UnreadableFile,
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Check::MissingYear => write!(f, "missing-year"),
+            Check::MissingGenre => write!(f, "missing-genre"),
+            Check::MissingCover => write!(f, "missing-cover"),
+            Check::SmallCover => write!(f, "small-cover"),
+            Check::InconsistentAlbumArtist => write!(f, "inconsistent-album-artist"),
+            Check::TrackGap => write!(f, "track-gap"),
+            Check::DuplicateTrack => write!(f, "duplicate-track"),
+            Check::FileName => write!(f, "file-name"),
+            Check::DirectoryName => write!(f, "directory-name"),
+            Check::UnreadableFile => write!(f, "unreadable-file"),
+        }
+    }
+}
+
+/// A problem with an album directory or one of its tracks
+pub struct Problem {
+    pub path: PathBuf,
+    pub check: Check,
+    pub message: String,
+}
+
+/// Check a library, returning the number of problems found, where a path that is not a directory counts as one
+pub fn process_lint(path: &Path, options: &Options) -> usize {
+    println!("Checking files in {}...\n", path.to_string_lossy().bright_yellow().bold());
+
+    if !path.is_dir() {
+        println!("{} {}\n", "ERROR".bright_red().bold(), "Not a valid directory".to_string().red());
+        return 1
+    }
+
+    let problem_count = lint_files(path, options);
+
+    if problem_count > 0 {
+        println!("Found {} {}.", problem_count.to_string().bright_red().bold(), format_problems(problem_count).to_lowercase());
+    } else {
+        println!("No problems found.");
+    }
+
+    problem_count
+}
+
+/// Check every album directory of a library, without writing to it, returning the number of problems found
+fn lint_files(root_path: &Path, options: &Options) -> usize {
+    let files = MediaFiles::new(root_path.into(), options, None);
+
+    let audio_file_map = files.get_audio_file_map();
+    let image_file_map = files.get_image_file_map();
+    let unreadable_file_map = files.get_unreadable_file_map();
+
+    // Directories with only unreadable audio files are checked too
+    let source_paths = audio_file_map
+        .keys()
+        .chain(unreadable_file_map.keys())
+        .collect::<BTreeSet<&PathBuf>>();
+
+    let mut problem_count = 0;
+    for source_path in source_paths {
+        // Album directories are keyed relative to the root, and reported in full like the tracks
+        let album_path = root_path.join(source_path);
+        let mut problems = unreadable_file_map
+            .get(source_path)
+            .map(|unreadable_files| lint_unreadable_files(unreadable_files))
+            .unwrap_or_default();
+        if let Some(audio_files) = audio_file_map.get(source_path) {
+            problems.extend(lint_album(&album_path, image_file_map.get(source_path), audio_files, options.min_cover_size));
+        }
+        print_problems(source_path, &album_path, &problems);
+
+        if let Some(lint_path) = &options.lint_path {
+            if let Err(err) = write_problems(lint_path, &problems, audio_file_map.get(source_path)) {
+                println!("  {} {}", "ERROR".bright_red().bold(), err.to_string().red());
+            }
+        }
+
+        problem_count += problems.len();
+    }
+
+    problem_count
+}
+
+fn lint_album(album_path: &Path, image_files: Option<&Vec<&ImageFile>>, audio_files: &[&AudioFile], min_cover_size: u32) -> Vec<Problem> {
+    let sorted_audio_files = sort_tracks(audio_files);
+
+    let mut problems = Vec::new();
+    let mut add_problem = |path: &Path, check: Check, message: String| problems.push(Problem { path: path.to_owned(), check, message });
+
+    match image_files.and_then(|image_files| get_cover_art_from_file(image_files, &sorted_audio_files)).or_else(|| get_cover_art_from_tag(&sorted_audio_files)) {
+        Some(image) if image.width().min(image.height()) < min_cover_size => {
+            add_problem(album_path, Check::SmallCover, format!("cover is {}x{}, smaller than {}px", image.width(), image.height(), min_cover_size));
+        },
+        Some(_) => {},
+        None => add_problem(album_path, Check::MissingCover, "no cover image or embedded cover art".to_string()),
+    }
+
+    let album_artists = sorted_audio_files
+        .iter()
+        .map(|f| f.get_meta().album_artist_name().unwrap_or("-"))
+        .collect::<BTreeSet<&str>>();
+    if album_artists.len() > 1 {
+        let album_artists = album_artists.into_iter().collect::<Vec<&str>>().join(", ");
+        add_problem(album_path, Check::InconsistentAlbumArtist, format!("album artist differs between tracks: {}", album_artists));
+    }
+
+    if let Some(meta) = sorted_audio_files.first().map(|f| f.get_meta()) {
+        let expected = get_album_directory(meta);
+        if !is_album_directory(album_path, &expected) {
+            add_problem(album_path, Check::DirectoryName, format!("should be in '{}'", expected.to_string_lossy()));
+        }
+    }
+
+    let mut tracks_by_disc = BTreeMap::<u32, Vec<u32>>::new();
+    for audio_file in &sorted_audio_files {
+        if let Some(track_number) = audio_file.get_meta().track_number() {
+            tracks_by_disc.entry(audio_file.get_meta().disc_number().unwrap_or(1)).or_default().push(track_number);
+        }
+    }
+    for (disc_number, track_numbers) in &tracks_by_disc {
+        let disc = if tracks_by_disc.len() > 1 { format!("disc {} ", disc_number) } else { String::new() };
+
+        let max_track_number = track_numbers.iter().max().copied().unwrap_or_default();
+        let missing = (1..=max_track_number)
+            .filter(|n| !track_numbers.contains(n))
+            .map(|n| n.to_string())
+            .collect::<Vec<String>>();
+        if !missing.is_empty() {
+            add_problem(album_path, Check::TrackGap, format!("{}missing track {}", disc, missing.join(", ")));
+        }
+
+        let repeated = track_numbers
+            .iter()
+            .filter(|n| track_numbers.iter().filter(|m| m == n).count() > 1)
+            .collect::<BTreeSet<&u32>>();
+        for track_number in repeated {
+            add_problem(album_path, Check::DuplicateTrack, format!("{}track {} appears more than once", disc, track_number));
+        }
+    }
+
+    let several_discs = has_several_discs(&sorted_audio_files);
+    let track_width = get_max_track_num_length(&sorted_audio_files);
+    for audio_file in &sorted_audio_files {
+        let meta = audio_file.get_meta();
+
+        // Values from the path or file name are what the cleaner would fill in, only tags count here
+        if !meta.provenance().has_value_from(MetadataField::Year, MetadataSource::Tag) {
+            add_problem(audio_file.path(), Check::MissingYear, "no year tag".to_string());
+        }
+        if !meta.provenance().has_value_from(MetadataField::Genre, MetadataSource::Tag) {
+            add_problem(audio_file.path(), Check::MissingGenre, "no genre tag".to_string());
+        }
+
+        let file_name = audio_file.path().file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        match get_track_file_name(meta, track_width, several_discs) {
+            Some(expected) if expected != file_name => add_problem(audio_file.path(), Check::FileName, format!("should be named '{}'", expected)),
+            Some(_) => {},
+            None => add_problem(audio_file.path(), Check::FileName, "no track number or title to name the file by".to_string()),
+        }
+    }
+
+    problems
+}
+
+fn lint_unreadable_files(unreadable_files: &[&UnreadableFile]) -> Vec<Problem> {
+    unreadable_files
+        .iter()
+        .map(|f| Problem { path: f.path().to_owned(), check: Check::UnreadableFile, message: f.error().to_string() })
+        .collect()
+}
+
+/// Whether an album directory is in the artist and album directories the cleaner would write it to, allowing for the
+/// suffix of a separate edition, e.g. "Album [FLAC 24-96]"
+fn is_album_directory(album_path: &Path, expected: &Path) -> bool {
+    let album_name = album_path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let artist_name = album_path.parent().and_then(|p| p.file_name());
+    let expected_album_name = expected.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let expected_artist_name = expected.parent().and_then(|p| p.file_name());
+
+    let is_album = album_name == expected_album_name || album_name
+        .strip_prefix(&expected_album_name)
+        .is_some_and(|suffix| suffix.starts_with(" [") && suffix.ends_with(']'));
+
+    is_album && artist_name == expected_artist_name
+}
+
+fn print_problems(source_path: &Path, album_path: &Path, problems: &[Problem]) {
+    let album_name = source_path.to_string_lossy();
+    if problems.is_empty() {
+        println!("  Album {} {}", album_name.bright_cyan().bold(), "OK".bright_green().bold());
+        return;
+    }
+
+    println!("  Album {} {} {}", album_name.bright_cyan().bold(), problems.len().to_string().bright_red().bold(), format_problems(problems.len()).bright_red().bold());
+    for problem in problems {
+        print!("        {:25} ", problem.check.to_string().yellow());
+        if problem.path != album_path {
+            print!("{} ", problem.path.file_name().map(|s| s.to_string_lossy()).unwrap_or_default().white());
+        }
+        println!("{}", problem.message.dimmed());
+    }
+}
+
+fn format_problems(count: usize) -> &'static str {
+    if count == 1 { "PROBLEM" } else { "PROBLEMS" }
+}
+
+/// Append problems, each as a line of JSON, to a file, with the audio properties of the track for problems of a track
+fn write_problems(path: &PathBuf, problems: &[Problem], audio_files: Option<&Vec<&AudioFile>>) -> CleanerResult<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    for problem in problems {
+        let mut line = json!({
+            "path": problem.path.to_string_lossy(),
+            "check": problem.check.to_string(),
+            "message": problem.message
+        });
+        if let Some(audio_file) = audio_files.and_then(|files| files.iter().find(|f| f.path() == &problem.path)) {
+            line["audio"] = get_audio_properties(audio_file.get_meta());
+        }
+        writeln!(file, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process::ExitCode};
+
+    use image::RgbImage;
+    use lofty::{Accessor, ItemKey, Tag, TagExt, TagType};
+    use tempfile::Builder;
+
+    use crate::get_exit_code;
+
+    use super::*;
+
+    // MPEG-1 layer III, 128kbps, 44.1kHz, joint stereo
+    const FRAME_HEADER: [u8; 4] = [0xff, 0xfb, 0x90, 0x40];
+    const FRAME_LENGTH: usize = 417;
+
+    /// An MP3 file of silent frames, tagged as the given track of an album by Artist
+    fn write_track(path: &Path, track_number: u32, title: &str) {
+        let mut frame = vec![0u8; FRAME_LENGTH];
+        frame[..4].copy_from_slice(&FRAME_HEADER);
+        fs::write(path, frame.repeat(20)).unwrap();
+
+        let mut tag = Tag::new(TagType::ID3v2);
+        tag.set_artist("Artist".to_string());
+        tag.insert_text(ItemKey::AlbumArtist, "Artist".to_string());
+        tag.set_album("Album".to_string());
+        tag.set_year(1999);
+        tag.insert_text(ItemKey::RecordingDate, "1999".to_string());
+        tag.set_genre("Rock".to_string());
+        tag.set_track(track_number);
+        tag.set_title(title.to_string());
+        tag.save_to_path(path).unwrap();
+    }
+
+    fn lint_exit_code(path: &Path) -> ExitCode {
+        get_exit_code(process_lint(path, &Options::default()) == 0)
+    }
+
+    #[test]
+    fn exit_code_is_zero_only_for_a_clean_library() {
+        let dir = Builder::new().prefix("cleaner").tempdir().unwrap();
+        let album_path = dir.path().join("Artist").join("Album");
+        fs::create_dir_all(&album_path).unwrap();
+        RgbImage::new(500, 500).save(album_path.join("cover.jpg")).unwrap();
+        write_track(&album_path.join("01 First.mp3"), 1, "First");
+        write_track(&album_path.join("02 Second.mp3"), 2, "Second");
+
+        assert_eq!(process_lint(dir.path(), &Options::default()), 0);
+        assert_eq!(lint_exit_code(dir.path()), ExitCode::SUCCESS);
+
+        // A missing track and a badly named file
+        fs::rename(album_path.join("01 First.mp3"), album_path.join("First.mp3")).unwrap();
+        fs::remove_file(album_path.join("02 Second.mp3")).unwrap();
+        write_track(&album_path.join("03 Third.mp3"), 3, "Third");
+
+        assert_eq!(process_lint(dir.path(), &Options::default()), 2);
+        assert_ne!(lint_exit_code(dir.path()), ExitCode::SUCCESS);
+        assert_ne!(lint_exit_code(&dir.path().join("Missing")), ExitCode::SUCCESS);
+    }
+}
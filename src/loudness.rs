@@ -0,0 +1,275 @@
+use std::{path::PathBuf, f64::consts::PI};
+
+use lofty::{ItemKey, TaggedFileExt};
+
+use crate::{audio_file::AudioFile, decoder::decode_audio, error::CleanerResult, media_file::MediaFile};
+
+// Loudness measurement follows ITU-R BS.1770 / EBU R128, see https://tech.ebu.ch/docs/tech/tech3341.pdf
+
+const ABSOLUTE_GATE: f64 = -70.0;
+const RELATIVE_GATE: f64 = -10.0;
+
+// Blocks are 400ms long, overlapping by 75%, so are built from four 100ms sub-blocks
+const SUB_BLOCK_MILLIS: u32 = 100;
+const SUB_BLOCKS_PER_BLOCK: usize = 4;
+
+/// ReplayGain 2.0 reference level
+const REPLAY_GAIN_REFERENCE: f64 = -18.0;
+
+pub struct ReplayGain {
+    pub track_gain: f64,
+    pub track_peak: f64,
+    pub album_gain: f64,
+    pub album_peak: f64,
+}
+
+/// The gated measurement blocks and sample peak for a single track, kept so that album loudness can be computed over the
+/// blocks of every track
+struct TrackLoudness {
+    block_energies: Vec<f64>,
+    peak: f64,
+}
+
+impl ReplayGain {
+
+    pub fn track_gain_text(&self) -> String {
+        format_gain(self.track_gain)
+    }
+
+    pub fn track_peak_text(&self) -> String {
+        format_peak(self.track_peak)
+    }
+
+    pub fn album_gain_text(&self) -> String {
+        format_gain(self.album_gain)
+    }
+
+    pub fn album_peak_text(&self) -> String {
+        format_peak(self.album_peak)
+    }
+}
+
+/// Analyse the loudness of all tracks in an album, returning ReplayGain values for each track in the same order
+pub fn analyse_album(audio_files: &Vec<&AudioFile>) -> CleanerResult<Vec<ReplayGain>> {
+    let tracks = audio_files
+        .iter()
+        .map(|f| analyse_track(f.path()))
+        .collect::<CleanerResult<Vec<TrackLoudness>>>()?;
+
+    let album_blocks = tracks
+        .iter()
+        .flat_map(|t| t.block_energies.iter().copied())
+        .collect::<Vec<f64>>();
+
+    let album_gain = REPLAY_GAIN_REFERENCE - integrated_loudness(&album_blocks);
+    let album_peak = tracks
+        .iter()
+        .map(|t| t.peak)
+        .fold(0.0, f64::max);
+
+    Ok(tracks
+        .iter()
+        .map(|t| ReplayGain {
+            track_gain: REPLAY_GAIN_REFERENCE - integrated_loudness(&t.block_energies),
+            track_peak: t.peak,
+            album_gain,
+            album_peak
+        })
+        .collect())
+}
+
+/// Get the existing ReplayGain values from the tags of every track in an album, only if all tracks have complete values
+pub fn get_album_replay_gain(audio_files: &Vec<&AudioFile>) -> Option<Vec<ReplayGain>> {
+    audio_files
+        .iter()
+        .map(|f| get_replay_gain(f))
+        .collect()
+}
+
+fn get_replay_gain(audio_file: &AudioFile) -> Option<ReplayGain> {
+    let tag = audio_file.get_meta().tagged_file().primary_tag()?;
+
+    let get_value = |key: ItemKey| tag
+        .get_string(&key)
+        .map(|s| s.trim().trim_end_matches("dB").trim())
+        .and_then(|s| s.parse::<f64>().ok());
+
+    Some(ReplayGain {
+        track_gain: get_value(ItemKey::ReplayGainTrackGain)?,
+        track_peak: get_value(ItemKey::ReplayGainTrackPeak)?,
+        album_gain: get_value(ItemKey::ReplayGainAlbumGain)?,
+        album_peak: get_value(ItemKey::ReplayGainAlbumPeak)?
+    })
+}
+
+fn analyse_track(path: &PathBuf) -> CleanerResult<TrackLoudness> {
+    let mut meter = None::<LoudnessMeter>;
+
+    decode_audio(path, |sample_rate, channels, samples| {
+        meter
+            .get_or_insert_with(|| LoudnessMeter::new(sample_rate, channels))
+            .process(samples);
+    })?;
+
+    Ok(meter
+        .map(|m| m.finish())
+        .unwrap_or(TrackLoudness { block_energies: Vec::new(), peak: 0.0 }))
+}
+
+fn integrated_loudness(block_energies: &[f64]) -> f64 {
+    let absolute_gated = block_energies
+        .iter()
+        .copied()
+        .filter(|e| to_loudness(*e) > ABSOLUTE_GATE)
+        .collect::<Vec<f64>>();
+
+    if absolute_gated.is_empty() {
+        return ABSOLUTE_GATE;
+    }
+
+    let relative_gate = to_loudness(mean(&absolute_gated)) + RELATIVE_GATE;
+
+    let relative_gated = absolute_gated
+        .iter()
+        .copied()
+        .filter(|e| to_loudness(*e) > relative_gate)
+        .collect::<Vec<f64>>();
+
+    to_loudness(mean(&relative_gated))
+}
+
+fn mean(values: &[f64]) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn to_loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+fn format_gain(gain: f64) -> String {
+    format!("{:.2} dB", gain)
+}
+
+fn format_peak(peak: f64) -> String {
+    format!("{:.6}", peak)
+}
+
+struct LoudnessMeter {
+    channel_weights: Vec<f64>,
+    filters: Vec<(Biquad, Biquad)>,
+    sub_block_length: usize,
+    sub_block_position: usize,
+    sub_block_energy: f64,
+    sub_blocks: Vec<f64>,
+    peak: f64,
+}
+
+impl LoudnessMeter {
+
+    fn new(sample_rate: u32, channels: usize) -> LoudnessMeter {
+        let fs = sample_rate as f64;
+        LoudnessMeter {
+            channel_weights: (0..channels).map(|c| channel_weight(c, channels)).collect(),
+            filters: (0..channels).map(|_| (Biquad::high_shelf(fs), Biquad::high_pass(fs))).collect(),
+            sub_block_length: (sample_rate * SUB_BLOCK_MILLIS / 1000) as usize,
+            sub_block_position: 0,
+            sub_block_energy: 0.0,
+            sub_blocks: Vec::new(),
+            peak: 0.0
+        }
+    }
+
+    fn process(&mut self, samples: &[f32]) {
+        let channels = self.channel_weights.len();
+        for frame in samples.chunks_exact(channels) {
+            let mut energy = 0.0;
+            for (channel, sample) in frame.iter().enumerate() {
+                let sample = *sample as f64;
+                self.peak = self.peak.max(sample.abs());
+                let (shelf, pass) = &mut self.filters[channel];
+                let filtered = pass.process(shelf.process(sample));
+                energy += self.channel_weights[channel] * filtered * filtered;
+            }
+            self.sub_block_energy += energy;
+            self.sub_block_position += 1;
+            if self.sub_block_position == self.sub_block_length {
+                self.sub_blocks.push(self.sub_block_energy);
+                self.sub_block_energy = 0.0;
+                self.sub_block_position = 0;
+            }
+        }
+    }
+
+    fn finish(self) -> TrackLoudness {
+        let block_length = (self.sub_block_length * SUB_BLOCKS_PER_BLOCK) as f64;
+        let block_energies = self.sub_blocks
+            .windows(SUB_BLOCKS_PER_BLOCK)
+            .map(|w| w.iter().sum::<f64>() / block_length)
+            .collect();
+
+        TrackLoudness {
+            block_energies,
+            peak: self.peak
+        }
+    }
+}
+
+/// Channel weighting, assuming the usual 5.1 channel order where there are more than three channels
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        (c, _) if c <= 3 => 1.0,
+        (_, 3) => 0.0,
+        (_, 4 | 5) => 1.41,
+        _ => 1.0
+    }
+}
+
+/// Second order filter, used for the two stages of the K-weighting pre-filter
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+
+    /// Stage 1, the acoustic effects of the head
+    fn high_shelf(fs: f64) -> Biquad {
+        let f0 = 1681.974450955533;
+        let gain = 3.999843853973347;
+        let q = 0.7071752369554196;
+
+        let k = (PI * f0 / fs).tan();
+        let vh = 10f64.powf(gain / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+
+        Biquad {
+            b: [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2]
+        }
+    }
+
+    /// Stage 2, the RLB weighting curve
+    fn high_pass(fs: f64) -> Biquad {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+
+        let k = (PI * f0 / fs).tan();
+        let a0 = 1.0 + k / q + k * k;
+
+        Biquad {
+            b: [1.0, -2.0, 1.0],
+            a: [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+            z: [0.0; 2]
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
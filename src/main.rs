@@ -2,21 +2,83 @@
 mod archives;
 mod audio_file;
 mod audio_file_meta;
+mod audio_quality;
 mod error;
+mod explain;
 mod files;
+mod flac;
+mod fingerprint;
 mod cleaner;
+mod config;
+mod cue_sheet;
+mod decoder;
+mod duplicate_policy;
+mod edition_policy;
+mod editions;
+mod id3v1;
+mod id3v1_mode;
+mod id3v2;
+mod id3v2_version;
 mod image_file;
+mod library;
+mod lint;
+mod loudness;
 mod media_file;
 mod media_files;
+mod metadata_field;
+mod metadata_source;
 mod mode;
+mod mpeg;
+mod multi_value;
+mod name_patterns;
+mod options;
+mod placeholders;
+mod precedence;
+mod preview;
+mod provenance;
+mod origin;
 mod other_file;
+mod replay_gain_mode;
+mod spectral;
+mod spectrogram;
+mod spectrogram_mode;
+mod spectrum;
+mod stray_tags;
+mod tags;
+mod tag_backup;
+mod tag_fields;
+mod tag_items;
 mod tagger;
+mod tempo_key;
+mod tempo_key_mode;
+mod unreadable_file;
 
-use std::{path::{PathBuf, Path}, process::ExitCode};
+use std::{path::{PathBuf, Path}, process::ExitCode, fs};
 
 use clap::{Parser};
+use config::Config;
+use duplicate_policy::DuplicatePolicy;
+use edition_policy::EditionPolicy;
 use files::process_files;
+use id3v1_mode::Id3v1Mode;
+use id3v2_version::Id3v2Version;
+use library::Library;
+use lint::process_lint;
 use mode::Mode;
+use error::CleanerResult;
+use name_patterns::{NamePatterns, PathPatterns};
+use options::Options;
+use placeholders::Placeholders;
+use precedence::{SourcePrecedence, parse_precedence_arg, parse_default_arg};
+use metadata_field::MetadataField;
+use metadata_source::MetadataSource;
+use multi_value::ValueDelimiters;
+use replay_gain_mode::ReplayGainMode;
+use spectrogram_mode::SpectrogramMode;
+use tag_fields::TagFields;
+use tag_items::TagItems;
+use tags::{process_tags, revert_tags};
+use tempo_key_mode::TempoKeyMode;
 
 use crate::{archives::process_archives};
 
@@ -26,8 +88,9 @@ struct Cli {
     /// Directory or archive to process
     path: PathBuf,
 
-    /// Output directory
-    output: PathBuf,
+    /// Output directory, or for the tags and revert modes, the directory of backups of the original tags; not used by the
+    /// lint mode
+    output: Option<PathBuf>,
 
     /// Processing mode
     #[arg(short, long, value_enum, default_value_t = Mode::Archives)]
@@ -40,24 +103,285 @@ struct Cli {
     /// Quality factor to use when generating JPEG cover art
     #[arg(short, long, value_parser = clap::value_parser!(u8).range(1..=100), default_value_t = 95)]
     quality: u8,
+
+    /// ReplayGain analysis and tagging
+    #[arg(long, value_enum, default_value_t = ReplayGainMode::Off)]
+    replay_gain: ReplayGainMode,
+
+    /// How to handle tracks whose audio is already in the output directory, or was already written in this run
+    #[arg(long, value_enum)]
+    duplicates: Option<DuplicatePolicy>,
+
+    /// Fingerprint tracks to find the same recording in a different encoding, or under a different title, in the output directory
+    #[arg(long)]
+    fingerprint: bool,
+
+    /// How to handle the same album arriving in more than one format, or already in the output directory in another format
+    #[arg(long, value_enum)]
+    editions: Option<EditionPolicy>,
+
+    /// Analyse the spectrum of lossless tracks to find those likely transcoded from a lossy source, or upsampled
+    #[arg(long)]
+    check_lossless: bool,
+
+    /// Directory to write suspect albums to, for manual review, instead of the output directory
+    #[arg(long, requires = "check_lossless")]
+    review_dir: Option<PathBuf>,
+
+    /// Render spectrogram images for quality review, written to a "spectrograms" directory in each album directory
+    #[arg(long, value_enum)]
+    spectrograms: Option<SpectrogramMode>,
+
+    /// Directory to write spectrogram images to, instead of the album directories
+    #[arg(long, requires = "spectrograms")]
+    spectrogram_dir: Option<PathBuf>,
+
+    /// Tempo (BPM) and musical key estimation and tagging
+    #[arg(long, value_enum, default_value_t = TempoKeyMode::Off)]
+    tempo_key: TempoKeyMode,
+
+    /// Add or rebuild the Xing/Info header of MP3 files when it is missing or does not match the audio stream
+    #[arg(long)]
+    repair_vbr_header: bool,
+
+    /// ID3v2 version to write to MP3 files
+    #[arg(long, value_enum, default_value_t = Id3v2Version::V4)]
+    id3v2_version: Id3v2Version,
+
+    /// Whether and how to write an ID3v1 tag, after the ID3v2 tag, to MP3 files, where values are limited to 30 bytes
+    #[arg(long, value_enum, default_value_t = Id3v1Mode::Plain)]
+    id3v1: Id3v1Mode,
+
+    /// Bytes of padding to leave in ID3v2 tags and FLAC metadata, so that tags can be edited later without rewriting the
+    /// whole file
+    #[arg(long, default_value_t = 4096)]
+    tag_padding: usize,
+
+    /// Do not record the source archive or directory, original file name and hash, cleaner version and time of
+    /// processing in the tags, e.g. for copies to be shared
+    #[arg(long)]
+    no_origin_tags: bool,
+
+    /// Smallest width and height, in pixels, of cover art not reported as too small by the lint mode
+    #[arg(long, default_value_t = 500)]
+    min_cover_size: u32,
+
+    /// File to write the problems found by the lint mode to, as JSON lines
+    #[arg(long)]
+    lint_json: Option<PathBuf>,
+
+    /// Configuration file, instead of config.toml in the user's configuration directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Regular expression, with named captures (track, disc, artist, album, title, year), for parsing file names;
+    /// may be repeated, tried in order, and replaces the configured patterns
+    #[arg(long)]
+    filename_pattern: Vec<String>,
+
+    /// Regular expression, with named captures (disc, artist, album, year), for parsing album directory names; may be
+    /// repeated, tried in order, and replaces the configured patterns
+    #[arg(long)]
+    directory_pattern: Vec<String>,
+
+    /// Order of sources (tag, path, filename, sidecar, archive, default, prompt) for a field, e.g. album=path,tag, with
+    /// prompt only last; may be repeated, and replaces the configured order for that field
+    #[arg(long, value_parser = parse_precedence_arg)]
+    precedence: Vec<(MetadataField, Vec<MetadataSource>)>,
+
+    /// Default value for a field, used where the default source is in its order of precedence, e.g. genre=Electronic;
+    /// may be repeated
+    #[arg(long, value_parser = parse_default_arg)]
+    default: Vec<(MetadataField, String)>,
+
+    /// Regular expression for placeholder tag values, e.g. "Unknown Artist", that are treated as missing; may be repeated,
+    /// and replaces the configured patterns
+    #[arg(long)]
+    placeholder_pattern: Vec<String>,
+
+    /// Delimiter that separates several artists in one value, e.g. ";" or " feat. "; may be repeated, and replaces the
+    /// configured delimiters
+    #[arg(long)]
+    artist_delimiter: Vec<String>,
+
+    /// Delimiter that separates several genres in one value, e.g. ";" or "/"; may be repeated, and replaces the configured
+    /// delimiters
+    #[arg(long)]
+    genre_delimiter: Vec<String>,
+
+    /// Tag item to carry over from the source file, by name, e.g. composer or musicbrainz, or by key, e.g. TCOM, or * for
+    /// all items; may be repeated, and replaces the configured items
+    #[arg(long)]
+    keep_tag_item: Vec<String>,
+
+    /// Tag item not to carry over from the source file, even if kept; may be repeated, and replaces the configured items
+    #[arg(long)]
+    drop_tag_item: Vec<String>,
+
+    /// Show which pattern each file and directory name was parsed with
+    #[arg(long)]
+    show_patterns: bool,
+
+    /// Show where each metadata value of every track came from, and the values of other sources that were ignored
+    #[arg(long)]
+    explain: bool,
+
+    /// File to write the explanation of every track to, as JSON lines
+    #[arg(long)]
+    explain_json: Option<PathBuf>,
+
+    /// Show the changes to the tags of every track, and ask for each album whether to write them, skip the album or edit
+    /// its values first
+    #[arg(long)]
+    preview: bool,
 }
 
 fn main() -> ExitCode {
     let args = Cli::parse();
 
     let source_path = &args.path;
-    let output_path = &args.output;
-    let quality = args.quality;
+    let output_path = match (&args.output, args.mode) {
+        (Some(output_path), _) => output_path,
+        (None, Mode::Lint) => source_path,
+        (None, _) => {
+            println!("Output directory is required");
+            return ExitCode::from(1);
+        }
+    };
+
+    let config = match Config::load(args.config.as_ref()) {
+        Ok(config) => config,
+        Err(err) => {
+            println!("Failed to load configuration: {}", err);
+            return ExitCode::from(1);
+        }
+    };
+
+    let path_patterns = match get_path_patterns(&args, &config) {
+        Ok(path_patterns) => path_patterns,
+        Err(err) => {
+            println!("{}", err);
+            return ExitCode::from(1);
+        }
+    };
+
+    let placeholders = match Some(&args.placeholder_pattern).filter(|p| !p.is_empty()).or(config.placeholder_patterns.as_ref()) {
+        Some(patterns) => Placeholders::new(patterns),
+        None => Ok(Placeholders::default())
+    };
+    let placeholders = match placeholders {
+        Ok(placeholders) => placeholders,
+        Err(err) => {
+            println!("{}", err);
+            return ExitCode::from(1);
+        }
+    };
+
+    let artist_delimiters = Some(&args.artist_delimiter).filter(|d| !d.is_empty()).or(config.artist_delimiters.as_ref());
+    let genre_delimiters = Some(&args.genre_delimiter).filter(|d| !d.is_empty()).or(config.genre_delimiters.as_ref());
+    let delimiters = ValueDelimiters::new(artist_delimiters, genre_delimiters);
+
+    let keep_tag_items = Some(&args.keep_tag_item).filter(|i| !i.is_empty()).or(config.keep_tag_items.as_ref());
+    let drop_tag_items = Some(&args.drop_tag_item).filter(|i| !i.is_empty()).or(config.drop_tag_items.as_ref());
+    let tag_items = match keep_tag_items {
+        Some(keep_tag_items) => TagItems::new(keep_tag_items, drop_tag_items.map_or(&[], |i| i.as_slice())),
+        None => TagItems::with_default_keep(drop_tag_items.map_or(&[], |i| i.as_slice()))
+    };
+
+    // Built in, then configured, keys for each field
+    let tag_fields = TagFields::default().with(&config.tag_fields);
+
+    // Built in, then configured, then command line orders and defaults, each replacing the last by field
+    let precedence = SourcePrecedence::default()
+        .with(&config.precedence, &config.defaults)
+        .with(&args.precedence.iter().cloned().collect(), &args.default.iter().cloned().collect());
+
+    let options = Options {
+        quality: args.quality,
+        replay_gain: args.replay_gain,
+        duplicates: args.duplicates,
+        fingerprint: args.fingerprint,
+        editions: args.editions,
+        check_lossless: args.check_lossless,
+        review_path: args.review_dir,
+        spectrograms: args.spectrograms,
+        spectrogram_path: args.spectrogram_dir,
+        tempo_key: args.tempo_key,
+        repair_vbr_header: args.repair_vbr_header,
+        id3v2_version: args.id3v2_version,
+        id3v1: args.id3v1,
+        tag_padding: args.tag_padding,
+        origin_tags: !args.no_origin_tags,
+        path_patterns,
+        precedence,
+        placeholders,
+        delimiters,
+        tag_items,
+        tag_fields,
+        show_patterns: args.show_patterns,
+        explain: args.explain,
+        explain_path: args.explain_json,
+        preview: args.preview,
+        min_cover_size: args.min_cover_size,
+        lint_path: args.lint_json,
+    };
+
+    // Explanations are appended per track, so start with an empty file
+    if let Some(explain_path) = &options.explain_path {
+        if let Err(err) = fs::write(explain_path, "") {
+            println!("Failed to create explanation file: {}", err);
+            return ExitCode::from(1);
+        }
+    }
+
+    // Problems are appended per album, so start with an empty file
+    if let Some(lint_path) = &options.lint_path {
+        if let Err(err) = fs::write(lint_path, "") {
+            println!("Failed to create lint report file: {}", err);
+            return ExitCode::from(1);
+        }
+    }
+
+    let mut library = Library::new(output_path.to_owned());
 
     if !Path::new(source_path).exists() {
         println!("Path '{}' does not exist", source_path.to_string_lossy());
         return ExitCode::from(1);
     }
 
+    // Only the archives and files modes write to an output library, the others leave its index alone
     match args.mode {
-        Mode::Archives => process_archives(&source_path, &output_path, quality),
-        Mode::Files => process_files(&source_path, &output_path, quality),
+        Mode::Archives => process_archives(source_path, output_path, &options, &mut library),
+        Mode::Files => process_files(source_path, output_path, &options, &mut library),
+        Mode::Tags => return get_exit_code(process_tags(source_path, output_path, &options)),
+        Mode::Revert => return get_exit_code(revert_tags(source_path, output_path)),
+        // Problems found by the lint mode fail the run, e.g. for a scheduled check
+        Mode::Lint => return get_exit_code(process_lint(source_path, &options) == 0),
+    }
+
+    if let Err(err) = library.save() {
+        println!("Failed to save library index: {}", err);
+        return ExitCode::from(1);
     }
 
-    return ExitCode::from(0);
+    ExitCode::from(0)
+}
+
+fn get_exit_code(is_success: bool) -> ExitCode {
+    if is_success { ExitCode::from(0) } else { ExitCode::from(1) }
+}
+
+/// Patterns given on the command line take precedence over configured patterns, which take precedence over the defaults
+fn get_path_patterns(args: &Cli, config: &Config) -> CleanerResult<PathPatterns> {
+    let filename_patterns = match Some(&args.filename_pattern).filter(|p| !p.is_empty()).or(config.filename_patterns.as_ref()) {
+        Some(patterns) => NamePatterns::new(patterns)?,
+        None => NamePatterns::default_filename()
+    };
+
+    let directory_patterns = match Some(&args.directory_pattern).filter(|p| !p.is_empty()).or(config.directory_patterns.as_ref()) {
+        Some(patterns) => NamePatterns::new(patterns)?,
+        None => NamePatterns::default_directory()
+    };
+
+    PathPatterns::new(filename_patterns, directory_patterns, config.marker_patterns.as_ref())
 }
@@ -2,25 +2,27 @@
 
 use walkdir::WalkDir;
 
-use crate::{audio_file::AudioFile, image_file::ImageFile, media_file::MediaFile, other_file::OtherFile};
+use crate::{error::CleanerError, replay_gain_mode::ReplayGainMode, name_patterns::NameFields, options::Options, audio_file::AudioFile, image_file::ImageFile, media_file::MediaFile, other_file::OtherFile, unreadable_file::UnreadableFile};
 
 pub struct MediaFiles {
     path: PathBuf,
     audio_files: Vec<AudioFile>,
     image_files: Vec<ImageFile>,
-    other_files: Vec<OtherFile>
+    other_files: Vec<OtherFile>,
+    unreadable_files: Vec<UnreadableFile>
 }
 
 impl MediaFiles {
 
-    pub fn new(path: PathBuf) -> MediaFiles {
+    pub fn new(path: PathBuf, options: &Options, archive_fields: Option<&NameFields>) -> MediaFiles {
         let mut files = MediaFiles {
             path,
             audio_files: Vec::new(),
             image_files: Vec::new(),
-            other_files: Vec::new()
+            other_files: Vec::new(),
+            unreadable_files: Vec::new()
         };
-        files.scan();
+        files.scan(options, archive_fields);
         files
     }
 
@@ -32,12 +34,17 @@ pub fn get_image_file_map(&self) -> BTreeMap<PathBuf, Vec<&ImageFile>> {
         self.get_file_map(&self.image_files)
     }
 
+    /// Audio files that could not be read, which are left out of the audio files
+    pub fn get_unreadable_file_map(&self) -> BTreeMap<PathBuf, Vec<&UnreadableFile>> {
+        self.get_file_map(&self.unreadable_files)
+    }
+
     #[allow(dead_code)]
     pub fn get_other_file_map(&self) -> BTreeMap<PathBuf, Vec<&OtherFile>> {
         self.get_file_map(&self.other_files)
     }
 
-    fn scan(&mut self) {
+    fn scan(&mut self, options: &Options, archive_fields: Option<&NameFields>) {
         let walker = WalkDir::new(&self.path)
             .min_depth(1);
 
@@ -47,18 +54,25 @@ fn scan(&mut self) {
                 let ext = entry.path().extension().and_then(|e| e.to_str());
                 let file_path = entry.path().to_path_buf();
                 match ext {
-                    Some("mp3" | "flac") => self.audio_files.push(AudioFile::new(&self.path, file_path)),
+                    Some("mp3" | "flac") => match AudioFile::new(&self.path, file_path.to_owned(), options, archive_fields) {
+                        Ok(audio_file) => self.audio_files.push(audio_file),
+                        Err(err) => self.unreadable_files.push(UnreadableFile::new(file_path, err))
+                    },
+                    // Opus files are not cleaned, but asking for their gain must not be silently ignored
+                    Some("opus") if options.replay_gain != ReplayGainMode::Off => self.unreadable_files.push(UnreadableFile::new(file_path, CleanerError::UnsupportedReplayGain)),
                     Some("png" | "jpg" | "jpeg") => self.image_files.push(ImageFile::new(file_path)),
                     _ => self.other_files.push(OtherFile::new(file_path))
                 };
             };
+
+        number_tracks_across_sides(&mut self.audio_files);
     }
 
-    fn get_file_map<'a, T: MediaFile>(&'a self, files: &'a Vec<T>) -> BTreeMap<PathBuf, Vec<&T>> {
+    fn get_file_map<'a, T: MediaFile>(&'a self, files: &'a [T]) -> BTreeMap<PathBuf, Vec<&'a T>> {
         let mut map = files
             .iter()
             .fold(
-                BTreeMap::<PathBuf, Vec<&T>>::new(),
+                BTreeMap::<PathBuf, Vec<&'a T>>::new(),
                 |mut acc, file| {
                     let parent = file
                         .path()
@@ -67,7 +81,7 @@ fn get_file_map<'a, T: MediaFile>(&'a self, files: &'a Vec<T>) -> BTreeMap<PathB
                         .to_path_buf();
                     let key = self.relative_path(parent);
                     acc.entry(key)
-                        .or_insert_with(|| Vec::new())
+                        .or_insert_with(Vec::new)
                         .push(file);
                     acc
                 }
@@ -89,3 +103,25 @@ fn relative_path(&self, path: PathBuf) -> PathBuf {
     }
 
 }
+
+/// Tracks numbered within vinyl sides, e.g. A1, A2, B1, are numbered in order across all sides in their directory, as
+/// tracks of one disc
+fn number_tracks_across_sides(audio_files: &mut [AudioFile]) {
+    let mut sided_files = audio_files
+        .iter_mut()
+        .filter(|f| f.side().is_some())
+        .collect::<Vec<&mut AudioFile>>();
+    sided_files.sort_by_key(|f| (f.path().parent().map(|p| p.to_path_buf()), f.side(), f.get_meta().track_number()));
+
+    let mut directory = None;
+    let mut track_number = 0;
+    for audio_file in sided_files {
+        let parent = audio_file.path().parent().map(|p| p.to_path_buf());
+        if parent != directory {
+            directory = parent;
+            track_number = 0;
+        }
+        track_number += 1;
+        audio_file.set_track_number(track_number);
+    }
+}
@@ -0,0 +1,34 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetadataField {
+    AlbumArtist,
+    Artist,
+    Album,
+    Year,
+    Track,
+    Disc,
+    Title,
+    Genre,
+    Catalogue,
+}
+
+impl Display for MetadataField {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataField::AlbumArtist => f.write_str("album-artist"),
+            MetadataField::Artist => f.write_str("artist"),
+            MetadataField::Album => f.write_str("album"),
+            MetadataField::Year => f.write_str("year"),
+            MetadataField::Track => f.write_str("track"),
+            MetadataField::Disc => f.write_str("disc"),
+            MetadataField::Title => f.write_str("title"),
+            MetadataField::Genre => f.write_str("genre"),
+            MetadataField::Catalogue => f.write_str("catalogue"),
+        }
+    }
+}
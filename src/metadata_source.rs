@@ -0,0 +1,37 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetadataSource {
+    /// Existing tags of the file
+    Tag,
+    /// Names of the album and artist directories
+    Path,
+    /// Name of the file
+    Filename,
+    /// CUE sheet in the same directory as the file
+    Sidecar,
+    /// Name of the archive the file was extracted from
+    Archive,
+    /// Asked for once per album, only for year and genre
+    Prompt,
+    /// Configured default value
+    Default,
+}
+
+impl Display for MetadataSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetadataSource::Tag => f.write_str("tag"),
+            MetadataSource::Path => f.write_str("path"),
+            MetadataSource::Filename => f.write_str("filename"),
+            MetadataSource::Sidecar => f.write_str("sidecar"),
+            MetadataSource::Archive => f.write_str("archive"),
+            MetadataSource::Prompt => f.write_str("prompt"),
+            MetadataSource::Default => f.write_str("default"),
+        }
+    }
+}
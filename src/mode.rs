@@ -8,6 +8,15 @@ pub enum Mode {
     Archives,
     /// Process all files, recursively, in the given directory
     Files,
+    /// Fix the tags of all files, recursively, in the given directory, where they are, backing up the original tags to
+    /// the output directory
+    Tags,
+    /// Restore the original tags of all files, recursively, in the given directory, from the backups in the output
+    /// directory
+    Revert,
+    /// Check the tags, file names and cover art of all files, recursively, in the given directory, without writing
+    /// anything
+    Lint,
 }
 
 impl Display for Mode {
@@ -15,6 +24,9 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Mode::Archives => f.write_str("archives"),
             Mode::Files => f.write_str("Files"),
+            Mode::Tags => f.write_str("tags"),
+            Mode::Revert => f.write_str("revert"),
+            Mode::Lint => f.write_str("lint"),
         }
     }
 }
@@ -0,0 +1,446 @@
+use crate::{error::{CleanerResult, CleanerError}, stray_tags::get_id3v2_length};
+
+// MPEG audio frame and Xing/LAME header handling, see http://gabriel.mp3-tech.org/mp3infotag.html
+
+const BITRATES_V1: [u32; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const BITRATES_V2: [u32; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+const SAMPLE_RATES_V1: [u32; 3] = [44100, 48000, 32000];
+
+const XING_FRAMES_FLAG: u32 = 0x01;
+const XING_BYTES_FLAG: u32 = 0x02;
+const XING_TOC_FLAG: u32 = 0x04;
+const XING_QUALITY_FLAG: u32 = 0x08;
+
+const TOC_LENGTH: usize = 100;
+
+/// Length of the LAME extension following the Xing fields
+const LAME_TAG_LENGTH: usize = 36;
+
+/// The frame bytes covered by the LAME tag CRC
+const LAME_CRC_LENGTH: usize = 190;
+
+/// Encoder delay and padding, in samples, from the LAME tag, needed by players for gapless playback
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct GaplessInfo {
+    pub encoder_delay: u16,
+    pub padding: u16,
+}
+
+pub enum VbrHeaderRepair {
+    Unchanged,
+    Updated,
+    Added,
+}
+
+#[derive(Clone, Copy)]
+struct FrameHeader {
+    mpeg1: bool,
+    mono: bool,
+    bitrate_index: usize,
+    sample_rate: u32,
+    length: usize,
+}
+
+/// The position of each part of an existing Xing (VBR) or Info (CBR) header frame
+struct XingHeader {
+    frame_start: usize,
+    frame_length: usize,
+    fields_start: usize,
+    flags: u32,
+    lame_start: Option<usize>,
+}
+
+/// Get the gapless playback information from the LAME tag of an MP3 file, if it has one
+pub fn get_gapless_info(data: &[u8]) -> Option<GaplessInfo> {
+    find_xing_header(data)
+        .and_then(|xing| xing.lame_start)
+        .map(|lame_start| {
+            let delay_padding = &data[lame_start + 21..lame_start + 24];
+            GaplessInfo {
+                encoder_delay: ((delay_padding[0] as u16) << 4) | ((delay_padding[1] as u16) >> 4),
+                padding: (((delay_padding[1] & 0x0f) as u16) << 8) | delay_padding[2] as u16
+            }
+        })
+}
+
+/// Make sure an MP3 file has a Xing/Info header with the correct frame count, byte count and seek table, adding one if
+/// it is missing, so that players show the correct duration and can seek accurately
+pub fn repair_vbr_header(data: &mut Vec<u8>) -> CleanerResult<VbrHeaderRepair> {
+    let audio_start = get_audio_start(data);
+    let xing = find_xing_header(data);
+    let first_audio_frame = xing.as_ref().map_or(audio_start, |x| x.frame_start + x.frame_length);
+
+    let frames = scan_frames(data, first_audio_frame);
+    let first_frame = frames.first().ok_or(CleanerError::InvalidMpegStream)?;
+    let first_header = parse_frame_header(&data[first_frame.0..]).ok_or(CleanerError::InvalidMpegStream)?;
+    let is_vbr = frames.iter().any(|(_, header)| header.bitrate_index != first_header.bitrate_index);
+
+    match xing {
+        Some(xing) => {
+            let frame_length = xing.frame_length;
+            let stream_bytes = first_audio_frame - xing.frame_start + frames.iter().map(|(_, h)| h.length).sum::<usize>();
+            let expected = build_xing_fields(&frames, xing.frame_start, stream_bytes, get_quality(data, &xing));
+
+            if xing.flags & (XING_FRAMES_FLAG | XING_BYTES_FLAG | XING_TOC_FLAG) == XING_FRAMES_FLAG | XING_BYTES_FLAG | XING_TOC_FLAG
+                && data[xing.fields_start..xing.fields_start + expected.len()] == expected[..] {
+                return Ok(VbrHeaderRepair::Unchanged);
+            }
+
+            // Rebuild the header frame in place, keeping the LAME tag, which must fit after the full set of fields, since
+            // dropping it would lose the gapless playback information
+            let lame = xing.lame_start.map(|start| data[start..start + LAME_TAG_LENGTH].to_vec());
+            let mut frame = data[xing.frame_start..xing.frame_start + 4].to_vec();
+            frame.resize(xing.fields_start - 8 - xing.frame_start, 0);
+            frame.extend_from_slice(&data[xing.fields_start - 8..xing.fields_start - 4]);
+            frame.extend_from_slice(&(XING_FRAMES_FLAG | XING_BYTES_FLAG | XING_TOC_FLAG | XING_QUALITY_FLAG).to_be_bytes());
+            frame.extend_from_slice(&expected);
+            if let Some(lame) = lame {
+                if frame.len() + lame.len() > frame_length {
+                    return Err(CleanerError::GaplessInfoChanged);
+                }
+                let lame_start = frame.len();
+                frame.extend_from_slice(&lame);
+                update_lame_tag(&mut frame, lame_start, stream_bytes);
+            }
+            if frame.len() > frame_length {
+                return Err(CleanerError::InvalidMpegStream);
+            }
+            frame.resize(frame_length, 0);
+
+            data.splice(xing.frame_start..xing.frame_start + frame_length, frame);
+            Ok(VbrHeaderRepair::Updated)
+        },
+        None => {
+            let frame = build_xing_frame(&frames, &first_header, &data[first_frame.0..first_frame.0 + 4], audio_start, is_vbr)?;
+            data.splice(first_frame.0..first_frame.0, frame);
+            Ok(VbrHeaderRepair::Added)
+        }
+    }
+}
+
+fn parse_frame_header(bytes: &[u8]) -> Option<FrameHeader> {
+    if bytes.len() < 4 || bytes[0] != 0xff || bytes[1] & 0xe0 != 0xe0 {
+        return None;
+    }
+
+    let version = (bytes[1] >> 3) & 0x03;
+    let layer = (bytes[1] >> 1) & 0x03;
+    let bitrate_index = (bytes[2] >> 4) as usize;
+    let sample_rate_index = ((bytes[2] >> 2) & 0x03) as usize;
+    let padding = ((bytes[2] >> 1) & 0x01) as usize;
+
+    // Only layer III is supported, and free format bitrate is not
+    if version == 1 || layer != 1 || bitrate_index == 0 || bitrate_index == 15 || sample_rate_index == 3 {
+        return None;
+    }
+
+    let mpeg1 = version == 3;
+    let sample_rate = match version {
+        3 => SAMPLE_RATES_V1[sample_rate_index],
+        2 => SAMPLE_RATES_V1[sample_rate_index] / 2,
+        _ => SAMPLE_RATES_V1[sample_rate_index] / 4
+    };
+
+    Some(FrameHeader {
+        mpeg1,
+        mono: bytes[3] >> 6 == 3,
+        bitrate_index,
+        sample_rate,
+        length: frame_length(mpeg1, bitrate_index, sample_rate) + padding
+    })
+}
+
+fn frame_length(mpeg1: bool, bitrate_index: usize, sample_rate: u32) -> usize {
+    if mpeg1 {
+        (144 * BITRATES_V1[bitrate_index] * 1000 / sample_rate) as usize
+    } else {
+        (72 * BITRATES_V2[bitrate_index] * 1000 / sample_rate) as usize
+    }
+}
+
+fn side_info_length(header: &FrameHeader) -> usize {
+    match (header.mpeg1, header.mono) {
+        (true, false) => 32,
+        (true, true) => 17,
+        (false, false) => 17,
+        (false, true) => 9
+    }
+}
+
+/// Offset of the first byte after any ID3v2 tag
+fn get_audio_start(data: &[u8]) -> usize {
+    get_id3v2_length(data).unwrap_or(0)
+}
+
+/// Find the first frame, skipping any padding or junk before it, requiring the following frame to be valid too
+fn find_first_frame(data: &[u8], start: usize) -> Option<usize> {
+    (start..data.len().saturating_sub(4)).find(|i| {
+        parse_frame_header(&data[*i..])
+            .is_some_and(|h| i + h.length == data.len() || parse_frame_header(&data[i + h.length..]).is_some())
+    })
+}
+
+/// Every frame from the given position up to the end of the MPEG stream, stopping at any trailing tags
+fn scan_frames(data: &[u8], start: usize) -> Vec<(usize, FrameHeader)> {
+    let mut frames = Vec::new();
+    let mut position = match find_first_frame(data, start) {
+        Some(position) => position,
+        None => return frames
+    };
+
+    while let Some(header) = parse_frame_header(&data[position..]) {
+        if position + header.length > data.len() {
+            break;
+        }
+        frames.push((position, header));
+        position += header.length;
+    }
+
+    frames
+}
+
+fn find_xing_header(data: &[u8]) -> Option<XingHeader> {
+    let frame_start = find_first_frame(data, get_audio_start(data))?;
+    let header = parse_frame_header(&data[frame_start..])?;
+    let tag_start = frame_start + 4 + side_info_length(&header);
+
+    let id = data.get(tag_start..tag_start + 4)?;
+    if id != b"Xing" && id != b"Info" {
+        return None;
+    }
+
+    let flags = u32::from_be_bytes(data.get(tag_start + 4..tag_start + 8)?.try_into().ok()?);
+    let fields_start = tag_start + 8;
+
+    let mut fields_end = fields_start;
+    for (flag, length) in [(XING_FRAMES_FLAG, 4), (XING_BYTES_FLAG, 4), (XING_TOC_FLAG, TOC_LENGTH), (XING_QUALITY_FLAG, 4)] {
+        if flags & flag != 0 {
+            fields_end += length;
+        }
+    }
+
+    let lame_start = Some(fields_end)
+        .filter(|start| start + LAME_TAG_LENGTH <= frame_start + header.length)
+        .filter(|start| matches!(data.get(*start..*start + 4), Some(b"LAME" | b"Lavf" | b"Lavc")));
+
+    Some(XingHeader {
+        frame_start,
+        frame_length: header.length,
+        fields_start,
+        flags,
+        lame_start
+    })
+}
+
+fn get_quality(data: &[u8], xing: &XingHeader) -> u32 {
+    if xing.flags & XING_QUALITY_FLAG == 0 {
+        return 0;
+    }
+
+    let mut offset = xing.fields_start;
+    for (flag, length) in [(XING_FRAMES_FLAG, 4), (XING_BYTES_FLAG, 4), (XING_TOC_FLAG, TOC_LENGTH)] {
+        if xing.flags & flag != 0 {
+            offset += length;
+        }
+    }
+    u32::from_be_bytes(data[offset..offset + 4].try_into().expect("Quality must be four bytes"))
+}
+
+/// Frame count, byte count, seek table and quality fields for the given audio frames
+fn build_xing_fields(frames: &[(usize, FrameHeader)], stream_start: usize, stream_bytes: usize, quality: u32) -> Vec<u8> {
+    let mut fields = Vec::with_capacity(12 + TOC_LENGTH);
+    fields.extend_from_slice(&(frames.len() as u32).to_be_bytes());
+    fields.extend_from_slice(&(stream_bytes as u32).to_be_bytes());
+
+    // Each entry is the position, as a fraction of 256 of the stream, of the frame at each percent of the duration
+    for percent in 0..TOC_LENGTH {
+        let frame_index = (percent * frames.len() / TOC_LENGTH).min(frames.len().saturating_sub(1));
+        let offset = frames.get(frame_index).map_or(0, |(position, _)| position - stream_start);
+        fields.push((offset * 256 / stream_bytes.max(1)).min(255) as u8);
+    }
+
+    fields.extend_from_slice(&quality.to_be_bytes());
+    fields
+}
+
+/// Build a new header frame to go before the given audio frames, using the smallest bitrate that gives enough room
+fn build_xing_frame(frames: &[(usize, FrameHeader)], first_header: &FrameHeader, first_header_bytes: &[u8], audio_start: usize, is_vbr: bool) -> CleanerResult<Vec<u8>> {
+    let tag_offset = 4 + side_info_length(first_header);
+    let required = tag_offset + 8 + 12 + TOC_LENGTH;
+
+    let bitrate_index = (1..15)
+        .find(|i| frame_length(first_header.mpeg1, *i, first_header.sample_rate) >= required)
+        .ok_or(CleanerError::InvalidMpegStream)?;
+    let length = frame_length(first_header.mpeg1, bitrate_index, first_header.sample_rate);
+
+    let audio_bytes = frames.iter().map(|(_, h)| h.length).sum::<usize>();
+    let stream_start = frames.first().map_or(audio_start, |(position, _)| *position);
+
+    // Offsets in the seek table are relative to the start of the new frame
+    let shifted = frames
+        .iter()
+        .map(|(position, header)| (position + length, *header))
+        .collect::<Vec<(usize, FrameHeader)>>();
+
+    let mut frame = vec![0u8; length];
+    frame[0] = first_header_bytes[0];
+    frame[1] = first_header_bytes[1] | 0x01;
+    frame[2] = ((bitrate_index as u8) << 4) | (first_header_bytes[2] & 0x0c);
+    frame[3] = first_header_bytes[3];
+    frame[tag_offset..tag_offset + 4].copy_from_slice(if is_vbr { b"Xing" } else { b"Info" });
+    frame[tag_offset + 4..tag_offset + 8].copy_from_slice(&(XING_FRAMES_FLAG | XING_BYTES_FLAG | XING_TOC_FLAG | XING_QUALITY_FLAG).to_be_bytes());
+
+    let fields = build_xing_fields(&shifted, stream_start, length + audio_bytes, 0);
+    frame[tag_offset + 8..tag_offset + 8 + fields.len()].copy_from_slice(&fields);
+
+    Ok(frame)
+}
+
+/// Update the music length and CRC of a LAME tag after the header frame has been rebuilt
+fn update_lame_tag(frame: &mut [u8], lame_start: usize, stream_bytes: usize) {
+    frame[lame_start + 28..lame_start + 32].copy_from_slice(&(stream_bytes as u32).to_be_bytes());
+
+    let crc_start = lame_start + LAME_TAG_LENGTH - 2;
+    if crc_start == LAME_CRC_LENGTH {
+        let crc = crc16(&frame[..LAME_CRC_LENGTH]);
+        frame[crc_start..crc_start + 2].copy_from_slice(&crc.to_be_bytes());
+    }
+}
+
+/// CRC-16 as used by the LAME tag (polynomial 0x8005, reflected)
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u16, |crc, _| {
+            if crc & 1 != 0 { (crc >> 1) ^ 0xa001 } else { crc >> 1 }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, collections::BTreeMap};
+
+    use lofty::{Tag, TagType, TaggedFileExt, Accessor};
+    use tempfile::Builder;
+
+    use crate::{audio_file_meta::{AudioFileMeta, AudioFileType}, provenance::Provenance, options::Options, tagger::{write_tagged_file, get_tagged_file, AlbumTags, TrackTags}};
+
+    use super::*;
+
+    // MPEG-1 layer III, 128kbps, 44.1kHz, joint stereo
+    const FRAME_HEADER: [u8; 4] = [0xff, 0xfb, 0x90, 0x40];
+    const FRAME_LENGTH: usize = 417;
+
+    /// An MP3 with an Info header frame and LAME tag followed by silent frames
+    fn build_mp3(frame_count: usize, encoder_delay: u16, padding: u16) -> Vec<u8> {
+        let mut data = Vec::new();
+
+        let mut info = vec![0u8; FRAME_LENGTH];
+        info[..4].copy_from_slice(&FRAME_HEADER);
+        info[36..40].copy_from_slice(b"Info");
+        info[40..44].copy_from_slice(&(XING_FRAMES_FLAG | XING_BYTES_FLAG | XING_TOC_FLAG | XING_QUALITY_FLAG).to_be_bytes());
+        info[44..48].copy_from_slice(&(frame_count as u32).to_be_bytes());
+        info[48..52].copy_from_slice(&(((frame_count + 1) * FRAME_LENGTH) as u32).to_be_bytes());
+        info[156..165].copy_from_slice(b"LAME3.100");
+        info[177] = (encoder_delay >> 4) as u8;
+        info[178] = (((encoder_delay & 0x0f) << 4) as u8) | (padding >> 8) as u8;
+        info[179] = padding as u8;
+        data.extend_from_slice(&info);
+
+        for _ in 0..frame_count {
+            let mut frame = vec![0u8; FRAME_LENGTH];
+            frame[..4].copy_from_slice(&FRAME_HEADER);
+            data.extend_from_slice(&frame);
+        }
+
+        data
+    }
+
+    fn write_temp_mp3(data: &[u8]) -> (tempfile::TempDir, PathBuf) {
+        let dir = Builder::new().prefix("cleaner").tempdir().unwrap();
+        let path = dir.path().join("track.mp3");
+        fs::write(&path, data).unwrap();
+        (dir, path)
+    }
+
+    #[test]
+    fn write_tagged_file_preserves_gapless_info() {
+        let (dir, path) = write_temp_mp3(&build_mp3(40, 576, 1234));
+        let target_path = dir.path().join("target.mp3");
+
+        // Start with an existing tag, as a source file would have, so that there is something to replace
+        let mut tagged_file = get_tagged_file(&path).unwrap();
+        let mut tag = Tag::new(TagType::ID3v2);
+        tag.set_title("Original".to_string());
+        tagged_file.insert_tag(tag);
+        tagged_file.save_to_path(&path).unwrap();
+
+        let original = get_gapless_info(&fs::read(&path).unwrap());
+        assert_eq!(original, Some(GaplessInfo { encoder_delay: 576, padding: 1234 }));
+
+        let meta = AudioFileMeta {
+            tagged_file: get_tagged_file(&path).unwrap(),
+            album_artist_name: Some("Artist".to_string()),
+            artist_names: vec!["Artist".to_string()],
+            album_title: Some("Album".to_string()),
+            year: Some(2001),
+            track_number: Some(1),
+            disc_number: None,
+            track_title: Some("A title long enough to be truncated in an ID3v1 tag".to_string()),
+            genres: vec!["Rock".to_string()],
+            catalogue_number: None,
+            tag_fields: BTreeMap::new(),
+            audio_file_type: Some(AudioFileType::Mp3),
+            provenance: Provenance::default()
+        };
+        let album = AlbumTags { default_year: None, default_genres: Vec::new(), total_tracks: 1, cover_image: None, overrides: Vec::new() };
+        write_tagged_file(&path, &target_path, &meta, &album, &TrackTags::default(), &Options::default()).unwrap();
+
+        assert_eq!(get_gapless_info(&fs::read(&target_path).unwrap()), original);
+        assert_eq!(get_tagged_file(&target_path).unwrap().primary_tag().and_then(|t| t.title().map(|s| s.to_string())).as_deref(), meta.track_title());
+    }
+
+    #[test]
+    fn repair_vbr_header_fixes_frame_count_and_keeps_lame_tag() {
+        let mut data = build_mp3(40, 576, 1234);
+        // Claim the wrong number of frames
+        data[44..48].copy_from_slice(&10u32.to_be_bytes());
+
+        assert!(matches!(repair_vbr_header(&mut data).unwrap(), VbrHeaderRepair::Updated));
+        assert!(matches!(repair_vbr_header(&mut data).unwrap(), VbrHeaderRepair::Unchanged));
+
+        assert_eq!(u32::from_be_bytes(data[44..48].try_into().unwrap()), 40);
+        assert_eq!(get_gapless_info(&data), Some(GaplessInfo { encoder_delay: 576, padding: 1234 }));
+    }
+
+    #[test]
+    fn repair_vbr_header_fails_rather_than_drop_lame_tag() {
+        // A 48kbps header frame, with room for the LAME tag after the frame and byte counts, but not after a seek table
+        let mut info = vec![0u8; 156];
+        info[..4].copy_from_slice(&[0xff, 0xfb, 0x30, 0x40]);
+        info[36..40].copy_from_slice(b"Info");
+        info[40..44].copy_from_slice(&(XING_FRAMES_FLAG | XING_BYTES_FLAG).to_be_bytes());
+        info[52..61].copy_from_slice(b"LAME3.100");
+        info[73..76].copy_from_slice(&[0x24, 0x04, 0xd2]);
+
+        let mut data = info;
+        data.extend_from_slice(&build_mp3(40, 0, 0)[FRAME_LENGTH..]);
+        let original = data.clone();
+        assert_eq!(get_gapless_info(&data), Some(GaplessInfo { encoder_delay: 576, padding: 1234 }));
+
+        assert!(matches!(repair_vbr_header(&mut data), Err(CleanerError::GaplessInfoChanged)));
+        assert_eq!(data, original);
+    }
+
+    #[test]
+    fn repair_vbr_header_adds_missing_header() {
+        let data = build_mp3(40, 0, 0)[FRAME_LENGTH..].to_vec();
+        let mut repaired = data.clone();
+
+        assert!(matches!(repair_vbr_header(&mut repaired).unwrap(), VbrHeaderRepair::Added));
+
+        assert_eq!(&repaired[36..40], b"Info");
+        assert_eq!(u32::from_be_bytes(repaired[44..48].try_into().unwrap()), 40);
+        assert!(repaired.ends_with(&data));
+    }
+}
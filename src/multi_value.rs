@@ -0,0 +1,126 @@
+use std::fmt;
+
+use regex::Regex;
+
+use crate::metadata_field::MetadataField;
+
+/// Separator of the joined form of several values, used where a format or a name has room for only one value
+pub const JOINED_SEPARATOR: &str = "; ";
+
+/// Delimiters within a value that separate several artists or genres, used when none are configured. Slashes do not
+/// separate artists by default, as they are part of some names, e.g. AC/DC.
+const DEFAULT_ARTIST_DELIMITERS: [&str; 3] = [";", " feat. ", " ft. "];
+const DEFAULT_GENRE_DELIMITERS: [&str; 2] = [";", "/"];
+
+/// Several values of one field, e.g. the artists of a track, shown in their joined form
+pub struct Values(pub Vec<String>);
+
+impl fmt::Display for Values {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0.join(JOINED_SEPARATOR))
+    }
+}
+
+/// Delimiters that split a value of the artist or genre fields into several, e.g. "Artist feat. Other" into two
+/// artists, matched ignoring case
+pub struct ValueDelimiters {
+    artist: Option<Regex>,
+    genre: Option<Regex>,
+}
+
+impl ValueDelimiters {
+
+    /// Delimiters for each field, or the default delimiters for a field with none given
+    pub fn new(artist: Option<&Vec<String>>, genre: Option<&Vec<String>>) -> ValueDelimiters {
+        let to_vec = |delimiters: &[&str]| delimiters.iter().map(|d| d.to_string()).collect::<Vec<String>>();
+
+        ValueDelimiters {
+            artist: compile_delimiters(artist.unwrap_or(&to_vec(&DEFAULT_ARTIST_DELIMITERS))),
+            genre: compile_delimiters(genre.unwrap_or(&to_vec(&DEFAULT_GENRE_DELIMITERS)))
+        }
+    }
+
+    /// Split values at the delimiters of their field, trimming them and leaving out empty and repeated values
+    pub fn split(&self, field: MetadataField, values: Vec<String>) -> Vec<String> {
+        let delimiters = match field {
+            MetadataField::Artist => self.artist.as_ref(),
+            MetadataField::Genre => self.genre.as_ref(),
+            _ => None
+        };
+
+        let mut split_values = Vec::<String>::new();
+        for value in values {
+            let parts = match delimiters {
+                Some(delimiters) => delimiters.split(&value).map(|s| s.trim().to_string()).collect(),
+                None => vec![value.trim().to_string()]
+            };
+            for part in parts {
+                if !part.is_empty() && !split_values.contains(&part) {
+                    split_values.push(part);
+                }
+            }
+        }
+
+        split_values
+    }
+}
+
+impl Default for ValueDelimiters {
+    fn default() -> Self {
+        ValueDelimiters::new(None, None)
+    }
+}
+
+/// The joined form of values, or None if there are none
+pub fn join_values(values: &[String]) -> Option<String> {
+    Some(values.join(JOINED_SEPARATOR)).filter(|s| !s.is_empty())
+}
+
+/// Split a value given in its joined form, e.g. when edited, into its values
+pub fn split_joined(value: &str) -> Vec<String> {
+    value
+        .split(JOINED_SEPARATOR.trim())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn compile_delimiters(delimiters: &[String]) -> Option<Regex> {
+    let alternatives = delimiters
+        .iter()
+        .filter(|d| !d.is_empty())
+        .map(|d| regex::escape(d))
+        .collect::<Vec<String>>();
+
+    if alternatives.is_empty() {
+        return None;
+    }
+
+    Some(Regex::new(&format!("(?i){}", alternatives.join("|"))).expect("Escaped delimiters must be a valid pattern"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_vec(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[test]
+    fn split_at_default_delimiters() {
+        let delimiters = ValueDelimiters::default();
+
+        assert_eq!(delimiters.split(MetadataField::Artist, to_vec(&["Artist FEAT. Other; Third", "AC/DC"])), to_vec(&["Artist", "Other", "Third", "AC/DC"]));
+        assert_eq!(delimiters.split(MetadataField::Genre, to_vec(&["Rock/Pop", "Rock; ", "Jazz"])), to_vec(&["Rock", "Pop", "Jazz"]));
+        assert_eq!(delimiters.split(MetadataField::Title, to_vec(&[" Rock/Pop; Jazz "])), to_vec(&["Rock/Pop; Jazz"]));
+    }
+
+    #[test]
+    fn split_at_configured_delimiters() {
+        let delimiters = ValueDelimiters::new(Some(&to_vec(&[" & "])), Some(&Vec::new()));
+
+        assert_eq!(delimiters.split(MetadataField::Artist, to_vec(&["Artist & Other feat. Third"])), to_vec(&["Artist", "Other feat. Third"]));
+        assert_eq!(delimiters.split(MetadataField::Genre, to_vec(&["Rock/Pop"])), to_vec(&["Rock/Pop"]));
+    }
+}
@@ -0,0 +1,411 @@
+use std::path::{PathBuf, Path};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::error::{CleanerResult, CleanerError};
+
+/// File name patterns tried in order when none are configured, from the most to the least specific
+const DEFAULT_FILENAME_PATTERNS: [&str; 6] = [
+    // Artist - Album - 03 - Title
+    r"^(?P<artist>.+?) - (?P<album>.+?) - (?P<track>\d{1,3}) - (?P<title>.+)$",
+    // 1-03 Title, 1-03. Title, 1-03 - Title
+    r"^(?P<disc>\d{1,2})-(?P<track>\d{1,3})\.?(?:\s-\s|\s)(?P<title>.+)$",
+    // A1. Title, B2 Title (vinyl sides)
+    r"^(?P<side>[A-Z])(?P<track>\d{1,2})\.?(?:\s-\s|\s)(?P<title>.+)$",
+    // 03_Title
+    r"^(?P<track>\d{1,3})_(?P<title>.+)$",
+    // 03 Title, 03. Title, 03 - Title
+    r"^(?P<track>\d{1,3})\.?(?:\s-\s|\s)(?P<title>.+)$",
+    r"^(?P<title>.+)$",
+];
+
+/// Directory name patterns tried in order when none are configured, applied after markers have been stripped
+const DEFAULT_DIRECTORY_PATTERNS: [&str; 7] = [
+    // CD1, Disc 2
+    r"(?i)^(?:cd|dis[ck])\s*(?P<disc>\d{1,2})$",
+    // Artist - 2001 - Album
+    r"^(?P<artist>.+?) - (?P<year>\d{4}) - (?P<album>.+)$",
+    // Artist - Album (2001)
+    r"^(?P<artist>.+?) - (?P<album>.+?)\s\(?(?P<year>\d{4})\)?$",
+    // [2001] Album, (2001) Album, 2001 - Album
+    r"^[\[\(]?(?P<year>\d{4})[\]\)]?(?:\s-\s|\s)(?P<album>.+)$",
+    // Album (2001), Album 2001
+    r"^(?P<album>.+)\s\(?(?P<year>\d{4})\)?$",
+    // Artist - Album
+    r"^(?P<artist>.+?) - (?P<album>.+)$",
+    r"^(?P<album>.+)$",
+];
+
+/// Quality and source markers, e.g. [FLAC], (WEB), [24-96], or a trailing " - WEB" or " - 320", stripped from directory
+/// names when none are configured. Unbracketed markers must follow a separator, and words must be in capitals, so that
+/// titles such as "Charlotte's Web" are kept.
+const DEFAULT_MARKER_PATTERNS: [&str; 2] = [
+    r"(?i)\s*[\[\(\{]\s*(?:flac|mp3|aac|ogg|alac|web|cd|vinyl|lp|sacd|dvd|lossless|hi-?res|v0|v2|vbr|cbr|320|256|192|\d{2}\s?bit|\d{2}[-/ ]\d{2,3}(?:\.\d)?|\d{2,3}(?:\.\d)?\s?khz|\d{3}\s?kbps)(?:[\s,/+-]+(?:flac|mp3|aac|ogg|alac|web|cd|vinyl|lp|sacd|dvd|lossless|hi-?res|v0|v2|vbr|cbr|320|256|192|\d{2}\s?bit|\d{2}[-/ ]\d{2,3}(?:\.\d)?|\d{2,3}(?:\.\d)?\s?khz|\d{3}\s?kbps))*\s*[\]\)\}]",
+    r"\s+-\s+(?:FLAC|MP3|AAC|WEB|(?i:v0|v2|320|256|\d{2}\s?bit|\d{2}-\d{2,3}))$",
+];
+
+/// Source and release type tags that follow the album title in a scene release name
+const SCENE_SOURCE_PATTERN: &str = r"(?i)^(?:web|\d?cd[mrs]?|cdep|mcd|vinyl|vls|lp|ep|single|flac|mp3|retail|promo|bootleg|dvd|sacd|sat|fm|dab|tape|live|remastered|reissue|ltd|limited|deluxe|advance|(?-i:[A-Z]{2}))$";
+
+/// Artist name for compilations, given as VA in scene release names
+const VARIOUS_ARTISTS_NAME: &str = "Various Artists";
+
+/// Pattern reported for names parsed as scene releases
+const SCENE_PATTERN_NAME: &str = "scene release name";
+
+/// Values captured from a file or directory name by the first matching pattern
+#[derive(Default)]
+pub struct NameFields {
+    pub track: Option<u32>,
+    pub disc: Option<u32>,
+    /// Vinyl side, where A is 1, that the track number counts from the start of
+    pub side: Option<u32>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub title: Option<String>,
+    pub year: Option<u32>,
+    pub catalogue: Option<String>,
+    /// The pattern that matched
+    pub pattern: Option<String>,
+}
+
+/// Regular expressions with named captures (track, disc, side, artist, album, title, year), tried in order against a name
+pub struct NamePatterns {
+    patterns: Vec<Regex>,
+}
+
+impl NamePatterns {
+
+    pub fn new(patterns: &[String]) -> CleanerResult<NamePatterns> {
+        Ok(NamePatterns { patterns: compile_patterns(patterns)? })
+    }
+
+    pub fn default_filename() -> NamePatterns {
+        NamePatterns { patterns: compile_default_patterns(&DEFAULT_FILENAME_PATTERNS) }
+    }
+
+    pub fn default_directory() -> NamePatterns {
+        NamePatterns { patterns: compile_default_patterns(&DEFAULT_DIRECTORY_PATTERNS) }
+    }
+
+    /// Parse a name with the first pattern that matches it
+    pub fn parse(&self, name: &str) -> NameFields {
+        self.parse_where(name, |_| true)
+    }
+
+    /// Parse a name with the first pattern that matches it and gives acceptable values
+    fn parse_where<F>(&self, name: &str, accept: F) -> NameFields
+    where
+        F: Fn(&NameFields) -> bool
+    {
+        self.patterns
+            .iter()
+            .filter_map(|pattern| pattern.captures(name).map(|captures| {
+                let text = |field: &str| captures
+                    .name(field)
+                    .map(|m| m.as_str().replace('_', " ").trim().to_string())
+                    .filter(|s| !s.is_empty());
+                let number = |field: &str| captures
+                    .name(field)
+                    .and_then(|m| parse_number(m.as_str()));
+
+                NameFields {
+                    track: number("track"),
+                    disc: number("disc"),
+                    side: number("side"),
+                    artist: text("artist"),
+                    album: text("album"),
+                    title: text("title"),
+                    year: number("year"),
+                    catalogue: text("catalogue"),
+                    pattern: Some(pattern.as_str().to_string())
+                }
+            }))
+            .find(accept)
+            .unwrap_or_default()
+    }
+}
+
+/// All of the patterns used to get metadata from the path of a file
+pub struct PathPatterns {
+    filename: NamePatterns,
+    directory: NamePatterns,
+    markers: Vec<Regex>,
+}
+
+impl PathPatterns {
+
+    pub fn new(filename: NamePatterns, directory: NamePatterns, markers: Option<&Vec<String>>) -> CleanerResult<PathPatterns> {
+        let markers = match markers {
+            Some(markers) => compile_patterns(markers)?,
+            None => compile_default_patterns(&DEFAULT_MARKER_PATTERNS)
+        };
+
+        Ok(PathPatterns { filename, directory, markers })
+    }
+
+    /// Parse the name, without extension, of a file
+    pub fn parse_file_name(&self, path: &Path) -> NameFields {
+        path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .map(|file_stem| self.filename.parse(file_stem))
+            .unwrap_or_default()
+    }
+
+    /// Parse the names of the directories between the root and a file, for any depth of hierarchy. Disc directories are
+    /// skipped over to find the album directory, and the directory above that, if any, is taken as the artist name.
+    /// Deeper directories are ignored.
+    pub fn parse_directories(&self, root_path: &PathBuf, path: &Path) -> NameFields {
+        let mut names = path
+            .ancestors()
+            .skip(1)
+            .take_while(|p| p != root_path)
+            .filter_map(|p| p.file_name())
+            .filter_map(|s| s.to_str())
+            .map(|s| self.strip_markers(s))
+            .peekable();
+
+        let mut disc = None;
+        while let Some(name) = names.next() {
+            let parent_name = names.peek().cloned();
+
+            // An artist captured from the album directory must agree with the artist directory, where there is one,
+            // otherwise a title such as "Live - Paris" would be split
+            let fields = self.directory.parse_where(&name, |f| match (&f.artist, &parent_name) {
+                (Some(artist), Some(parent_name)) => artist.eq_ignore_ascii_case(parent_name),
+                _ => true
+            });
+
+            if fields.album.is_none() && fields.disc.is_some() && parent_name.is_some() {
+                disc = disc.or(fields.disc);
+                continue;
+            }
+
+            return NameFields {
+                disc: disc.or(fields.disc),
+                artist: fields.artist.or(parent_name),
+                ..fields
+            };
+        }
+
+        NameFields { disc, ..NameFields::default() }
+    }
+
+    /// Parse the name of an archive, as a scene release name, e.g. Artist-Album-(CAT001)-WEB-2020-GROUP, or otherwise
+    /// as a directory name
+    pub fn parse_archive_name(&self, path: &Path) -> NameFields {
+        let file_stem = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(file_stem) => file_stem,
+            None => return NameFields::default()
+        };
+
+        parse_scene_name(file_stem).unwrap_or_else(|| {
+            let name = self.strip_markers(file_stem);
+            self.directory.parse(&name)
+        })
+    }
+
+    fn strip_markers(&self, name: &str) -> String {
+        let mut name = name.trim().to_string();
+        loop {
+            let stripped = self.markers
+                .iter()
+                .fold(name.to_owned(), |s, marker| marker.replace_all(&s, "").trim().to_string());
+            if stripped == name || stripped.is_empty() {
+                return name;
+            }
+            name = stripped;
+        }
+    }
+}
+
+impl Default for PathPatterns {
+    fn default() -> Self {
+        PathPatterns {
+            filename: NamePatterns::default_filename(),
+            directory: NamePatterns::default_directory(),
+            markers: compile_default_patterns(&DEFAULT_MARKER_PATTERNS)
+        }
+    }
+}
+
+pub fn compile_patterns(patterns: &[String]) -> CleanerResult<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|p| Regex::new(p).map_err(|err| CleanerError::InvalidPattern(p.to_string(), err.to_string())))
+        .collect()
+}
+
+fn compile_default_patterns(patterns: &[&str]) -> Vec<Regex> {
+    patterns
+        .iter()
+        .map(|p| Regex::new(p).expect("Default pattern must be valid"))
+        .collect()
+}
+
+/// Parse a scene release name, where fields are separated by hyphens, words by underscores, and the last two fields are
+/// the year and release group
+fn parse_scene_name(name: &str) -> Option<NameFields> {
+    lazy_static! {
+        static ref SOURCE_RE: Regex = Regex::new(SCENE_SOURCE_PATTERN).unwrap();
+        static ref YEAR_RE: Regex = Regex::new(r"^(?:19|20)\d{2}$").unwrap();
+        static ref CATALOGUE_RE: Regex = Regex::new(r"^\((.+)\)$").unwrap();
+    }
+
+    if name.contains(' ') {
+        return None;
+    }
+
+    let fields = name.split('-').collect::<Vec<&str>>();
+    if fields.iter().any(|f| f.is_empty()) {
+        return None;
+    }
+    let year_index = fields.iter().rposition(|f| YEAR_RE.is_match(f)).filter(|i| *i >= 2 && *i == fields.len() - 2)?;
+
+    let mut catalogue = None;
+    let mut album_end = year_index;
+    while album_end > 2 {
+        let field = fields[album_end - 1];
+        if let Some(captures) = CATALOGUE_RE.captures(field) {
+            catalogue = catalogue.or_else(|| captures.get(1).map(|m| m.as_str().to_string()));
+        } else if !SOURCE_RE.is_match(field) {
+            break;
+        }
+        album_end -= 1;
+    }
+
+    let to_text = |s: &str| Some(s.replace('_', " ").trim().to_string()).filter(|s| !s.is_empty());
+    let artist = match fields[0] {
+        "VA" | "va" => Some(VARIOUS_ARTISTS_NAME.to_string()),
+        artist => to_text(artist)
+    };
+
+    Some(NameFields {
+        artist,
+        album: to_text(&fields[1..album_end].join("-")),
+        year: fields[year_index].parse::<u32>().ok(),
+        catalogue,
+        pattern: Some(SCENE_PATTERN_NAME.to_string()),
+        ..NameFields::default()
+    })
+}
+
+/// Parse a number, or a single letter as used for vinyl sides, where A is 1
+fn parse_number(s: &str) -> Option<u32> {
+    let s = s.trim();
+    match s.chars().next() {
+        Some(c) if s.len() == 1 && c.is_ascii_alphabetic() => Some(c.to_ascii_uppercase() as u32 - 'A' as u32 + 1),
+        _ => s.parse::<u32>().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_file_name(name: &str) -> NameFields {
+        NamePatterns::default_filename().parse(name)
+    }
+
+    #[test]
+    fn default_filename_patterns_parse_track_and_title() {
+        for name in ["01 - Title", "01 Title", "01. Title", "01_Title"] {
+            let fields = parse_file_name(name);
+            assert_eq!((fields.track, fields.disc, fields.title.as_deref()), (Some(1), None, Some("Title")), "{}", name);
+        }
+    }
+
+    #[test]
+    fn default_filename_patterns_parse_disc_and_side() {
+        let fields = parse_file_name("1-01 Title");
+        assert_eq!((fields.disc, fields.track, fields.title.as_deref()), (Some(1), Some(1), Some("Title")));
+
+        let fields = parse_file_name("B2. Title");
+        assert_eq!((fields.side, fields.disc, fields.track, fields.title.as_deref()), (Some(2), None, Some(2), Some("Title")));
+    }
+
+    #[test]
+    fn default_filename_patterns_parse_artist_and_album() {
+        let fields = parse_file_name("Artist - Album - 03 - Title");
+        assert_eq!(fields.artist.as_deref(), Some("Artist"));
+        assert_eq!(fields.album.as_deref(), Some("Album"));
+        assert_eq!((fields.track, fields.title.as_deref()), (Some(3), Some("Title")));
+    }
+
+    #[test]
+    fn default_filename_patterns_keep_names_without_track_as_title() {
+        for name in ["Title", "1999", "AB1 Title", "Title 01", "01-Title"] {
+            let fields = parse_file_name(name);
+            assert_eq!((fields.track, fields.disc, fields.side, fields.title.as_deref()), (None, None, None, Some(name)), "{}", name);
+        }
+    }
+
+    #[test]
+    fn default_marker_patterns_strip_bracketed_and_separated_markers() {
+        let path_patterns = PathPatterns::default();
+        for (name, stripped) in [
+            ("Album [FLAC]", "Album"),
+            ("Album (WEB) [24-96]", "Album"),
+            ("Album {16bit 44.1kHz}", "Album"),
+            ("Album - WEB", "Album"),
+            ("Album - 320", "Album"),
+            ("Album - FLAC - 24bit", "Album"),
+        ] {
+            assert_eq!(path_patterns.strip_markers(name), stripped, "{}", name);
+        }
+    }
+
+    #[test]
+    fn default_marker_patterns_keep_titles_ending_in_marker_words() {
+        let path_patterns = PathPatterns::default();
+        for name in ["Charlotte's Web", "Spider - Web", "Route 256", "Flight 320", "Live at the Web", "Vinyl", "Album - Flac"] {
+            assert_eq!(path_patterns.strip_markers(name), name);
+        }
+    }
+
+    #[test]
+    fn default_directory_patterns_parse_album_with_markers_removed() {
+        let path_patterns = PathPatterns::default();
+        let fields = path_patterns.parse_directories(&PathBuf::from("/root"), Path::new("/root/Artist/Charlotte's Web (2001) [FLAC]/01 Title.flac"));
+        assert_eq!(fields.artist.as_deref(), Some("Artist"));
+        assert_eq!(fields.album.as_deref(), Some("Charlotte's Web"));
+        assert_eq!(fields.year, Some(2001));
+    }
+
+    #[test]
+    fn scene_release_names_give_artist_album_year_and_catalogue() {
+        let fields = parse_scene_name("Artist-Album-(CAT001)-WEB-2020-GRP").unwrap();
+        assert_eq!(fields.artist.as_deref(), Some("Artist"));
+        assert_eq!(fields.album.as_deref(), Some("Album"));
+        assert_eq!(fields.year, Some(2020));
+        assert_eq!(fields.catalogue.as_deref(), Some("CAT001"));
+
+        let fields = parse_scene_name("VA-Summer_Hits_Part-2-2CD-FLAC-2019-GRP").unwrap();
+        assert_eq!(fields.artist.as_deref(), Some(VARIOUS_ARTISTS_NAME));
+        assert_eq!(fields.album.as_deref(), Some("Summer Hits Part-2"));
+        assert_eq!((fields.year, fields.catalogue), (Some(2019), None));
+    }
+
+    #[test]
+    fn names_without_year_and_group_are_not_scene_release_names() {
+        for name in ["Artist - Album (2001)", "Artist-Album", "Artist-Album-2020", "Artist-2020-GRP", "Artist-Album-2020-"] {
+            assert!(parse_scene_name(name).is_none(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn archive_names_fall_back_to_directory_patterns() {
+        let path_patterns = PathPatterns::default();
+
+        let fields = path_patterns.parse_archive_name(Path::new("/archives/Artist - Album (2001) [FLAC].zip"));
+        assert_eq!(fields.artist.as_deref(), Some("Artist"));
+        assert_eq!(fields.album.as_deref(), Some("Album"));
+        assert_eq!(fields.year, Some(2001));
+        assert_ne!(fields.pattern.as_deref(), Some(SCENE_PATTERN_NAME));
+
+        let fields = path_patterns.parse_archive_name(Path::new("/archives/Artist-Album-2020.zip"));
+        assert_eq!((fields.artist, fields.album.as_deref(), fields.year), (None, Some("Artist-Album-2020"), None));
+    }
+}
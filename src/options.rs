@@ -0,0 +1,69 @@
+use std::path::PathBuf;
+
+use crate::{replay_gain_mode::ReplayGainMode, duplicate_policy::DuplicatePolicy, edition_policy::EditionPolicy, spectrogram_mode::SpectrogramMode, tempo_key_mode::TempoKeyMode, name_patterns::PathPatterns, precedence::SourcePrecedence, placeholders::Placeholders, multi_value::ValueDelimiters, tag_items::TagItems, tag_fields::TagFields, id3v2_version::Id3v2Version, id3v1_mode::Id3v1Mode};
+
+/// Options controlling how files are cleaned, common to all processing modes
+pub struct Options {
+    pub quality: u8,
+    pub replay_gain: ReplayGainMode,
+    pub duplicates: Option<DuplicatePolicy>,
+    pub fingerprint: bool,
+    pub editions: Option<EditionPolicy>,
+    pub check_lossless: bool,
+    pub review_path: Option<PathBuf>,
+    pub spectrograms: Option<SpectrogramMode>,
+    pub spectrogram_path: Option<PathBuf>,
+    pub tempo_key: TempoKeyMode,
+    pub repair_vbr_header: bool,
+    pub id3v2_version: Id3v2Version,
+    pub id3v1: Id3v1Mode,
+    pub tag_padding: usize,
+    pub origin_tags: bool,
+    pub path_patterns: PathPatterns,
+    pub precedence: SourcePrecedence,
+    pub placeholders: Placeholders,
+    pub delimiters: ValueDelimiters,
+    pub tag_items: TagItems,
+    pub tag_fields: TagFields,
+    pub show_patterns: bool,
+    pub explain: bool,
+    pub explain_path: Option<PathBuf>,
+    pub preview: bool,
+    pub min_cover_size: u32,
+    pub lint_path: Option<PathBuf>,
+}
+
+/// The options of a run without any arguments, as used for files already written by the cleaner, and in tests
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            quality: 95,
+            replay_gain: ReplayGainMode::Off,
+            duplicates: None,
+            fingerprint: false,
+            editions: None,
+            check_lossless: false,
+            review_path: None,
+            spectrograms: None,
+            spectrogram_path: None,
+            tempo_key: TempoKeyMode::Off,
+            repair_vbr_header: false,
+            id3v2_version: Id3v2Version::V4,
+            id3v1: Id3v1Mode::Plain,
+            tag_padding: 4096,
+            origin_tags: true,
+            path_patterns: PathPatterns::default(),
+            precedence: SourcePrecedence::default(),
+            placeholders: Placeholders::default(),
+            delimiters: ValueDelimiters::default(),
+            tag_items: TagItems::default(),
+            tag_fields: TagFields::default(),
+            show_patterns: false,
+            explain: false,
+            explain_path: None,
+            preview: false,
+            min_cover_size: 500,
+            lint_path: None,
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use std::{path::Path, time::{SystemTime, UNIX_EPOCH}};
+
+use lofty::{ItemKey, ItemValue, Tag, TagItem, TaggedFile, TaggedFileExt};
+use sha2::{Sha256, Digest};
+
+/// Keys of the items recording where a file came from, written as TXXX frames for ID3v2 and as fields for Vorbis comments
+const SOURCE_KEY: &str = "CLEANER_SOURCE";
+const ORIGINAL_FILE_NAME_KEY: &str = "CLEANER_ORIGINAL_FILENAME";
+const ORIGINAL_HASH_KEY: &str = "CLEANER_ORIGINAL_SHA256";
+const VERSION_KEY: &str = "CLEANER_VERSION";
+const TIMESTAMP_KEY: &str = "CLEANER_TIMESTAMP";
+
+const ORIGIN_KEYS: [&str; 5] = [SOURCE_KEY, ORIGINAL_FILE_NAME_KEY, ORIGINAL_HASH_KEY, VERSION_KEY, TIMESTAMP_KEY];
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+/// Where a file came from, and when and by which version of the cleaner it was processed
+pub struct Origin {
+    /// Archive or directory the file was found in
+    source: String,
+    original_file_name: String,
+    /// Hash of the whole original file, tags included
+    original_hash: String,
+    version: String,
+    timestamp: String,
+}
+
+impl Origin {
+
+    /// The origin of a file being processed now, keeping the source, original file name and hash recorded by an earlier
+    /// run, so that they still describe the file as it was first found
+    pub fn new(source: &Path, path: &Path, data: &[u8], tagged_file: &TaggedFile) -> Origin {
+        let source = source.canonicalize().unwrap_or_else(|_| source.to_path_buf());
+        let original_file_name = path.file_name().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+        let original_hash = Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+
+        let recorded = |key: &str| tagged_file.tags().iter().find_map(|tag| get_item_text(tag, key));
+
+        Origin {
+            source: recorded(SOURCE_KEY).unwrap_or_else(|| source.to_string_lossy().to_string()),
+            original_file_name: recorded(ORIGINAL_FILE_NAME_KEY).unwrap_or(original_file_name),
+            original_hash: recorded(ORIGINAL_HASH_KEY).unwrap_or(original_hash),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            timestamp: format_timestamp(SystemTime::now())
+        }
+    }
+
+    /// Add the origin to a tag, replacing any items recorded by an earlier run
+    pub fn add_to_tag(&self, tag: &mut Tag) {
+        let values = [&self.source, &self.original_file_name, &self.original_hash, &self.version, &self.timestamp];
+        for (key, value) in ORIGIN_KEYS.iter().zip(values) {
+            // Lofty's checked insert drops keys that have no mapping for the tag type
+            tag.insert_item_unchecked(TagItem::new(ItemKey::Unknown(key.to_string()), ItemValue::Text(value.to_owned())));
+        }
+    }
+}
+
+/// Whether a key is one of the origin items, which are written by the cleaner, so never carried over from the source tags
+pub fn is_origin_key(key: &ItemKey) -> bool {
+    match key {
+        ItemKey::Unknown(key) => ORIGIN_KEYS.iter().any(|k| k.eq_ignore_ascii_case(key)),
+        _ => false
+    }
+}
+
+/// Formats differ in the case of keys they have no mapping for
+fn get_item_text(tag: &Tag, key: &str) -> Option<String> {
+    tag.items()
+        .iter()
+        .filter(|item| matches!(item.key(), ItemKey::Unknown(k) if k.eq_ignore_ascii_case(key)))
+        .find_map(|item| item.value().text())
+        .map(|s| s.to_string())
+}
+
+/// A time as an RFC 3339 timestamp in UTC, e.g. 2024-05-01T12:30:00Z
+fn format_timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let (year, month, day) = get_civil_date(seconds / SECONDS_PER_DAY);
+    let seconds_of_day = seconds % SECONDS_PER_DAY;
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, seconds_of_day / 3600, seconds_of_day % 3600 / 60, seconds_of_day % 60)
+}
+
+/// Year, month and day of a number of days since 1970-01-01, see https://howardhinnant.github.io/date_algorithms.html
+fn get_civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, fs, path::PathBuf};
+
+    use tempfile::Builder;
+
+    use crate::{audio_file_meta::{AudioFileMeta, AudioFileType}, options::Options, provenance::Provenance, tagger::{get_tagged_file, write_tagged_file, AlbumTags, TrackTags}};
+
+    use super::*;
+
+    // MPEG-1 layer III, 128kbps, 44.1kHz, joint stereo
+    const FRAME_HEADER: [u8; 4] = [0xff, 0xfb, 0x90, 0x40];
+    const FRAME_LENGTH: usize = 417;
+
+    fn mp3_data() -> Vec<u8> {
+        let mut frame = vec![0u8; FRAME_LENGTH];
+        frame[..4].copy_from_slice(&FRAME_HEADER);
+        frame.repeat(20)
+    }
+
+    /// A FLAC stream with only its stream information, of 16 bit stereo at 44.1kHz, before its audio frames
+    fn flac_data() -> Vec<u8> {
+        let mut data = b"fLaC\x80\x00\x00\x22\x10\x00\x10\x00\0\0\0\0\0\0".to_vec();
+        data.extend_from_slice(&((44100u64 << 44) | (1 << 41) | (15 << 36)).to_be_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(b"\xff\xf8audio frames");
+        data
+    }
+
+    /// Write a file with new tags recording the given origin source, as the cleaner does
+    fn write_with_origin(source_path: &PathBuf, target_path: &PathBuf, origin_source: &Path, audio_file_type: AudioFileType) {
+        let meta = AudioFileMeta {
+            tagged_file: get_tagged_file(source_path).unwrap(),
+            album_artist_name: None,
+            artist_names: vec!["Artist".to_string()],
+            album_title: Some("Album".to_string()),
+            year: None,
+            track_number: Some(1),
+            disc_number: None,
+            track_title: Some("Title".to_string()),
+            genres: Vec::new(),
+            catalogue_number: None,
+            tag_fields: BTreeMap::new(),
+            audio_file_type: Some(audio_file_type),
+            provenance: Provenance::default()
+        };
+        let album = AlbumTags { default_year: None, default_genres: Vec::new(), total_tracks: 1, cover_image: None, overrides: Vec::new() };
+        let track = TrackTags { origin_source: Some(origin_source), ..TrackTags::default() };
+        write_tagged_file(source_path, target_path, &meta, &album, &track, &Options::default()).unwrap();
+    }
+
+    fn read_origin_item(path: &PathBuf, key: &str) -> Option<String> {
+        get_tagged_file(path).unwrap().tags().iter().find_map(|tag| get_item_text(tag, key))
+    }
+
+    /// The origin of a file survives being written and read back, and being cleaned again from somewhere else
+    fn assert_origin_round_trip(extension: &str, data: &[u8], audio_file_type: AudioFileType) {
+        let dir = Builder::new().prefix("cleaner").tempdir().unwrap();
+        let (first_source, second_source) = (dir.path().join("Downloads"), dir.path().join("Library"));
+        fs::create_dir(&first_source).unwrap();
+        fs::create_dir(&second_source).unwrap();
+
+        let source_path = first_source.join(format!("Original.{}", extension));
+        let target_path = second_source.join(format!("01 Title.{}", extension));
+        let cleaned_path = dir.path().join(format!("Cleaned again.{}", extension));
+        fs::write(&source_path, data).unwrap();
+        let original_hash = Sha256::digest(data).iter().map(|b| format!("{:02x}", b)).collect::<String>();
+
+        write_with_origin(&source_path, &target_path, &first_source, audio_file_type);
+        let recorded_source = first_source.canonicalize().unwrap().to_string_lossy().to_string();
+        assert_eq!(read_origin_item(&target_path, SOURCE_KEY).as_deref(), Some(recorded_source.as_str()));
+        assert_eq!(read_origin_item(&target_path, ORIGINAL_FILE_NAME_KEY), Some(format!("Original.{}", extension)));
+        assert_eq!(read_origin_item(&target_path, ORIGINAL_HASH_KEY).as_deref(), Some(original_hash.as_str()));
+
+        write_with_origin(&target_path, &cleaned_path, &second_source, audio_file_type);
+        assert_eq!(read_origin_item(&cleaned_path, SOURCE_KEY).as_deref(), Some(recorded_source.as_str()));
+        assert_eq!(read_origin_item(&cleaned_path, ORIGINAL_HASH_KEY).as_deref(), Some(original_hash.as_str()));
+    }
+
+    #[test]
+    fn origin_is_read_back_from_id3v2_txxx_frames() {
+        assert_origin_round_trip("mp3", &mp3_data(), AudioFileType::Mp3);
+    }
+
+    #[test]
+    fn origin_is_read_back_from_vorbis_comments() {
+        assert_origin_round_trip("flac", &flac_data(), AudioFileType::Flac);
+    }
+}
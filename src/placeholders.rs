@@ -0,0 +1,88 @@
+use regex::Regex;
+
+use crate::{error::CleanerResult, name_patterns::compile_patterns};
+
+/// Tag values that stand in for missing information, used when none are configured. Only values that cannot be real
+/// names are matched, as a real name treated as missing is lost.
+const DEFAULT_PLACEHOLDER_PATTERNS: [&str; 4] = [
+    r"(?i)^(?:track|audio\s?track|piste)\s*\d+$",
+    r"(?i)^unknown\s(?:artist|album|title|genre|track)$",
+    r"^<.*>$",
+    r"^\s*$",
+];
+
+/// Patterns for placeholder tag values, e.g. "Track 01" or "Unknown Artist", which are treated as missing
+pub struct Placeholders {
+    patterns: Vec<Regex>,
+}
+
+impl Placeholders {
+
+    pub fn new(patterns: &[String]) -> CleanerResult<Placeholders> {
+        Ok(Placeholders { patterns: compile_patterns(patterns)? })
+    }
+
+    pub fn is_placeholder(&self, value: &str) -> bool {
+        let value = value.trim();
+        self.patterns.iter().any(|pattern| pattern.is_match(value))
+    }
+
+    /// Keep a value only if it is not a placeholder, and, if a file name is given, is not the same as the file name
+    pub fn filter(&self, value: Option<String>, file_stem: Option<&str>) -> Option<String> {
+        value
+            .filter(|s| !self.is_placeholder(s))
+            .filter(|s| !file_stem.is_some_and(|file_stem| s.trim().eq_ignore_ascii_case(file_stem.trim())))
+    }
+}
+
+impl Default for Placeholders {
+    fn default() -> Self {
+        Placeholders {
+            patterns: DEFAULT_PLACEHOLDER_PATTERNS
+                .iter()
+                .map(|p| Regex::new(p).expect("Default pattern must be valid"))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_in_placeholders_are_missing() {
+        let placeholders = Placeholders::default();
+        for value in ["Track 01", "track12", "Audio Track 3", "Piste 7", "Unknown Artist", "unknown album", "<no title>", "", "   "] {
+            assert!(placeholders.filter(Some(value.to_string()), None).is_none(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn real_names_like_placeholders_are_kept() {
+        let placeholders = Placeholders::default();
+        for value in ["Unknown", "Untitled", "Track", "Track 01 (Remix)", "The Unknown Artist", "Unknown Pleasures", "Side A", "01"] {
+            assert_eq!(placeholders.filter(Some(value.to_string()), None).as_deref(), Some(value));
+        }
+    }
+
+    #[test]
+    fn values_equal_to_the_file_name_are_missing_only_when_compared() {
+        let placeholders = Placeholders::default();
+
+        assert!(placeholders.filter(Some("01 first song".to_string()), Some("01 First Song")).is_none());
+        assert_eq!(placeholders.filter(Some("Great Album".to_string()), Some("01 First Song")).as_deref(), Some("Great Album"));
+
+        // Titles are not compared with the file name, as a file is often named after its title
+        assert_eq!(placeholders.filter(Some("First Song".to_string()), None).as_deref(), Some("First Song"));
+    }
+
+    #[test]
+    fn configured_patterns_replace_the_built_in_ones() {
+        let placeholders = Placeholders::new(&["(?i)^untitled$".to_string()]).unwrap();
+
+        assert!(placeholders.filter(Some("Untitled".to_string()), None).is_none());
+        assert_eq!(placeholders.filter(Some("Track 01".to_string()), None).as_deref(), Some("Track 01"));
+        assert!(Placeholders::new(&["(".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use serde::{de::Error, Deserialize, Deserializer};
+
+use crate::{metadata_field::MetadataField, metadata_source::MetadataSource, provenance::Provenance};
+
+/// Order in which the sources of each metadata field are tried, and default values for fields with no other source
+pub struct SourcePrecedence {
+    orders: BTreeMap<MetadataField, Vec<MetadataSource>>,
+    defaults: BTreeMap<MetadataField, String>,
+}
+
+impl SourcePrecedence {
+
+    /// Replace the order of sources and the default value of the given fields, leaving other fields as they were
+    pub fn with(mut self, orders: &BTreeMap<MetadataField, Vec<MetadataSource>>, defaults: &BTreeMap<MetadataField, String>) -> SourcePrecedence {
+        self.orders.extend(orders.iter().map(|(field, sources)| (*field, sources.to_owned())));
+        self.defaults.extend(defaults.iter().map(|(field, value)| (*field, value.to_owned())));
+        self
+    }
+
+    /// Get the value of a field from the first source, in order of precedence, that has one, recording the values of all
+    /// of its sources
+    pub fn resolve<T, F>(&self, field: MetadataField, provenance: &mut Provenance, mut f: F) -> Option<T>
+    where
+        T: ToString,
+        F: FnMut(MetadataSource) -> Option<T>
+    {
+        let mut values = self.sources(field)
+            .iter()
+            .filter_map(|source| f(*source).map(|value| (*source, value)))
+            .collect::<Vec<(MetadataSource, T)>>();
+
+        provenance.record(field, values.iter().map(|(source, value)| (*source, value.to_string())).collect());
+
+        if values.is_empty() {
+            None
+        } else {
+            Some(values.remove(0).1)
+        }
+    }
+
+    pub fn sources(&self, field: MetadataField) -> &[MetadataSource] {
+        self.orders
+            .get(&field)
+            .map(|sources| sources.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Whether any field takes values from the given source
+    pub fn uses(&self, source: MetadataSource) -> bool {
+        self.orders
+            .values()
+            .any(|sources| sources.contains(&source))
+    }
+
+    /// Whether to ask for a value, once per album, when no track of the album has one from its other sources
+    pub fn allows_prompt(&self, field: MetadataField) -> bool {
+        self.sources(field).contains(&MetadataSource::Prompt)
+    }
+
+    pub fn default_value(&self, field: MetadataField) -> Option<&str> {
+        self.defaults
+            .get(&field)
+            .map(|value| value.as_str())
+    }
+}
+
+impl Default for SourcePrecedence {
+    fn default() -> Self {
+        use MetadataSource::{Tag, Path, Filename, Sidecar, Archive, Prompt, Default};
+
+        let orders = [
+            (MetadataField::AlbumArtist, vec![Tag, Sidecar, Path, Filename, Archive, Default]),
+            (MetadataField::Artist, vec![Tag, Sidecar, Path, Filename, Archive, Default]),
+            (MetadataField::Album, vec![Tag, Sidecar, Path, Filename, Archive, Default]),
+            (MetadataField::Year, vec![Tag, Sidecar, Path, Filename, Archive, Default, Prompt]),
+            (MetadataField::Track, vec![Tag, Filename, Default]),
+            (MetadataField::Disc, vec![Tag, Filename, Path, Default]),
+            (MetadataField::Title, vec![Tag, Sidecar, Filename, Default]),
+            (MetadataField::Genre, vec![Tag, Sidecar, Default, Prompt]),
+            (MetadataField::Catalogue, vec![Tag, Sidecar, Archive, Default]),
+        ];
+
+        SourcePrecedence {
+            orders: BTreeMap::from(orders),
+            defaults: BTreeMap::new()
+        }
+    }
+}
+
+/// Parse a command line precedence, e.g. album=path,tag
+pub fn parse_precedence_arg(s: &str) -> Result<(MetadataField, Vec<MetadataSource>), String> {
+    let (field, sources) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected FIELD=SOURCE,... but found '{}'", s))?;
+
+    let field = MetadataField::from_str(field.trim(), true)?;
+    let sources = sources
+        .split(',')
+        .map(|source| MetadataSource::from_str(source.trim(), true))
+        .collect::<Result<Vec<MetadataSource>, String>>()?;
+
+    check_order(field, &sources)?;
+    Ok((field, sources))
+}
+
+/// Read the configured orders of sources, checking each as for the command line
+pub fn deserialize_orders<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BTreeMap<MetadataField, Vec<MetadataSource>>, D::Error> {
+    let orders = BTreeMap::<MetadataField, Vec<MetadataSource>>::deserialize(deserializer)?;
+    for (field, sources) in &orders {
+        check_order(*field, sources).map_err(D::Error::custom)?;
+    }
+
+    Ok(orders)
+}
+
+/// Prompts are made once per album, after every track has been given the values of all of its other sources, so the
+/// prompt can only be the last source
+fn check_order(field: MetadataField, sources: &[MetadataSource]) -> Result<(), String> {
+    match sources.iter().position(|source| *source == MetadataSource::Prompt) {
+        Some(index) if index + 1 < sources.len() => Err(format!("prompt must be the last source of {}", field)),
+        _ => Ok(())
+    }
+}
+
+/// Parse a command line default value, e.g. genre=Electronic
+pub fn parse_default_arg(s: &str) -> Result<(MetadataField, String), String> {
+    let (field, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected FIELD=VALUE but found '{}'", s))?;
+
+    Ok((MetadataField::from_str(field.trim(), true)?, value.trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use MetadataSource::{Tag, Path, Filename, Default, Prompt};
+
+    use super::*;
+
+    /// Resolve a field from the given values of each source, returning the value and the source it was taken from
+    fn resolve(precedence: &SourcePrecedence, field: MetadataField, values: &[(MetadataSource, &str)]) -> (Option<String>, Option<MetadataSource>) {
+        let mut provenance = Provenance::default();
+        let value = precedence.resolve(field, &mut provenance, |source| values
+            .iter()
+            .find(|(s, _)| *s == source)
+            .map(|(_, value)| value.to_string()));
+        (value, provenance.source(field))
+    }
+
+    #[test]
+    fn resolve_prefers_tag_to_filename_by_default() {
+        let precedence = SourcePrecedence::default();
+
+        let (value, source) = resolve(&precedence, MetadataField::Title, &[(Filename, "From file name"), (Tag, "From tag")]);
+        assert_eq!(value.as_deref(), Some("From tag"));
+        assert!(source == Some(Tag));
+
+        let (value, source) = resolve(&precedence, MetadataField::Title, &[(Filename, "From file name")]);
+        assert_eq!(value.as_deref(), Some("From file name"));
+        assert!(source == Some(Filename));
+    }
+
+    #[test]
+    fn resolve_prefers_path_to_tag_when_configured() {
+        let orders = BTreeMap::from([(MetadataField::Album, vec![Path, Tag])]);
+        let precedence = SourcePrecedence::default().with(&orders, &BTreeMap::new());
+
+        let mut provenance = Provenance::default();
+        let value = precedence.resolve(MetadataField::Album, &mut provenance, |source| match source {
+            Tag => Some("From tag"),
+            Path => Some("From path"),
+            _ => None
+        });
+        assert_eq!(value, Some("From path"));
+        assert!(provenance.ignored(MetadataField::Album).iter().map(|(s, v)| (*s, v.as_str())).eq([(Tag, "From tag")]));
+
+        // Fields without a configured order keep the default one
+        assert!(precedence.sources(MetadataField::Title) == [Tag, MetadataSource::Sidecar, Filename, Default]);
+    }
+
+    #[test]
+    fn resolve_falls_back_to_default_last() {
+        let defaults = BTreeMap::from([(MetadataField::Genre, "Electronic".to_string())]);
+        let precedence = SourcePrecedence::default().with(&BTreeMap::new(), &defaults);
+        let default_value = precedence.default_value(MetadataField::Genre).unwrap();
+
+        let (value, source) = resolve(&precedence, MetadataField::Genre, &[(Default, default_value), (Tag, "Rock")]);
+        assert_eq!(value.as_deref(), Some("Rock"));
+        assert!(source == Some(Tag));
+
+        let (value, source) = resolve(&precedence, MetadataField::Genre, &[(Default, default_value)]);
+        assert_eq!(value.as_deref(), Some("Electronic"));
+        assert!(source == Some(Default));
+
+        let (value, source) = resolve(&precedence, MetadataField::Genre, &[]);
+        assert!(value.is_none() && source.is_none());
+    }
+
+    #[test]
+    fn precedence_arg_rejects_prompt_before_other_sources() {
+        assert!(parse_precedence_arg("year=prompt,tag").is_err());
+        assert!(parse_precedence_arg("year=tag,prompt,default").is_err());
+
+        let (field, sources) = parse_precedence_arg("year = tag, default, prompt").unwrap();
+        assert!(field == MetadataField::Year);
+        assert!(sources == [Tag, Default, Prompt]);
+    }
+
+    #[test]
+    fn precedence_arg_rejects_unknown_fields_and_sources() {
+        assert!(parse_precedence_arg("album").is_err());
+        assert!(parse_precedence_arg("colour=tag").is_err());
+        assert!(parse_precedence_arg("album=tag,guess").is_err());
+    }
+
+    #[test]
+    fn default_arg_requires_a_known_field_and_value() {
+        let (field, value) = parse_default_arg(" genre = Electronic ").unwrap();
+        assert!(field == MetadataField::Genre);
+        assert_eq!(value, "Electronic");
+
+        assert!(parse_default_arg("genre").is_err());
+        assert!(parse_default_arg("colour=Blue").is_err());
+    }
+}
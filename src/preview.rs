@@ -0,0 +1,98 @@
+use std::io::{stdin, stdout, Write};
+
+use colored::Colorize;
+
+use crate::{audio_file::AudioFile, media_file::MediaFile, metadata_field::MetadataField, options::Options, precedence::parse_default_arg, tagger::{get_tag_changes, AlbumTags, TrackTags, TagChange}};
+
+/// What to do with an album once the changes to its tags have been shown
+enum PreviewAction {
+    Write,
+    Skip,
+    Edit,
+}
+
+/// Show the changes to the tags of each track of an album, with the values of each track in the same order, then ask
+/// whether to write them, skip the album, or edit values of the album first, which are added to its overrides. Returns
+/// false if the album is skipped.
+pub fn preview_album(audio_files: &Vec<&AudioFile>, tracks: &[TrackTags], album: &mut AlbumTags, options: &Options) -> bool {
+    let name_width = audio_files
+        .iter()
+        .filter_map(|f| f.path().file_name())
+        .map(|s| s.to_string_lossy().chars().count())
+        .max()
+        .unwrap_or(0);
+
+    loop {
+        for (audio_file, track) in audio_files.iter().zip(tracks) {
+            match get_tag_changes(audio_file.path(), audio_file.get_meta(), album, track, options) {
+                Ok(changes) => print_changes(audio_file, &changes, name_width),
+                Err(err) => {
+                    let file_name = audio_file.path().file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+                    println!("   Diff {:name_width$} {} {}", file_name.white().bold(), "ERROR".bright_red().bold(), err.to_string().red());
+                }
+            }
+        }
+
+        match get_action_input() {
+            PreviewAction::Write => return true,
+            PreviewAction::Skip => return false,
+            PreviewAction::Edit => album.overrides.extend(get_edit_input()),
+        }
+    }
+}
+
+fn print_changes(audio_file: &AudioFile, changes: &[TagChange], name_width: usize) {
+    let file_name = audio_file.path().file_name().map(|s| s.to_string_lossy()).unwrap_or_default();
+    if changes.is_empty() {
+        println!("   Diff {:name_width$} {}", file_name.white().bold(), "unchanged".dimmed());
+        return;
+    }
+
+    println!("   Diff {}", file_name.white().bold());
+    for change in changes {
+        let old = change.old.as_deref().unwrap_or("-");
+        let new = change.new.as_deref().unwrap_or("-");
+        println!("        {:12} {} {} {}", change.name.dimmed(), old.red(), "→".dimmed(), new.bright_green());
+    }
+}
+
+/// Ask what to do with the album until one of the actions is given, skipping it if there is no more input, so nothing is
+/// written without confirmation
+fn get_action_input() -> PreviewAction {
+    loop {
+        print!("  {} {}>", "Write".bright_red().bold(), "[y]es/[s]kip/[e]dit".dimmed());
+        stdout().flush().expect("Failed to flush terminal output");
+        match stdin().lines().next() {
+            Some(Ok(line)) => match line.trim().to_lowercase().as_str() {
+                "y" | "yes" => return PreviewAction::Write,
+                "s" | "skip" => return PreviewAction::Skip,
+                "e" | "edit" => return PreviewAction::Edit,
+                _ => println!("        {}", "expected y, s or e".red()),
+            },
+            _ => return PreviewAction::Skip
+        }
+    }
+}
+
+/// Ask for values of the album, as FIELD=VALUE, until an empty line. Track numbers and titles differ by track, so are
+/// not edited here.
+fn get_edit_input() -> Vec<(MetadataField, String)> {
+    let mut overrides = Vec::new();
+    loop {
+        print!("   {} {}>", "Edit".bright_red().bold(), "field=value".dimmed());
+        stdout().flush().expect("Failed to flush terminal output");
+        let line = match stdin().lines().next() {
+            Some(Ok(line)) if !line.trim().is_empty() => line,
+            _ => return overrides
+        };
+
+        match parse_default_arg(&line) {
+            Ok((MetadataField::Track | MetadataField::Title, _)) => println!("        {}", "track and title cannot be edited for an album".red()),
+            Ok((field @ (MetadataField::Year | MetadataField::Disc), value)) if value.parse::<u32>().is_err() => {
+                println!("        {}", format!("{} must be a number", field).red());
+            },
+            Ok(field_value) => overrides.push(field_value),
+            Err(err) => println!("        {}", err.red()),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+
+use crate::{metadata_field::MetadataField, metadata_source::MetadataSource};
+
+/// Which source each metadata field was taken from, and the values of other sources that were ignored
+#[derive(Default)]
+pub struct Provenance {
+    fields: BTreeMap<MetadataField, Vec<(MetadataSource, String)>>,
+}
+
+impl Provenance {
+
+    /// Record the values of every source of a field that had one, in order of precedence
+    pub fn record(&mut self, field: MetadataField, values: Vec<(MetadataSource, String)>) {
+        self.fields.insert(field, values);
+    }
+
+    /// The source that the value of a field was taken from
+    pub fn source(&self, field: MetadataField) -> Option<MetadataSource> {
+        self.fields
+            .get(&field)
+            .and_then(|values| values.first())
+            .map(|(source, _)| *source)
+    }
+
+    /// Whether a source had a value for a field, whether or not it was used
+    pub fn has_value_from(&self, field: MetadataField, source: MetadataSource) -> bool {
+        self.fields
+            .get(&field)
+            .is_some_and(|values| values.iter().any(|(s, _)| *s == source))
+    }
+
+    /// Values from lower precedence sources that were not used
+    pub fn ignored(&self, field: MetadataField) -> &[(MetadataSource, String)] {
+        self.fields
+            .get(&field)
+            .map(|values| values.get(1..).unwrap_or_default())
+            .unwrap_or_default()
+    }
+}
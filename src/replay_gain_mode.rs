@@ -0,0 +1,23 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum ReplayGainMode {
+    /// Do not analyse loudness, keeping any existing ReplayGain tags unless dropped
+    Off,
+    /// Analyse the loudness of every album and write new ReplayGain tags
+    Analyse,
+    /// Keep existing ReplayGain values where every track in the album has them, otherwise analyse
+    Keep,
+}
+
+impl Display for ReplayGainMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayGainMode::Off => f.write_str("off"),
+            ReplayGainMode::Analyse => f.write_str("analyse"),
+            ReplayGainMode::Keep => f.write_str("keep"),
+        }
+    }
+}
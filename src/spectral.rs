@@ -0,0 +1,84 @@
+use std::path::PathBuf;
+
+use crate::{decoder::decode_audio, error::CleanerResult, spectrum::{Stft, mix_to_mono}};
+
+const FRAME_SIZE: usize = 4096;
+
+/// Lowest frequency considered when looking for a lossy encoder's low-pass cutoff
+const MIN_CUTOFF_FREQUENCY: f32 = 10000.0;
+
+/// Width of the bands either side of a candidate cutoff that are compared
+const BAND_WIDTH: f32 = 500.0;
+
+/// Drop in level, in dB, across a cutoff for it to be considered a brick wall low-pass rather than a natural roll off
+const MIN_CUTOFF_DROP: f32 = 25.0;
+
+/// Fraction of the Nyquist frequency below which a cutoff suggests the audio came from a lossy or lower resolution source
+const SUSPECT_CUTOFF_RATIO: f32 = 0.93;
+
+pub struct SpectralAnalysis {
+    cutoff: f32,
+    sample_rate: u32,
+}
+
+impl SpectralAnalysis {
+
+    /// Effective upper frequency limit of the audio in Hz
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff
+    }
+
+    /// Whether the cutoff is low enough that the audio was likely transcoded from a lossy source, or upsampled
+    pub fn is_suspect(&self) -> bool {
+        self.cutoff < self.sample_rate as f32 / 2.0 * SUSPECT_CUTOFF_RATIO
+    }
+}
+
+/// Estimate the effective frequency cutoff of an audio file from its average spectrum
+pub fn analyse_spectrum(path: &PathBuf) -> CleanerResult<SpectralAnalysis> {
+    let mut stft = Stft::new(FRAME_SIZE, FRAME_SIZE);
+    let mut mono = Vec::new();
+    let mut power = vec![0.0f64; FRAME_SIZE / 2 + 1];
+    let mut frames = 0;
+    let mut rate = 0;
+
+    decode_audio(path, |sample_rate, channels, samples| {
+        rate = sample_rate;
+        mono.clear();
+        mix_to_mono(samples, channels, &mut mono);
+        stft.push(&mono, |magnitudes| {
+            for (total, magnitude) in power.iter_mut().zip(magnitudes) {
+                *total += (*magnitude as f64).powi(2);
+            }
+            frames += 1;
+        });
+    })?;
+
+    let levels = power
+        .iter()
+        .map(|p| (10.0 * (p / frames.max(1) as f64 + 1e-20).log10()) as f32)
+        .collect::<Vec<f32>>();
+
+    Ok(SpectralAnalysis {
+        cutoff: find_cutoff(&levels, rate),
+        sample_rate: rate
+    })
+}
+
+/// Find the frequency with the steepest drop in level, if it is steep enough to be a low-pass filter, otherwise the
+/// Nyquist frequency
+fn find_cutoff(levels: &[f32], sample_rate: u32) -> f32 {
+    let nyquist = sample_rate as f32 / 2.0;
+    let bin_width = nyquist / (levels.len() - 1) as f32;
+    let band_bins = ((BAND_WIDTH / bin_width) as usize).max(1);
+    let min_bin = (MIN_CUTOFF_FREQUENCY / bin_width) as usize;
+
+    let mean = |band: &[f32]| band.iter().sum::<f32>() / band.len() as f32;
+
+    (min_bin.max(band_bins)..levels.len().saturating_sub(band_bins))
+        .map(|bin| (bin, mean(&levels[bin - band_bins..bin]) - mean(&levels[bin..bin + band_bins])))
+        .filter(|(_, drop)| *drop >= MIN_CUTOFF_DROP)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(bin, _)| bin as f32 * bin_width)
+        .unwrap_or(nyquist)
+}
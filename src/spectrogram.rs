@@ -0,0 +1,109 @@
+use std::{path::PathBuf, time::Duration};
+
+use image::{RgbImage, Rgb, GenericImage};
+
+use crate::{decoder::decode_audio, error::CleanerResult, spectrum::{Stft, mix_to_mono}};
+
+const FRAME_SIZE: usize = 2048;
+const HOP_SIZE: usize = FRAME_SIZE / 2;
+
+const WIDTH: u32 = 1200;
+const HEIGHT: u32 = 512;
+
+/// Gap between the spectrograms of each track on an album sheet
+const SHEET_GAP: u32 = 8;
+
+/// Level range, in dB relative to full scale, mapped onto the colour scale
+const MIN_LEVEL: f32 = -120.0;
+const MAX_LEVEL: f32 = 0.0;
+
+/// Spacing of the horizontal frequency grid lines, in Hz
+const GRID_FREQUENCY: f32 = 5000.0;
+
+/// Colour scale from silence to full scale
+const COLOURS: [[f32; 3]; 6] = [
+    [0.0, 0.0, 0.0],
+    [0.0, 0.0, 128.0],
+    [128.0, 0.0, 160.0],
+    [224.0, 32.0, 32.0],
+    [255.0, 200.0, 0.0],
+    [255.0, 255.0, 255.0],
+];
+
+/// Render a spectrogram of an audio file, time on the horizontal axis and linear frequency on the vertical axis up to the
+/// Nyquist frequency
+pub fn render_spectrogram(path: &PathBuf, duration: Duration) -> CleanerResult<RgbImage> {
+    let mut stft = Stft::new(FRAME_SIZE, HOP_SIZE);
+    let mut mono = Vec::new();
+    let mut levels = vec![0.0f32; (WIDTH * HEIGHT) as usize];
+    let mut frame = 0;
+    let mut rate = 0;
+    let mut expected_frames = 0.0;
+
+    decode_audio(path, |sample_rate, channels, samples| {
+        if rate == 0 {
+            rate = sample_rate;
+            expected_frames = (duration.as_secs_f64() * sample_rate as f64 / HOP_SIZE as f64).max(1.0);
+        }
+        mono.clear();
+        mix_to_mono(samples, channels, &mut mono);
+        stft.push(&mono, |magnitudes| {
+            // Keep the loudest value where several frames or bins map to the same pixel
+            let x = ((frame as f64 / expected_frames * WIDTH as f64) as u32).min(WIDTH - 1);
+            for (bin, magnitude) in magnitudes.iter().enumerate() {
+                let y = HEIGHT - 1 - (bin as u32 * HEIGHT / magnitudes.len() as u32);
+                let level = &mut levels[(y * WIDTH + x) as usize];
+                *level = level.max(*magnitude);
+            }
+            frame += 1;
+        });
+    })?;
+
+    // Normalise so a full scale sine wave reaches 0dB
+    let scale = 2.0 / (FRAME_SIZE as f32 / 2.0);
+
+    let mut image = RgbImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        let level = 20.0 * (levels[(y * WIDTH + x) as usize] * scale + 1e-10).log10();
+        to_colour(level)
+    });
+
+    if rate > 0 {
+        draw_grid(&mut image, rate);
+    }
+
+    Ok(image)
+}
+
+/// Stack the spectrograms of each track of an album into a single image
+pub fn render_album_sheet(images: &[RgbImage]) -> CleanerResult<RgbImage> {
+    let count = images.len() as u32;
+    let mut sheet = RgbImage::new(WIDTH, count * HEIGHT + count.saturating_sub(1) * SHEET_GAP);
+
+    for (index, image) in images.iter().enumerate() {
+        sheet.copy_from(image, 0, index as u32 * (HEIGHT + SHEET_GAP))?;
+    }
+
+    Ok(sheet)
+}
+
+fn to_colour(level: f32) -> Rgb<u8> {
+    let position = ((level - MIN_LEVEL) / (MAX_LEVEL - MIN_LEVEL)).clamp(0.0, 1.0) * (COLOURS.len() - 1) as f32;
+    let index = (position as usize).min(COLOURS.len() - 2);
+    let fraction = position - index as f32;
+
+    let from = COLOURS[index];
+    let to = COLOURS[index + 1];
+    Rgb([0, 1, 2].map(|c| (from[c] + (to[c] - from[c]) * fraction) as u8))
+}
+
+fn draw_grid(image: &mut RgbImage, sample_rate: u32) {
+    let nyquist = sample_rate as f32 / 2.0;
+    let mut frequency = GRID_FREQUENCY;
+    while frequency < nyquist {
+        let y = HEIGHT - 1 - (frequency / nyquist * HEIGHT as f32) as u32;
+        for x in (0..WIDTH).step_by(4) {
+            image.put_pixel(x, y, Rgb([96, 96, 96]));
+        }
+        frequency += GRID_FREQUENCY;
+    }
+}
@@ -0,0 +1,20 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum SpectrogramMode {
+    /// One spectrogram image per track
+    Tracks,
+    /// A single image for each album, with the spectrogram of every track
+    Album,
+}
+
+impl Display for SpectrogramMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpectrogramMode::Tracks => f.write_str("tracks"),
+            SpectrogramMode::Album => f.write_str("album"),
+        }
+    }
+}
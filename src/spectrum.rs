@@ -0,0 +1,139 @@
+use std::{sync::Arc, f32::consts::PI};
+
+use realfft::{RealFftPlanner, RealToComplex, num_complex::Complex};
+
+/// Short-time Fourier transform over a mono signal, producing the magnitude spectrum of each overlapping, Hann windowed,
+/// frame
+pub struct Stft {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    hop_size: usize,
+    pending: Vec<f32>,
+    input: Vec<f32>,
+    output: Vec<Complex<f32>>,
+    magnitudes: Vec<f32>,
+}
+
+impl Stft {
+
+    pub fn new(frame_size: usize, hop_size: usize) -> Stft {
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(frame_size);
+        let input = fft.make_input_vec();
+        let output = fft.make_output_vec();
+        let magnitudes = vec![0.0; output.len()];
+
+        Stft {
+            fft,
+            window: (0..frame_size).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / frame_size as f32).cos()).collect(),
+            hop_size,
+            pending: Vec::with_capacity(frame_size * 2),
+            input,
+            output,
+            magnitudes
+        }
+    }
+
+    /// Add samples, calling the given function with the magnitude spectrum of each frame completed by those samples
+    pub fn push<F>(&mut self, samples: &[f32], mut f: F)
+    where
+        F: FnMut(&[f32])
+    {
+        let frame_size = self.window.len();
+        self.pending.extend_from_slice(samples);
+
+        let mut start = 0;
+        while self.pending.len() - start >= frame_size {
+            for (i, sample) in self.pending[start..start + frame_size].iter().enumerate() {
+                self.input[i] = sample * self.window[i];
+            }
+            if self.fft.process(&mut self.input, &mut self.output).is_ok() {
+                for (magnitude, value) in self.magnitudes.iter_mut().zip(&self.output) {
+                    *magnitude = value.norm();
+                }
+                f(&self.magnitudes);
+            }
+            start += self.hop_size;
+        }
+
+        self.pending.drain(..start);
+    }
+}
+
+/// Reduces the sample rate of a mono signal, low-pass filtering first so that higher frequencies, which vary most between
+/// encodings, do not alias into the remaining band
+pub struct Downsampler {
+    step: f64,
+    position: f64,
+    filters: Vec<LowPass>,
+}
+
+/// Q factors for the sections of an eighth order Butterworth filter
+const BUTTERWORTH_Q: [f32; 4] = [0.5098, 0.6013, 0.9000, 2.5629];
+
+impl Downsampler {
+
+    pub fn new(sample_rate: u32, target_rate: u32) -> Downsampler {
+        let cutoff = target_rate as f32 * 0.4;
+        Downsampler {
+            step: (sample_rate as f64 / target_rate as f64).max(1.0),
+            position: 0.0,
+            filters: BUTTERWORTH_Q.iter().map(|q| LowPass::new(sample_rate as f32, cutoff, *q)).collect()
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32], output: &mut Vec<f32>) {
+        for sample in samples {
+            let filtered = self.filters
+                .iter_mut()
+                .fold(*sample, |x, filter| filter.process(x));
+            self.position += 1.0;
+            if self.position >= self.step {
+                output.push(filtered);
+                self.position -= self.step;
+            }
+        }
+    }
+}
+
+/// Second order low-pass filter section
+struct LowPass {
+    b: [f32; 3],
+    a: [f32; 2],
+    z: [f32; 2],
+}
+
+impl LowPass {
+
+    fn new(sample_rate: f32, cutoff: f32, q: f32) -> LowPass {
+        let w0 = 2.0 * PI * cutoff / sample_rate;
+        let alpha = w0.sin() / (2.0 * q);
+        let cos = w0.cos();
+        let a0 = 1.0 + alpha;
+
+        LowPass {
+            b: [(1.0 - cos) / 2.0 / a0, (1.0 - cos) / a0, (1.0 - cos) / 2.0 / a0],
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            z: [0.0; 2]
+        }
+    }
+
+    fn process(&mut self, x: f32) -> f32 {
+        let y = self.b[0] * x + self.z[0];
+        self.z[0] = self.b[1] * x - self.a[0] * y + self.z[1];
+        self.z[1] = self.b[2] * x - self.a[1] * y;
+        y
+    }
+}
+
+/// Mix interleaved samples down to a single channel
+pub fn mix_to_mono(samples: &[f32], channels: usize, output: &mut Vec<f32>) {
+    output.extend(samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32));
+}
+
+/// Pitch class (0 is C) of the nearest equal tempered note to a frequency
+pub fn to_pitch_class(frequency: f32) -> usize {
+    let note = 12.0 * (frequency / 440.0).log2() + 69.0;
+    (note.round() as i32).rem_euclid(12) as usize
+}
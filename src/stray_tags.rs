@@ -0,0 +1,259 @@
+use std::{fmt, ops::Range};
+
+use lofty::FileType;
+
+use crate::id3v2::read_synchsafe;
+
+// Tag blocks found before or after the audio data, see https://id3.org/id3v2.4.0-structure,
+// https://wiki.hydrogenaud.io/index.php?title=APEv2_specification and https://id3.org/Lyrics3v2
+
+const ID3V2_HEADER_LENGTH: usize = 10;
+const ID3V2_FOOTER_FLAG: u8 = 0x10;
+const ID3V1_LENGTH: usize = 128;
+const ID3V1_EXTENDED_LENGTH: usize = 227;
+const APE_FOOTER_LENGTH: usize = 32;
+const APE_HEADER_FLAG: u32 = 0x8000_0000;
+const LYRICS3_V1_MAX_LENGTH: usize = 5100;
+const LYRICS3_V2_SIZE_LENGTH: usize = 6;
+
+/// A tag block removed from a file because it is not one of the tags written for its format, or a duplicate of one
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StrayTag {
+    Id3v1,
+    Id3v2,
+    Ape,
+    Lyrics3,
+}
+
+impl fmt::Display for StrayTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StrayTag::Id3v1 => write!(f, "ID3v1"),
+            StrayTag::Id3v2 => write!(f, "ID3v2"),
+            StrayTag::Ape => write!(f, "APE"),
+            StrayTag::Lyrics3 => write!(f, "Lyrics3"),
+        }
+    }
+}
+
+/// Find all tag blocks at the start and end of an MP3 or FLAC file, as the tags are written again, returning the range
+/// of the file between them, and those tags that would not have been written, i.e. APE and Lyrics3 tags, ID3 tags of
+/// FLAC files and repeated ID3 tags of MP3 files
+pub fn find_stray_tags(data: &[u8], file_type: FileType) -> (Range<usize>, Vec<StrayTag>) {
+    // ID3 tags are native to MP3 files, other formats of file have no use for them
+    let has_id3 = match file_type {
+        FileType::MPEG => true,
+        FileType::FLAC => false,
+        _ => return (0..data.len(), Vec::new())
+    };
+
+    let mut start = 0;
+    let mut end = data.len();
+    let mut stray_tags = Vec::new();
+
+    let mut id3v2_count = 0;
+    while let Some(length) = get_id3v2_length(&data[start..end]) {
+        id3v2_count += 1;
+        if id3v2_count > 1 || !has_id3 {
+            stray_tags.push(StrayTag::Id3v2);
+        }
+        start += length;
+    }
+
+    // Tags are appended in any order, so keep going from the end until the audio data is reached
+    let mut id3v1_count = 0;
+    loop {
+        let block = &data[start..end];
+        let (stray_tag, length) = if let Some(length) = get_id3v1_length(block) {
+            id3v1_count += 1;
+            (Some(StrayTag::Id3v1).filter(|_| id3v1_count > 1 || !has_id3), length)
+        } else if let Some(length) = get_ape_length(block) {
+            (Some(StrayTag::Ape), length)
+        } else if let Some(length) = get_lyrics3_length(block) {
+            (Some(StrayTag::Lyrics3), length)
+        } else if let Some(length) = get_appended_id3v2_length(block) {
+            (Some(StrayTag::Id3v2), length)
+        } else {
+            break;
+        };
+
+        stray_tags.extend(stray_tag);
+        end -= length;
+    }
+
+    (start..end, stray_tags)
+}
+
+/// Length of an ID3v2 tag at the start of the data, including its header and any footer
+pub fn get_id3v2_length(data: &[u8]) -> Option<usize> {
+    if data.len() < ID3V2_HEADER_LENGTH || !data.starts_with(b"ID3") {
+        return None;
+    }
+
+    let footer_length = if data[5] & ID3V2_FOOTER_FLAG != 0 { ID3V2_HEADER_LENGTH } else { 0 };
+    let length = ID3V2_HEADER_LENGTH + read_synchsafe(&data[6..10])? + footer_length;
+    Some(length).filter(|length| *length <= data.len())
+}
+
+/// An ID3v2 tag at the end of a file, which can only be found by its footer
+fn get_appended_id3v2_length(data: &[u8]) -> Option<usize> {
+    if data.len() < ID3V2_HEADER_LENGTH * 2 {
+        return None;
+    }
+
+    let footer = &data[data.len() - ID3V2_HEADER_LENGTH..];
+    if !footer.starts_with(b"3DI") {
+        return None;
+    }
+
+    let length = ID3V2_HEADER_LENGTH * 2 + read_synchsafe(&footer[6..10])?;
+    Some(length).filter(|length| *length <= data.len() && data[data.len() - length..].starts_with(b"ID3"))
+}
+
+/// An ID3v1 tag, with the extended tag in front of it if there is one
+fn get_id3v1_length(data: &[u8]) -> Option<usize> {
+    if data.len() < ID3V1_LENGTH || !data[data.len() - ID3V1_LENGTH..].starts_with(b"TAG") {
+        return None;
+    }
+
+    let extended_length = ID3V1_LENGTH + ID3V1_EXTENDED_LENGTH;
+    if data.len() >= extended_length && data[data.len() - extended_length..].starts_with(b"TAG+") {
+        return Some(extended_length);
+    }
+
+    Some(ID3V1_LENGTH)
+}
+
+/// An APE tag, found by its footer, with the header in front of it if there is one
+fn get_ape_length(data: &[u8]) -> Option<usize> {
+    if data.len() < APE_FOOTER_LENGTH {
+        return None;
+    }
+
+    let footer = &data[data.len() - APE_FOOTER_LENGTH..];
+    if !footer.starts_with(b"APETAGEX") {
+        return None;
+    }
+
+    let size = u32::from_le_bytes(footer[12..16].try_into().ok()?) as usize;
+    let flags = u32::from_le_bytes(footer[20..24].try_into().ok()?);
+    let header_length = if flags & APE_HEADER_FLAG != 0 { APE_FOOTER_LENGTH } else { 0 };
+    Some(size + header_length).filter(|length| *length >= APE_FOOTER_LENGTH && *length <= data.len())
+}
+
+/// A Lyrics3 block, either version 2, which gives its size, or version 1, which has to be searched for
+fn get_lyrics3_length(data: &[u8]) -> Option<usize> {
+    if data.ends_with(b"LYRICS200") {
+        let size_end = data.len().checked_sub(b"LYRICS200".len())?;
+        let size_start = size_end.checked_sub(LYRICS3_V2_SIZE_LENGTH)?;
+        let size = std::str::from_utf8(&data[size_start..size_end]).ok()?.parse::<usize>().ok()?;
+        let length = size + LYRICS3_V2_SIZE_LENGTH + b"LYRICS200".len();
+        return Some(length).filter(|length| *length <= data.len() && data[data.len() - length..].starts_with(b"LYRICSBEGIN"));
+    }
+
+    if data.ends_with(b"LYRICSEND") {
+        let search_start = data.len().saturating_sub(LYRICS3_V1_MAX_LENGTH + b"LYRICSEND".len());
+        return data[search_start..]
+            .windows(b"LYRICSBEGIN".len())
+            .rposition(|w| w == b"LYRICSBEGIN")
+            .map(|position| data.len() - search_start - position);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUDIO: &[u8] = &[0xff, 0xfb, 0x90, 0x40, 1, 2, 3, 4, 5, 6, 7, 8];
+
+    fn synchsafe(size: usize) -> [u8; 4] {
+        [(size >> 21) as u8 & 0x7f, (size >> 14) as u8 & 0x7f, (size >> 7) as u8 & 0x7f, size as u8 & 0x7f]
+    }
+
+    /// An ID3v2.4 tag of padding only, with a footer if it is to be appended
+    fn id3v2(body_length: usize, footer: bool) -> Vec<u8> {
+        let flags = if footer { ID3V2_FOOTER_FLAG } else { 0 };
+        let mut tag = [b"ID3".as_slice(), &[4, 0, flags], &synchsafe(body_length)].concat();
+        tag.resize(ID3V2_HEADER_LENGTH + body_length, 0);
+        if footer {
+            tag.extend_from_slice(&[b"3DI".as_slice(), &[4, 0, flags], &synchsafe(body_length)].concat());
+        }
+        tag
+    }
+
+    fn id3v1() -> Vec<u8> {
+        let mut tag = b"TAG".to_vec();
+        tag.resize(ID3V1_LENGTH, b' ');
+        tag
+    }
+
+    /// An APEv2 tag with a header, whose size covers the items and footer
+    fn ape(items: &[u8]) -> Vec<u8> {
+        let header_or_footer = |is_header: bool| {
+            let flags = APE_HEADER_FLAG | if is_header { 0x2000_0000 } else { 0 };
+            [b"APETAGEX".as_slice(), &2000u32.to_le_bytes(), &((items.len() + APE_FOOTER_LENGTH) as u32).to_le_bytes(), &1u32.to_le_bytes(), &flags.to_le_bytes(), &[0; 8]].concat()
+        };
+        [header_or_footer(true), items.to_vec(), header_or_footer(false)].concat()
+    }
+
+    fn lyrics3_v2(fields: &[u8]) -> Vec<u8> {
+        let content = [b"LYRICSBEGIN".as_slice(), fields].concat();
+        [content.as_slice(), format!("{:06}", content.len()).as_bytes(), b"LYRICS200"].concat()
+    }
+
+    #[test]
+    fn mp3_keeps_first_id3_tags_and_strips_ape_and_lyrics3() {
+        let data = [id3v2(20, false), AUDIO.to_vec(), ape(b"items"), lyrics3_v2(b"IND0000210"), id3v1()].concat();
+
+        let (range, stray_tags) = find_stray_tags(&data, FileType::MPEG);
+
+        assert_eq!(&data[range], AUDIO);
+        assert_eq!(stray_tags, [StrayTag::Lyrics3, StrayTag::Ape]);
+    }
+
+    #[test]
+    fn mp3_strips_repeated_and_appended_id3_tags() {
+        let data = [id3v2(20, false), id3v2(5, false), AUDIO.to_vec(), id3v2(8, true), id3v1(), id3v1()].concat();
+
+        let (range, stray_tags) = find_stray_tags(&data, FileType::MPEG);
+
+        assert_eq!(&data[range], AUDIO);
+        assert_eq!(stray_tags, [StrayTag::Id3v2, StrayTag::Id3v1, StrayTag::Id3v2]);
+    }
+
+    #[test]
+    fn flac_strips_all_id3_tags_and_lyrics3_v1() {
+        let flac = [b"fLaC".as_slice(), AUDIO].concat();
+        let data = [id3v2(20, false), flac.clone(), b"LYRICSBEGINsome lyricsLYRICSEND".to_vec(), id3v1()].concat();
+
+        let (range, stray_tags) = find_stray_tags(&data, FileType::FLAC);
+
+        assert_eq!(&data[range], flac.as_slice());
+        assert_eq!(stray_tags, [StrayTag::Id3v2, StrayTag::Id3v1, StrayTag::Lyrics3]);
+    }
+
+    #[test]
+    fn invalid_tags_are_left_as_audio() {
+        // An APE footer claiming more than the file, and a Lyrics3 size that does not lead back to its start
+        let mut ape_tag = ape(b"items");
+        let footer_start = ape_tag.len() - APE_FOOTER_LENGTH;
+        ape_tag[footer_start + 12..footer_start + 16].copy_from_slice(&10_000u32.to_le_bytes());
+        let mut lyrics = lyrics3_v2(b"IND0000210");
+        lyrics[0] = b'X';
+
+        for data in [[AUDIO, &ape_tag].concat(), [AUDIO, &lyrics].concat(), AUDIO.to_vec()] {
+            let (range, stray_tags) = find_stray_tags(&data, FileType::MPEG);
+            assert_eq!(range, 0..data.len());
+            assert!(stray_tags.is_empty());
+        }
+    }
+
+    #[test]
+    fn other_formats_are_left_alone() {
+        let data = [id3v2(20, false), AUDIO.to_vec(), ape(b"items")].concat();
+
+        assert_eq!(find_stray_tags(&data, FileType::Opus), (0..data.len(), Vec::new()));
+    }
+}
@@ -0,0 +1,189 @@
+use std::{path::{PathBuf, Path}, fs, ops::Range};
+
+use lofty::FileType;
+
+use crate::{error::{CleanerResult, CleanerError}, stray_tags::find_stray_tags, flac::get_metadata_length};
+
+/// Extension added to the name of an audio file for the backup of its original tags
+const BACKUP_EXTENSION: &str = "tags";
+
+/// Extension added to the name of an audio file while it is being rewritten, so it is never left half written
+const TEMPORARY_EXTENSION: &str = "cleaner-tmp";
+
+/// Bytes used for the length of the leading tags in a backup
+const LENGTH_BYTES: usize = 8;
+
+/// The backup of the tags of a file, in a backup directory laid out like the directory the file is in
+pub fn get_backup_path(root_path: &Path, path: &Path, backup_root_path: &Path) -> PathBuf {
+    let relative_path = path.strip_prefix(root_path).unwrap_or(path);
+    let mut file_name = relative_path.as_os_str().to_owned();
+    file_name.push(".");
+    file_name.push(BACKUP_EXTENSION);
+    backup_root_path.join(file_name)
+}
+
+/// Back up the tags of a file, i.e. everything before and after its audio frames, unless there is a backup already, so
+/// that the backup is always of the tags the file had before it was first fixed. Returns whether a backup was written.
+pub fn backup_tags(path: &Path, backup_path: &Path) -> CleanerResult<bool> {
+    if backup_path.exists() {
+        return Ok(false);
+    }
+
+    let data = fs::read(path)?;
+    let audio_range = get_audio_range(&data, get_file_type(path)?)?;
+
+    let mut backup = Vec::with_capacity(data.len() - audio_range.len() + LENGTH_BYTES);
+    backup.extend_from_slice(&(audio_range.start as u64).to_be_bytes());
+    backup.extend_from_slice(&data[..audio_range.start]);
+    backup.extend_from_slice(&data[audio_range.end..]);
+
+    if let Some(parent) = backup_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(backup_path, backup)?;
+
+    Ok(true)
+}
+
+/// Put the backed up tags back around the current audio frames of a file, and remove the backup
+pub fn restore_tags(path: &Path, backup_path: &Path) -> CleanerResult<()> {
+    let backup = fs::read(backup_path)?;
+    let length = backup.get(..LENGTH_BYTES).ok_or(CleanerError::InvalidTagBackup)?;
+    let head_end = LENGTH_BYTES + u64::from_be_bytes(length.try_into().expect("Must be 8 bytes")) as usize;
+    let head = backup.get(LENGTH_BYTES..head_end).ok_or(CleanerError::InvalidTagBackup)?;
+    let tail = &backup[head_end..];
+
+    let data = fs::read(path)?;
+    let audio_range = get_audio_range(&data, get_file_type(path)?)?;
+
+    let mut output = Vec::with_capacity(head.len() + audio_range.len() + tail.len());
+    output.extend_from_slice(head);
+    output.extend_from_slice(&data[audio_range]);
+    output.extend_from_slice(tail);
+    replace_file(path, &output)?;
+
+    fs::remove_file(backup_path)?;
+    Ok(())
+}
+
+/// The path of the audio file that a backup is of
+pub fn get_backed_up_path(backup_root_path: &Path, backup_path: &Path, root_path: &Path) -> Option<PathBuf> {
+    let relative_path = backup_path.strip_prefix(backup_root_path).ok()?;
+    let file_name = relative_path.to_str()?.strip_suffix(&format!(".{}", BACKUP_EXTENSION))?;
+    Some(root_path.join(file_name))
+}
+
+pub fn is_backup(path: &Path) -> bool {
+    path.extension().is_some_and(|e| e == BACKUP_EXTENSION)
+}
+
+/// Write a file by writing a temporary file next to it, then renaming it over the original
+fn replace_file(path: &Path, data: &[u8]) -> CleanerResult<()> {
+    let temporary_path = get_temporary_path(path);
+    fs::write(&temporary_path, data)?;
+    fs::rename(&temporary_path, path)?;
+    Ok(())
+}
+
+pub fn get_temporary_path(path: &Path) -> PathBuf {
+    let mut file_name = path.as_os_str().to_owned();
+    file_name.push(".");
+    file_name.push(TEMPORARY_EXTENSION);
+    PathBuf::from(file_name)
+}
+
+/// The audio frames of a file, without any of the tags or, for FLAC, metadata blocks around them
+fn get_audio_range(data: &[u8], file_type: FileType) -> CleanerResult<Range<usize>> {
+    let (audio_range, _) = find_stray_tags(data, file_type);
+    match file_type {
+        FileType::MPEG => Ok(audio_range),
+        FileType::FLAC => Ok(audio_range.start + get_metadata_length(&data[audio_range.clone()])?..audio_range.end),
+        _ => Err(CleanerError::UnexpectedFileExtension)
+    }
+}
+
+fn get_file_type(path: &Path) -> CleanerResult<FileType> {
+    FileType::from_path(path).ok_or(CleanerError::UnexpectedFileExtension)
+}
+
+#[cfg(test)]
+mod tests {
+    use lofty::ogg::VorbisComments;
+    use tempfile::Builder;
+
+    use crate::flac::build_flac_file;
+
+    use super::*;
+
+    /// An MPEG frame header, followed by the rest of the frame
+    const AUDIO: &[u8] = &[0xff, 0xfb, 0x90, 0x40, 1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// An ID3v2.4 tag of a single text frame
+    fn id3v2_tag(title: &str) -> Vec<u8> {
+        let mut frame = b"TIT2".to_vec();
+        frame.extend_from_slice(&[0, 0, 0, title.len() as u8 + 1, 0, 0, 3]);
+        frame.extend_from_slice(title.as_bytes());
+
+        let mut tag = vec![b'I', b'D', b'3', 4, 0, 0, 0, 0, 0, frame.len() as u8];
+        tag.extend_from_slice(&frame);
+        tag
+    }
+
+    fn id3v1_tag(title: &str) -> Vec<u8> {
+        let mut tag = vec![0; 128];
+        tag[..3].copy_from_slice(b"TAG");
+        tag[3..3 + title.len()].copy_from_slice(title.as_bytes());
+        tag
+    }
+
+    /// A FLAC stream with only its stream information, of 16 bit stereo at 44.1kHz, before the given audio frames
+    fn flac_stream(audio: &[u8]) -> Vec<u8> {
+        let mut data = b"fLaC\x80\x00\x00\x22\x10\x00\x10\x00\0\0\0\0\0\0".to_vec();
+        data.extend_from_slice(&((44100u64 << 44) | (1 << 41) | (15 << 36)).to_be_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(audio);
+        data
+    }
+
+    /// Back up the tags of a file, replace them with fixed ones, then revert them, checking that the original file is back
+    fn assert_round_trip(extension: &str, original: &[u8], fixed: &[u8]) {
+        let dir = Builder::new().prefix("cleaner").tempdir().unwrap();
+        let path = dir.path().join("Music").join(format!("Track.{}", extension));
+        let backup_root_path = dir.path().join("Backup");
+        fs::create_dir(path.parent().unwrap()).unwrap();
+        fs::write(&path, original).unwrap();
+
+        let backup_path = get_backup_path(&dir.path().join("Music"), &path, &backup_root_path);
+        assert!(backup_tags(&path, &backup_path).unwrap());
+        fs::write(&path, fixed).unwrap();
+
+        // A second fix keeps the backup of the original tags
+        assert!(!backup_tags(&path, &backup_path).unwrap());
+        assert_eq!(get_backed_up_path(&backup_root_path, &backup_path, &dir.path().join("Music")), Some(path.to_owned()));
+
+        restore_tags(&path, &backup_path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), original);
+        assert!(!backup_path.exists());
+        assert!(!get_temporary_path(&path).exists());
+    }
+
+    #[test]
+    fn mp3_tags_are_restored_byte_for_byte() {
+        let original = [id3v2_tag("Original"), AUDIO.to_vec(), id3v1_tag("Original")].concat();
+        let fixed = [id3v2_tag("A fixed title"), AUDIO.to_vec()].concat();
+
+        assert_round_trip("mp3", &original, &fixed);
+    }
+
+    #[test]
+    fn flac_metadata_is_restored_byte_for_byte() {
+        let mut comments = VorbisComments::default();
+        comments.insert("TITLE".to_string(), "Original".to_string(), false);
+        let original = [id3v2_tag("Stray"), build_flac_file(&flac_stream(AUDIO), &comments, 32).unwrap()].concat();
+
+        comments.insert("TITLE".to_string(), "A fixed title".to_string(), true);
+        let fixed = build_flac_file(&original[id3v2_tag("Stray").len()..], &comments, 1024).unwrap();
+
+        assert_round_trip("flac", &original, &fixed);
+    }
+}
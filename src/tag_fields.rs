@@ -0,0 +1,248 @@
+use std::collections::BTreeMap;
+
+use lofty::{ItemKey, ItemValue, Tag, TagItem, TagType};
+use serde::{Deserialize, Deserializer};
+
+use crate::tag_items::is_same_key;
+
+/// Names of the fields the cleaner reads and writes itself, whose keys can be configured like those of custom fields
+pub const ALBUM_ARTIST_FIELD: &str = "album-artist";
+pub const CATALOGUE_FIELD: &str = "catalogue";
+pub const INITIAL_KEY_FIELD: &str = "initial-key";
+
+const BUILT_IN_FIELDS: [&str; 3] = [ALBUM_ARTIST_FIELD, CATALOGUE_FIELD, INITIAL_KEY_FIELD];
+
+/// Keys of a field in each tag format. The first key of a format is written, and all of them are read, in order.
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FieldKeys {
+    /// Frame IDs, e.g. TMED, or descriptions of TXXX frames, e.g. RELEASETYPE
+    #[serde(deserialize_with = "one_or_many")]
+    id3v2: Vec<String>,
+    /// Field names, e.g. RELEASETYPE
+    #[serde(deserialize_with = "one_or_many")]
+    vorbis: Vec<String>,
+    /// Atom names, or freeform atoms, e.g. ----:com.apple.iTunes:RELEASETYPE
+    #[serde(deserialize_with = "one_or_many")]
+    mp4: Vec<String>,
+}
+
+impl FieldKeys {
+
+    fn new(id3v2: &[&str], vorbis: &[&str], mp4: &[&str]) -> FieldKeys {
+        let to_vec = |keys: &[&str]| keys.iter().map(|k| k.to_string()).collect();
+        FieldKeys { id3v2: to_vec(id3v2), vorbis: to_vec(vorbis), mp4: to_vec(mp4) }
+    }
+
+    /// Item keys for a tag type, none for formats without a key, e.g. ID3v1
+    fn item_keys(&self, tag_type: TagType) -> Vec<ItemKey> {
+        let keys = match tag_type {
+            TagType::ID3v2 => &self.id3v2,
+            TagType::VorbisComments => &self.vorbis,
+            TagType::MP4ilst => &self.mp4,
+            _ => return Vec::new()
+        };
+
+        keys.iter().map(|key| ItemKey::from_key(tag_type, key)).collect()
+    }
+}
+
+/// Mapping of logical field names, e.g. catalogue or release-type, to the keys of each tag format, used for both reading
+/// and writing tags
+pub struct TagFields {
+    fields: BTreeMap<String, FieldKeys>,
+}
+
+impl TagFields {
+
+    /// Replace the keys of the given fields, or add them as custom fields, leaving other fields as they were
+    pub fn with(mut self, fields: &BTreeMap<String, FieldKeys>) -> TagFields {
+        self.fields.extend(fields.iter().map(|(name, keys)| (name.to_lowercase(), keys.to_owned())));
+        self
+    }
+
+    /// Value of a field from the first of its keys for the type of the tag that has one
+    pub fn read(&self, name: &str, tag: &Tag) -> Option<String> {
+        let field_keys = self.fields.get(name)?;
+        field_keys
+            .item_keys(tag.tag_type())
+            .iter()
+            .find_map(|key| get_item_text(tag, key))
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+    }
+
+    /// Whether a key is one of the keys of a field for a tag type
+    pub fn has_key(&self, name: &str, tag_type: TagType, key: &ItemKey) -> bool {
+        self.fields
+            .get(name)
+            .is_some_and(|field_keys| field_keys.item_keys(tag_type).iter().any(|k| is_same_key(k, key)))
+    }
+
+    /// Values of the configured fields other than those the cleaner reads itself
+    pub fn read_custom(&self, tag: &Tag) -> BTreeMap<String, String> {
+        self.fields
+            .keys()
+            .filter(|name| !BUILT_IN_FIELDS.contains(&name.as_str()))
+            .filter_map(|name| self.read(name, tag).map(|value| (name.to_owned(), value)))
+            .collect()
+    }
+
+    /// Write the value of a field with the first of its keys for the type of the tag, replacing the values of all of its
+    /// keys, e.g. those carried over from the source tags
+    pub fn write(&self, name: &str, tag: &mut Tag, value: String) {
+        let item_keys = match self.fields.get(name) {
+            Some(field_keys) => field_keys.item_keys(tag.tag_type()),
+            None => return
+        };
+
+        if let Some(write_key) = item_keys.first() {
+            tag.retain_items(|item| !item_keys.iter().any(|key| is_same_key(key, item.key())));
+            // Lofty's checked insert drops keys that have no mapping for the tag type, e.g. TXXX descriptions
+            tag.insert_item_unchecked(TagItem::new(write_key.to_owned(), ItemValue::Text(value)));
+        }
+    }
+}
+
+impl Default for TagFields {
+    fn default() -> Self {
+        let fields = [
+            (ALBUM_ARTIST_FIELD, FieldKeys::new(&["TPE2"], &["ALBUMARTIST", "ALBUM ARTIST"], &["aART"])),
+            (CATALOGUE_FIELD, FieldKeys::new(&["CATALOGNUMBER"], &["CATALOGNUMBER"], &["----:com.apple.iTunes:CATALOGNUMBER"])),
+            (INITIAL_KEY_FIELD, FieldKeys::new(&["TKEY"], &["INITIALKEY", "KEY"], &["----:com.apple.iTunes:initialkey"])),
+        ];
+
+        TagFields {
+            fields: fields.into_iter().map(|(name, keys)| (name.to_string(), keys)).collect()
+        }
+    }
+}
+
+fn get_item_text<'a>(tag: &'a Tag, key: &ItemKey) -> Option<&'a str> {
+    tag.items()
+        .iter()
+        .filter(|item| is_same_key(key, item.key()))
+        .find_map(|item| item.value().text())
+}
+
+/// Keys may be given as a single key or a list of keys
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(key) => vec![key],
+        OneOrMany::Many(keys) => keys,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use lofty::{TagExt, TaggedFileExt, id3::v2::ID3v2Tag, ogg::VorbisComments};
+    use tempfile::{Builder, TempDir};
+
+    use crate::{audio_file::AudioFile, flac::build_flac_file, options::Options, tagger::{get_tagged_file, write_tagged_file, AlbumTags, TrackTags}};
+
+    use super::*;
+
+    const CONFIG: &str = r#"
+        [tag-fields.release-type]
+        id3v2 = "RELEASETYPE"
+        vorbis = ["RELEASETYPE", "RELEASE TYPE"]
+
+        [tag-fields.media]
+        id3v2 = "TMED"
+        vorbis = "MEDIA"
+    "#;
+
+    // MPEG-1 layer III, 128kbps, 44.1kHz, joint stereo
+    const FRAME_HEADER: [u8; 4] = [0xff, 0xfb, 0x90, 0x40];
+    const FRAME_LENGTH: usize = 417;
+
+    fn configured_fields() -> TagFields {
+        let config = toml::from_str::<BTreeMap<String, BTreeMap<String, FieldKeys>>>(CONFIG).unwrap();
+        TagFields::default().with(&config["tag-fields"])
+    }
+
+    /// An MP3 file of silent frames, with an ID3v2 tag of the given items
+    fn tagged_mp3(items: &[(&str, &str)]) -> Vec<u8> {
+        let mut tag = Tag::new(TagType::ID3v2);
+        for (key, value) in items {
+            tag.insert_item_unchecked(TagItem::new(ItemKey::from_key(TagType::ID3v2, key), ItemValue::Text(value.to_string())));
+        }
+        let mut data = Vec::new();
+        ID3v2Tag::from(tag).dump_to(&mut data).unwrap();
+
+        let mut frame = vec![0u8; FRAME_LENGTH];
+        frame[..4].copy_from_slice(&FRAME_HEADER);
+        data.extend_from_slice(&frame.repeat(20));
+        data
+    }
+
+    /// A FLAC file with only its stream information, of 16 bit stereo at 44.1kHz, and Vorbis comments of the given items
+    fn tagged_flac(items: &[(&str, &str)]) -> Vec<u8> {
+        let mut data = b"fLaC\x80\x00\x00\x22\x10\x00\x10\x00\0\0\0\0\0\0".to_vec();
+        data.extend_from_slice(&((44100u64 << 44) | (1 << 41) | (15 << 36)).to_be_bytes());
+        data.extend_from_slice(&[0; 16]);
+        data.extend_from_slice(b"\xff\xf8audio frames");
+
+        let mut comments = VorbisComments::default();
+        for (key, value) in items {
+            comments.insert(key.to_string(), value.to_string(), false);
+        }
+        build_flac_file(&data, &comments, 0).unwrap()
+    }
+
+    /// Keys of the items of the primary tag of a file, as written in its format
+    fn read_keys(path: &PathBuf) -> Vec<String> {
+        let tagged_file = get_tagged_file(path).unwrap();
+        let tag = tagged_file.primary_tag().unwrap();
+        tag.items().iter().filter_map(|item| item.key().map_key(tag.tag_type(), true).map(|k| k.to_string())).collect()
+    }
+
+    /// Write a file with the values of its custom fields, read with the configured keys, and read them back
+    fn write_and_read_back(extension: &str, data: &[u8]) -> (TempDir, BTreeMap<String, String>, PathBuf) {
+        let temp_dir = Builder::new().prefix("cleaner").tempdir().unwrap();
+        let dir = temp_dir.path().to_path_buf();
+        let source_path = dir.join(format!("Source.{}", extension));
+        let target_path = dir.join(format!("Target.{}", extension));
+        fs::write(&source_path, data).unwrap();
+
+        let options = Options { tag_fields: configured_fields(), ..Options::default() };
+        let audio_file = AudioFile::new(&dir, source_path.to_owned(), &options, None).unwrap();
+        assert_eq!(audio_file.get_meta().tag_fields(), &BTreeMap::from([("media".to_string(), "Vinyl".to_string()), ("release-type".to_string(), "Live".to_string())]));
+
+        let album = AlbumTags { default_year: None, default_genres: Vec::new(), total_tracks: 1, cover_image: None, overrides: Vec::new() };
+        write_tagged_file(&source_path, &target_path, audio_file.get_meta(), &album, &TrackTags::default(), &options).unwrap();
+
+        let written = AudioFile::new(&dir, target_path.to_owned(), &options, None).unwrap();
+        (temp_dir, written.get_meta().tag_fields().to_owned(), target_path)
+    }
+
+    #[test]
+    fn custom_fields_are_written_to_txxx_frames_and_frames_of_their_id3v2_keys() {
+        let (_dir, fields, path) = write_and_read_back("mp3", &tagged_mp3(&[("RELEASETYPE", "Live"), ("TMED", "Vinyl")]));
+
+        assert_eq!(fields.get("release-type").map(|s| s.as_str()), Some("Live"));
+        assert_eq!(fields.get("media").map(|s| s.as_str()), Some("Vinyl"));
+        let keys = read_keys(&path);
+        assert!(keys.contains(&"RELEASETYPE".to_string()) && keys.contains(&"TMED".to_string()));
+    }
+
+    #[test]
+    fn custom_fields_are_written_to_the_first_of_their_vorbis_keys() {
+        let (_dir, fields, path) = write_and_read_back("flac", &tagged_flac(&[("RELEASE TYPE", "Live"), ("MEDIA", "Vinyl")]));
+
+        assert_eq!(fields.get("release-type").map(|s| s.as_str()), Some("Live"));
+        assert_eq!(fields.get("media").map(|s| s.as_str()), Some("Vinyl"));
+        let keys = read_keys(&path);
+        assert!(keys.contains(&"RELEASETYPE".to_string()) && keys.contains(&"MEDIA".to_string()));
+        assert!(!keys.contains(&"RELEASE TYPE".to_string()));
+    }
+}
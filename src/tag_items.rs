@@ -0,0 +1,263 @@
+use lofty::{ItemKey, Tag, TagType, TaggedFile, TaggedFileExt};
+
+use crate::origin::is_origin_key;
+
+/// Name that keeps all items not written by the cleaner itself
+const ALL_ITEMS_NAME: &str = "*";
+
+/// Items carried over from the source tags when none are configured
+const DEFAULT_KEPT_ITEMS: [&str; 22] = [
+    "composer", "lyricist", "conductor", "arranger", "remixer", "producer", "comment", "lyrics", "label", "publisher",
+    "isrc", "barcode", "bpm", "initial-key", "mood", "original-release-date", "compilation", "copyright", "work",
+    "musicbrainz", "rating", "play-count",
+];
+
+/// Items always written from the cleaned metadata, so never carried over, even if they have no new value
+const WRITTEN_ITEMS: [ItemKey; 12] = [
+    ItemKey::AlbumArtist, ItemKey::TrackArtist, ItemKey::AlbumTitle, ItemKey::TrackTitle, ItemKey::Year,
+    ItemKey::RecordingDate, ItemKey::TrackNumber, ItemKey::TrackTotal, ItemKey::DiscNumber, ItemKey::DiscTotal,
+    ItemKey::Genre, ItemKey::CatalogNumber,
+];
+
+/// Tag items to carry over from the source file when its tags are replaced, given by name, e.g. composer or
+/// musicbrainz, or by format specific key, e.g. TCOM or "MusicBrainz Album Id"
+pub struct TagItems {
+    /// Kept items, or None for all items
+    keep: Option<Vec<ItemKey>>,
+    drop: Vec<ItemKey>,
+}
+
+impl TagItems {
+
+    pub fn new(keep: &[String], drop: &[String]) -> TagItems {
+        let keep = if keep.iter().any(|name| name == ALL_ITEMS_NAME) {
+            None
+        } else {
+            Some(keep.iter().flat_map(|name| get_item_keys(name)).collect())
+        };
+
+        TagItems {
+            keep,
+            drop: drop.iter().flat_map(|name| get_item_keys(name)).collect()
+        }
+    }
+
+    /// The default kept items, less the dropped items
+    pub fn with_default_keep(drop: &[String]) -> TagItems {
+        TagItems::new(&DEFAULT_KEPT_ITEMS.map(|s| s.to_string()), drop)
+    }
+
+    pub fn is_kept(&self, key: &ItemKey) -> bool {
+        let is_written = is_written_key(key) || is_origin_key(key);
+        let is_kept = self.keep.as_ref().is_none_or(|keep| keep.iter().any(|k| is_same_key(k, key)));
+        !is_written && is_kept && !self.is_dropped(key)
+    }
+
+    pub fn is_dropped(&self, key: &ItemKey) -> bool {
+        self.drop.iter().any(|k| is_same_key(k, key))
+    }
+
+    /// Copy the kept items of the source tags into a new tag, from the tag of the same type if there is one, then the
+    /// others. ID3v1 tags have no room for anything beyond what the cleaner writes.
+    pub fn carry_over(&self, source: &TaggedFile, tag: &mut Tag) {
+        carry_over_items(source, tag, |key| self.is_kept(key));
+    }
+
+    /// Copy the given items of the source tags into a new tag, unless they are dropped, whether or not they are kept
+    pub fn carry_over_keys(&self, source: &TaggedFile, tag: &mut Tag, keys: &[ItemKey]) {
+        carry_over_items(source, tag, |key| keys.iter().any(|k| is_same_key(k, key)) && !self.is_dropped(key));
+    }
+}
+
+impl Default for TagItems {
+    fn default() -> Self {
+        TagItems::with_default_keep(&[])
+    }
+}
+
+/// Copy the items of the source tags that match into a new tag, unless it already has them, from the tag of the same
+/// type if there is one, then the others
+fn carry_over_items<F>(source: &TaggedFile, tag: &mut Tag, is_carried: F)
+where
+    F: Fn(&ItemKey) -> bool
+{
+    let tag_type = tag.tag_type();
+    if tag_type == TagType::ID3v1 {
+        return;
+    }
+
+    let mut source_tags = source.tags().iter().collect::<Vec<&Tag>>();
+    source_tags.sort_by_key(|t| t.tag_type() != tag_type);
+
+    for source_tag in source_tags {
+        let items = source_tag.items()
+            .iter()
+            .filter(|item| is_carried(item.key()))
+            .filter(|item| tag.get_item_ref(item.key()).is_none())
+            .cloned()
+            .collect::<Vec<_>>();
+
+        // Items of other tag types are only carried if this tag type has a key for them
+        for item in items {
+            if source_tag.tag_type() == tag_type {
+                tag.push_item_unchecked(item);
+            } else {
+                tag.push_item(item);
+            }
+        }
+    }
+}
+
+/// Keys for a name, either one of the names for common items, or a key as used by a tag format
+fn get_item_keys(name: &str) -> Vec<ItemKey> {
+    let unknown = |keys: &[&str]| keys.iter().map(|k| ItemKey::Unknown(k.to_string())).collect::<Vec<ItemKey>>();
+
+    match name.to_lowercase().as_str() {
+        "composer" => vec![ItemKey::Composer, ItemKey::ComposerSortOrder],
+        "lyricist" => vec![ItemKey::Lyricist, ItemKey::Writer],
+        "conductor" => vec![ItemKey::Conductor],
+        "arranger" => vec![ItemKey::Arranger],
+        "remixer" => vec![ItemKey::Remixer, ItemKey::MixDj],
+        "producer" => vec![ItemKey::Producer, ItemKey::Engineer, ItemKey::MixEngineer],
+        "performers" => vec![ItemKey::Performer, ItemKey::MusicianCredits, ItemKey::InvolvedPeople],
+        "comment" => vec![ItemKey::Comment, ItemKey::Description],
+        "lyrics" => vec![ItemKey::Lyrics],
+        "label" => vec![ItemKey::Label],
+        "publisher" => vec![ItemKey::Publisher],
+        "isrc" => vec![ItemKey::ISRC],
+        "barcode" => vec![ItemKey::Barcode],
+        "bpm" => vec![ItemKey::BPM],
+        "initial-key" => vec![ItemKey::InitialKey, ItemKey::Unknown("INITIALKEY".to_string())],
+        "mood" => vec![ItemKey::Mood],
+        "original-release-date" => vec![ItemKey::OriginalReleaseDate],
+        "compilation" => vec![ItemKey::FlagCompilation],
+        "copyright" => vec![ItemKey::CopyrightMessage, ItemKey::License],
+        "work" => vec![ItemKey::Movement, ItemKey::MovementIndex, ItemKey::ContentGroup],
+        "replay-gain" => vec![ItemKey::ReplayGainTrackGain, ItemKey::ReplayGainTrackPeak, ItemKey::ReplayGainAlbumGain, ItemKey::ReplayGainAlbumPeak],
+        "encoder" => vec![ItemKey::EncodedBy, ItemKey::EncoderSoftware, ItemKey::EncoderSettings],
+        "sort-order" => vec![ItemKey::AlbumTitleSortOrder, ItemKey::AlbumArtistSortOrder, ItemKey::TrackTitleSortOrder, ItemKey::TrackArtistSortOrder],
+        // Picard writes TXXX frames, and the recording ID as a UFID frame, for ID3v2 and fields for Vorbis comments
+        "musicbrainz" => unknown(&[
+            "UFID", "MusicBrainz Album Id", "MusicBrainz Artist Id", "MusicBrainz Album Artist Id",
+            "MusicBrainz Release Group Id", "MusicBrainz Release Track Id", "MusicBrainz Work Id",
+            "MusicBrainz Album Type", "MusicBrainz Album Status", "MusicBrainz Album Release Country",
+            "MUSICBRAINZ_TRACKID", "MUSICBRAINZ_ALBUMID", "MUSICBRAINZ_ARTISTID", "MUSICBRAINZ_ALBUMARTISTID",
+            "MUSICBRAINZ_RELEASEGROUPID", "MUSICBRAINZ_RELEASETRACKID", "MUSICBRAINZ_WORKID", "RELEASETYPE",
+            "RELEASESTATUS", "RELEASECOUNTRY",
+        ]),
+        "rating" => [vec![ItemKey::Popularimeter], unknown(&["RATING", "FMPS_RATING"])].concat(),
+        "play-count" => unknown(&["PCNT", "PLAYCOUNT", "FMPS_PLAYCOUNT"]),
+        _ => vec![
+            [TagType::ID3v2, TagType::VorbisComments, TagType::APE, TagType::MP4ilst]
+                .iter()
+                .map(|t| ItemKey::from_key(*t, name))
+                .find(|k| !matches!(k, ItemKey::Unknown(_)))
+                .unwrap_or_else(|| ItemKey::Unknown(name.to_string()))
+        ]
+    }
+}
+
+/// Whether a key is one of the items always written from the cleaned metadata
+pub fn is_written_key(key: &ItemKey) -> bool {
+    WRITTEN_ITEMS.iter().any(|k| k == key)
+}
+
+/// Keys are the same, ignoring case for keys that lofty has no variant for, as formats differ in case
+pub fn is_same_key(a: &ItemKey, b: &ItemKey) -> bool {
+    match (a, b) {
+        (ItemKey::Unknown(a), ItemKey::Unknown(b)) => a.eq_ignore_ascii_case(b),
+        _ => a == b
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use lofty::TagExt;
+    use tempfile::Builder;
+
+    use crate::tagger::get_tagged_file;
+
+    use super::*;
+
+    fn unknown(key: &str) -> ItemKey {
+        ItemKey::Unknown(key.to_string())
+    }
+
+    #[test]
+    fn default_items_are_kept_and_others_dropped() {
+        let tag_items = TagItems::default();
+
+        for key in [ItemKey::Composer, ItemKey::Lyrics, ItemKey::Label, unknown("MusicBrainz Album Id"), unknown("musicbrainz_trackid")] {
+            assert!(tag_items.is_kept(&key), "{:?}", key);
+        }
+        for key in [ItemKey::ReplayGainTrackGain, ItemKey::EncoderSoftware, ItemKey::InvolvedPeople, ItemKey::TrackTitleSortOrder, unknown("SOMETHING_ELSE")] {
+            assert!(!tag_items.is_kept(&key), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn written_and_origin_items_are_never_kept() {
+        let tag_items = TagItems::new(&[ALL_ITEMS_NAME.to_string()], &[]);
+
+        assert!(tag_items.is_kept(&unknown("SOMETHING_ELSE")));
+        for key in [ItemKey::TrackTitle, ItemKey::Genre, ItemKey::CatalogNumber, unknown("CLEANER_SOURCE"), unknown("cleaner_original_sha256")] {
+            assert!(!tag_items.is_kept(&key), "{:?}", key);
+        }
+    }
+
+    #[test]
+    fn dropped_items_are_not_kept_even_if_listed() {
+        let tag_items = TagItems::new(&["composer".to_string(), "comment".to_string()], &["comment".to_string(), "TCOM".to_string()]);
+
+        assert!(!tag_items.is_kept(&ItemKey::Comment));
+        assert!(!tag_items.is_kept(&ItemKey::Composer));
+        assert!(tag_items.is_dropped(&ItemKey::Description));
+        assert!(!tag_items.is_kept(&ItemKey::Lyrics));
+    }
+
+    #[test]
+    fn items_are_named_by_format_specific_keys() {
+        assert_eq!(get_item_keys("TCOM"), [ItemKey::Composer]);
+        assert_eq!(get_item_keys("LYRICIST"), [ItemKey::Lyricist, ItemKey::Writer]);
+        assert_eq!(get_item_keys("Custom Key"), [unknown("Custom Key")]);
+    }
+
+    #[test]
+    fn unknown_keys_are_the_same_ignoring_case() {
+        assert!(is_same_key(&unknown("RATING"), &unknown("rating")));
+        assert!(!is_same_key(&unknown("RATING"), &unknown("FMPS_RATING")));
+        assert!(!is_same_key(&ItemKey::Composer, &unknown("COMPOSER")));
+        assert!(is_written_key(&ItemKey::TrackArtist) && !is_written_key(&ItemKey::Composer));
+    }
+
+    #[test]
+    fn carry_over_copies_kept_items_only() {
+        let dir = Builder::new().prefix("cleaner").tempdir().unwrap();
+        let path = dir.path().join("track.mp3");
+        let mut frame = vec![0u8; 417];
+        frame[..4].copy_from_slice(&[0xff, 0xfb, 0x90, 0x40]);
+        fs::write(&path, frame.repeat(10)).unwrap();
+
+        let mut source_tag = Tag::new(TagType::ID3v2);
+        for (key, value) in [(ItemKey::Composer, "Composer"), (ItemKey::EncoderSoftware, "Encoder"), (ItemKey::TrackTitle, "Old title")] {
+            source_tag.insert_text(key, value.to_string());
+        }
+        source_tag.save_to_path(&path).unwrap();
+        let source = get_tagged_file(&path).unwrap();
+
+        let mut tag = Tag::new(TagType::ID3v2);
+        tag.insert_text(ItemKey::TrackTitle, "New title".to_string());
+        TagItems::default().carry_over(&source, &mut tag);
+
+        assert_eq!(tag.get_string(&ItemKey::Composer), Some("Composer"));
+        assert_eq!(tag.get_string(&ItemKey::EncoderSoftware), None);
+        assert_eq!(tag.get_string(&ItemKey::TrackTitle), Some("New title"));
+
+        // Nothing is carried over into ID3v1 tags
+        let mut tag = Tag::new(TagType::ID3v1);
+        TagItems::default().carry_over(&source, &mut tag);
+        assert!(tag.items().is_empty());
+    }
+}
@@ -1,104 +1,397 @@
-use std::path::PathBuf;
+use std::{path::{PathBuf, Path}, fs};
 
+use clap::ValueEnum;
 use image::EncodableLayout;
-use lofty::{Probe, Tag, Accessor, TagExt, TaggedFile, ItemKey, TagType, TaggedFileExt, PictureType, Picture};
+use lofty::{Probe, Tag, Accessor, TagExt, TaggedFile, ItemKey, ItemValue, TagItem, TagType, TaggedFileExt, PictureType, Picture, FileType, id3::v2::ID3v2Tag, ogg::VorbisComments};
+use sha2::{Sha256, Digest};
 
-use crate::{audio_file_meta::AudioFileMeta, error::CleanerResult};
+use crate::{audio_file_meta::AudioFileMeta, error::{CleanerResult, CleanerError}, loudness::ReplayGain, mpeg::{VbrHeaderRepair, get_gapless_info, repair_vbr_header}, tempo_key::TempoKey, options::Options, replay_gain_mode::ReplayGainMode, stray_tags::{StrayTag, find_stray_tags}, id3v1::write_id3v1_tag, id3v1_mode::Id3v1Mode, id3v2::{convert_to_id3v2_3, pad_tag, read_tag, read_text_values}, flac::build_flac_file, id3v2_version::Id3v2Version, metadata_field::MetadataField, origin::Origin, tag_fields::{TagFields, ALBUM_ARTIST_FIELD, CATALOGUE_FIELD, INITIAL_KEY_FIELD}, multi_value::{join_values, split_joined}, tag_items::{is_same_key, is_written_key}};
+
+/// Changes made to the tags of a file other than writing the cleaned metadata
+pub struct TagReport {
+    /// Tags removed as they are not written for the format of the file, or are repeated
+    pub stray_tags: Vec<StrayTag>,
+    /// Fields cut short to fit in the ID3v1 tag
+    pub truncated_fields: Vec<MetadataField>,
+    pub vbr_header_repair: VbrHeaderRepair,
+}
+
+/// Name the cover art is shown with among the metadata fields
+const COVER_FIELD_NAME: &str = "cover";
+
+/// Bytes of the hash of cover art shown to tell pictures of the same size apart
+const COVER_HASH_BYTES: usize = 4;
+
+/// ID3v2 frames with several values, written separated by terminators, including the roles and people of TIPL in pairs
+const MULTI_VALUE_FRAMES: [(&str, ItemKey); 3] = [("TPE1", ItemKey::TrackArtist), ("TCON", ItemKey::Genre), ("TIPL", ItemKey::InvolvedPeople)];
+
+/// Values shared by the tracks of an album, for those without their own, and values of the album edited when previewing
+pub struct AlbumTags {
+    pub default_year: Option<u32>,
+    pub default_genres: Vec<String>,
+    pub total_tracks: u32,
+    pub cover_image: Option<Vec<u8>>,
+    /// Values edited when previewing the changes, which replace those of the metadata
+    pub overrides: Vec<(MetadataField, String)>,
+}
+
+impl AlbumTags {
+
+    /// The last value edited for a field when previewing, if any
+    pub fn get_override(&self, field: MetadataField) -> Option<&str> {
+        self.overrides.iter().rev().find(|(f, _)| *f == field).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Values of a single track, besides its metadata, that are written to its tags
+#[derive(Default)]
+pub struct TrackTags<'a> {
+    pub replay_gain: Option<&'a ReplayGain>,
+    pub tempo_key: Option<&'a TempoKey>,
+    /// The archive or directory the file was found in, recorded as its origin
+    pub origin_source: Option<&'a Path>,
+}
+
+/// ReplayGain items, written as TXXX frames for ID3v2 and as fields for Vorbis comments
+const REPLAY_GAIN_KEYS: [ItemKey; 4] = [ItemKey::ReplayGainTrackGain, ItemKey::ReplayGainTrackPeak, ItemKey::ReplayGainAlbumGain, ItemKey::ReplayGainAlbumPeak];
+
+/// Name the stray tags removed from a file are shown with among the changes
+const STRAY_TAG_NAME: &str = "stray tag";
+
+/// A field or item of the tags of a file whose value changes when they are written
+pub struct TagChange {
+    pub name: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
 
 pub fn get_tagged_file(path: &PathBuf) -> CleanerResult<TaggedFile> {
-	let tagged_file = Probe::open(path)?
+	let mut tagged_file = Probe::open(path)?
         .read()?;
 
+    // Lofty reads only the first of the values of an ID3v2.4 text frame, and ID3v2.3 values joined with slashes as one,
+    // so those of multi-valued fields are read again
+    if tagged_file.file_type() == FileType::MPEG {
+        if let Some(tag) = tagged_file.tag_mut(TagType::ID3v2) {
+            let data = read_tag(path)?;
+            for (frame_id, key) in MULTI_VALUE_FRAMES {
+                let values = read_text_values(&data, frame_id);
+                if values.len() > 1 {
+                    tag.remove_key(&key);
+                    for value in values.into_iter().filter(|v| !v.is_empty()) {
+                        tag.push_item_unchecked(TagItem::new(key.to_owned(), ItemValue::Text(value)));
+                    }
+                }
+            }
+        }
+    }
+
     Ok(tagged_file)
 }
 
-pub fn clean_tags(path: &PathBuf, meta: &AudioFileMeta, default_year: &Option<u32>, default_genre: &Option<&str>, total_tracks: u32, cover_image: &Option<Vec<u8>>) -> CleanerResult<()> {
-	let mut tagged_file = get_tagged_file(path)?;
+/// Write a copy of an audio file with new tags, in a single write, built in memory from the audio of the source file,
+/// without any of its tags, and the cleaned metadata. Tags are padded so that they can be edited later without
+/// rewriting the whole file. With an origin source, the archive or directory the file was found in, its origin is
+/// recorded in the tags.
+pub fn write_tagged_file(source_path: &PathBuf, target_path: &PathBuf, meta: &AudioFileMeta, album: &AlbumTags, track: &TrackTags, options: &Options) -> CleanerResult<TagReport> {
+    let data = fs::read(source_path)?;
+    let file_type = meta.tagged_file().file_type();
+    let origin = track.origin_source.map(|origin_source| Origin::new(origin_source, source_path, &data, meta.tagged_file()));
+
+    // All tags are left behind, including those lofty does not read, e.g. Lyrics3, or would not remove
+    let (audio_range, stray_tags) = find_stray_tags(&data, file_type);
+    let audio = &data[audio_range];
+
+    // Primarily use the native tag type for the file, i.e. ID3v2 for MP3, Vorbis comments for FLAC
+    let primary_tag_type = meta.tagged_file().primary_tag_type();
+    let tag = build_tag(primary_tag_type, meta, album, track, options, origin.as_ref());
+
+    let mut truncated_fields = Vec::new();
+    let mut vbr_header_repair = VbrHeaderRepair::Unchanged;
+    let output = match file_type {
+        FileType::MPEG => {
+            let mut output = build_id3v2_tag(tag, options.id3v2_version, options.tag_padding)?;
+            output.extend_from_slice(audio);
 
-    remove_tags(path, &mut tagged_file)?;
+            // Add ID3v1 for fallback/compatibility, unless it is not wanted
+            if options.id3v1 != Id3v1Mode::Off {
+                let tag = build_tag(TagType::ID3v1, meta, album, track, options, None);
+                truncated_fields = write_id3v1_tag(tag, options.id3v1 == Id3v1Mode::Ascii, &mut output)?;
+            }
+
+            if options.repair_vbr_header {
+                vbr_header_repair = repair_vbr_header(&mut output)?;
+            }
+
+            // The encoder delay and padding in the LAME tag must survive tag writing and header repair, or gapless
+            // playback breaks
+            if get_gapless_info(&output) != get_gapless_info(audio) {
+                return Err(CleanerError::GaplessInfoChanged);
+            }
 
-    // Primarily use ID3v2
-    add_tag(&mut tagged_file, TagType::ID3v2, meta, default_year, default_genre, total_tracks, &cover_image)
-        .save_to_path(path)?;
+            output
+        },
+        FileType::FLAC => build_flac_file(audio, &VorbisComments::from(tag), options.tag_padding)?,
+        _ => return Err(CleanerError::UnexpectedFileExtension)
+    };
 
-    // Add ID3v1 for fallback/compatibility
-    add_tag(&mut tagged_file, TagType::ID3v1, meta, default_year, default_genre, total_tracks, &cover_image)
-        .save_to_path(path)?;
+    fs::write(target_path, output)?;
 
-    Ok(())
+    Ok(TagReport { stray_tags, truncated_fields, vbr_header_repair })
 }
 
-fn remove_tags(path: &PathBuf, tagged_file: &mut TaggedFile) -> CleanerResult<()> {
-    remove_tag(path, tagged_file, TagType::ID3v1)?;
-    remove_tag(path, tagged_file, TagType::ID3v2)?;
+/// Compare the tags of a file with the tag that would be written for it, returning what changes: each metadata field
+/// and the cover art, then every other item, e.g. those carried over or dropped, ReplayGain, tempo, key and origin, by the
+/// key of the tag format, and the stray tags that are removed
+pub fn get_tag_changes(source_path: &PathBuf, meta: &AudioFileMeta, album: &AlbumTags, track: &TrackTags, options: &Options) -> CleanerResult<Vec<TagChange>> {
+    let data = fs::read(source_path)?;
+    let tagged_file = meta.tagged_file();
+    let tag_fields = &options.tag_fields;
+    let old_tag = tagged_file.primary_tag().or_else(|| tagged_file.first_tag());
+    let origin = track.origin_source.map(|origin_source| Origin::new(origin_source, source_path, &data, tagged_file));
+    let new_tag = build_tag(tagged_file.primary_tag_type(), meta, album, track, options, origin.as_ref());
 
-    Ok(())
+    let mut changes = MetadataField::value_variants()
+        .iter()
+        .map(|field| TagChange {
+            name: field.to_string(),
+            old: old_tag.and_then(|t| get_field_value(t, *field, tag_fields)),
+            new: get_field_value(&new_tag, *field, tag_fields)
+        })
+        .collect::<Vec<TagChange>>();
+    changes.push(TagChange {
+        name: COVER_FIELD_NAME.to_string(),
+        old: old_tag.and_then(describe_cover),
+        new: describe_cover(&new_tag)
+    });
+
+    // Items of the metadata fields are compared above, by their values rather than as they are written
+    let mut item_keys = Vec::<&ItemKey>::new();
+    for item in old_tag.iter().flat_map(|t| t.items()).chain(new_tag.items()) {
+        if !is_field_key(item.key(), new_tag.tag_type(), tag_fields) && !item_keys.iter().any(|k| is_same_key(k, item.key())) {
+            item_keys.push(item.key());
+        }
+    }
+    changes.extend(item_keys.into_iter().map(|key| TagChange {
+        name: key.map_key(new_tag.tag_type(), true).map(|s| s.to_string()).unwrap_or_else(|| format!("{:?}", key)),
+        old: old_tag.and_then(|t| get_item_values(t, key)),
+        new: get_item_values(&new_tag, key)
+    }));
+
+    let (_, stray_tags) = find_stray_tags(&data, tagged_file.file_type());
+    changes.extend(stray_tags.iter().map(|stray_tag| TagChange {
+        name: STRAY_TAG_NAME.to_string(),
+        old: Some(stray_tag.to_string()),
+        new: None
+    }));
+
+    changes.retain(|c| c.old != c.new);
+    Ok(changes)
 }
 
-fn remove_tag(path: &PathBuf, tagged_file: &mut TaggedFile, tag_type: TagType) -> CleanerResult<()> {
-    if let Some(tag) = tagged_file.remove(tag_type) {
-        tag.remove_from_path(path)?;
+/// Whether an item holds the value of one of the metadata fields
+fn is_field_key(key: &ItemKey, tag_type: TagType, tag_fields: &TagFields) -> bool {
+    is_written_key(key) || [ALBUM_ARTIST_FIELD, CATALOGUE_FIELD].iter().any(|name| tag_fields.has_key(name, tag_type, key))
+}
+
+/// Values of all items of a key, with binary values given by their size
+fn get_item_values(tag: &Tag, key: &ItemKey) -> Option<String> {
+    let values = tag.items()
+        .iter()
+        .filter(|item| is_same_key(key, item.key()))
+        .map(|item| match item.value() {
+            ItemValue::Text(text) | ItemValue::Locator(text) => text.to_owned(),
+            ItemValue::Binary(data) => format!("{} bytes", data.len()),
+        })
+        .collect::<Vec<String>>();
+
+    join_values(&values)
+}
+
+fn get_field_value(tag: &Tag, field: MetadataField, tag_fields: &TagFields) -> Option<String> {
+    match field {
+        MetadataField::AlbumArtist => tag_fields.read(ALBUM_ARTIST_FIELD, tag),
+        MetadataField::Artist => join_values(&tag.get_strings(&ItemKey::TrackArtist).map(|s| s.to_string()).collect::<Vec<String>>()),
+        MetadataField::Album => tag.album().map(|s| s.to_string()),
+        MetadataField::Year => tag.year().map(|y| y.to_string()),
+        // ID3v2 track numbers are written with the total, e.g. 3/12
+        MetadataField::Track => tag.get_string(&ItemKey::TrackNumber)
+            .and_then(|s| s.split('/').next())
+            .and_then(|s| s.trim().parse::<u32>().ok())
+            .map(|n| n.to_string()),
+        MetadataField::Disc => tag.disk().map(|n| n.to_string()),
+        MetadataField::Title => tag.title().map(|s| s.to_string()),
+        MetadataField::Genre => join_values(&tag.get_strings(&ItemKey::Genre).map(|s| s.to_string()).collect::<Vec<String>>()),
+        MetadataField::Catalogue => tag_fields.read(CATALOGUE_FIELD, tag),
     }
+}
+
+/// Dimensions and the start of the hash of the front cover, or any picture, of a tag
+fn describe_cover(tag: &Tag) -> Option<String> {
+    let picture = tag.get_picture_type(PictureType::CoverFront).or_else(|| tag.pictures().first())?;
+    let hash = Sha256::digest(picture.data())
+        .iter()
+        .take(COVER_HASH_BYTES)
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
 
-    Ok(())
+    match image_meta::load_from_buf(picture.data()) {
+        Ok(image_meta) => Some(format!("{}x{} {}", image_meta.dimensions.width, image_meta.dimensions.height, hash)),
+        Err(_) => Some(hash)
+    }
 }
 
-fn add_tag<'a>(tagged_file: &'a mut TaggedFile, tag_type: TagType, meta: &AudioFileMeta, default_year: &Option<u32>, default_genre: &Option<&str>, total_tracks: u32, cover_image: &Option<Vec<u8>>) -> &'a Tag {
-    tagged_file
-        .insert_tag(Tag::new(tag_type));
+/// The bytes of an ID3v2 tag, in the given version, with padding after its frames. Tags are converted to lofty's ID3v2
+/// tag first, as only that writes items that have no frame of their own, e.g. MusicBrainz IDs, as TXXX frames.
+fn build_id3v2_tag(mut tag: Tag, id3v2_version: Id3v2Version, padding: usize) -> CleanerResult<Vec<u8>> {
+    // Lofty separates several artists with terminators, but keeps only the last of several genres or involved people
+    for key in [ItemKey::Genre, ItemKey::InvolvedPeople] {
+        let values = tag.take_strings(&key).collect::<Vec<String>>();
+        if !values.is_empty() {
+            tag.insert_text(key, values.join("\0"));
+        }
+    }
+
+    // Lofty takes the ReplayGain keys to be frame IDs, which it then fails to write, so they are given as the descriptions
+    // of TXXX frames instead
+    for key in REPLAY_GAIN_KEYS {
+        let description = key.map_key(TagType::ID3v2, false).map(|s| s.to_string());
+        let value = tag.take_strings(&key).next();
+        if let (Some(value), Some(description)) = (value, description) {
+            tag.insert_item_unchecked(TagItem::new(ItemKey::Unknown(description), ItemValue::Text(value)));
+        }
+    }
 
-    let tag = tagged_file
-        .tag_mut(tag_type)
-        .expect("Tag must have been inserted");
+    let mut bytes = Vec::new();
+    ID3v2Tag::from(tag).dump_to(&mut bytes)?;
+
+    // Lofty only writes ID3v2.4
+    if id3v2_version == Id3v2Version::V3 {
+        bytes = convert_to_id3v2_3(&bytes)?;
+    }
+
+    pad_tag(&mut bytes, padding)?;
+    Ok(bytes)
+}
+
+fn build_tag(tag_type: TagType, meta: &AudioFileMeta, album: &AlbumTags, track: &TrackTags, options: &Options, origin: Option<&Origin>) -> Tag {
+    let mut tag = Tag::new(tag_type);
+    let tag_fields = &options.tag_fields;
+
+    // Values edited when previewing the changes replace those of the metadata
+    let text = |field: MetadataField, value: Option<&str>| album.get_override(field).or(value).map(|s| s.to_string());
+    let number = |field: MetadataField, value: Option<u32>| album.get_override(field).and_then(|s| s.parse::<u32>().ok()).or(value);
+    let values = |field: MetadataField, values: &[String]| album.get_override(field).map(split_joined).unwrap_or_else(|| values.to_vec());
+
+    // Items not written below, e.g. composer or MusicBrainz IDs, are carried over from the source tags
+    options.tag_items.carry_over(meta.tagged_file(), &mut tag);
+
+    // Without ReplayGain analysis, the existing values still hold for the same audio, so are kept unless dropped
+    if options.replay_gain == ReplayGainMode::Off {
+        options.tag_items.carry_over_keys(meta.tagged_file(), &mut tag, &REPLAY_GAIN_KEYS);
+    }
 
     // See https://docs.rs/lofty/latest/lofty/enum.ItemKey.html
 
-    if let Some(album_artist_name) = meta.album_artist_name() {
-        tag.insert_text(ItemKey::AlbumArtist, album_artist_name.to_string());
+    if let Some(album_artist_name) = text(MetadataField::AlbumArtist, meta.album_artist_name()) {
+        tag_fields.write(ALBUM_ARTIST_FIELD, &mut tag, album_artist_name);
     }
 
-    if let Some(artist_name) = meta.artist_name() {
-        tag.set_artist(artist_name.to_string());
+    // Several values are written as repeated Vorbis comment fields, or separated by terminators in ID3v2.4 frames. ID3v1
+    // has room for one artist, so they are joined.
+    let artist_names = values(MetadataField::Artist, meta.artist_names());
+    match tag_type {
+        TagType::ID3v1 => if let Some(artist_name) = join_values(&artist_names) {
+            tag.set_artist(artist_name);
+        },
+        _ => for artist_name in artist_names {
+            tag.push_item(TagItem::new(ItemKey::TrackArtist, ItemValue::Text(artist_name)));
+        }
     }
 
-    if let Some(album_title) = meta.album_title() {
-        tag.set_album(album_title.to_string());
+    if let Some(album_title) = text(MetadataField::Album, meta.album_title()) {
+        tag.set_album(album_title);
     }
 
-    if let Some(year) = meta.year().or_else(|| default_year.to_owned()) {
+    if let Some(year) = number(MetadataField::Year, meta.year()).or(album.default_year) {
         match tag_type {
             TagType::ID3v1 => {
                 tag.insert_text(ItemKey::Year, year.to_string());
             },
-            TagType::ID3v2 => {
+            _ => {
                 tag.set_year(year);
             }
-            _ => panic!("Unexpected tag type")
         };
         tag.insert_text(ItemKey::RecordingDate, year.to_string());
     }
 
-    if let Some(track_number) = meta.track_number() {
+    if let Some(track_number) = number(MetadataField::Track, meta.track_number()) {
         match tag_type {
             TagType::ID3v1 => {
                 tag.set_track(track_number);
             },
             TagType::ID3v2 => {
-                tag.insert_text(ItemKey::TrackNumber, format!("{}/{}", track_number.to_string(), total_tracks));
+                tag.insert_text(ItemKey::TrackNumber, format!("{}/{}", track_number, album.total_tracks));
+            }
+            _ => {
+                tag.set_track(track_number);
+                tag.set_track_total(album.total_tracks);
             }
-            _ => panic!("Unexpected tag type")
         };
     }
 
-    if let Some(track_title) = meta.track_title() {
-        tag.set_title(track_title.to_string());
+    if let Some(disc_number) = number(MetadataField::Disc, meta.disc_number()).filter(|_| tag_type != TagType::ID3v1) {
+        tag.set_disk(disc_number);
+    }
+
+    if let Some(catalogue_number) = text(MetadataField::Catalogue, meta.catalogue_number()).filter(|_| tag_type != TagType::ID3v1) {
+        tag_fields.write(CATALOGUE_FIELD, &mut tag, catalogue_number);
+    }
+
+    if let Some(track_title) = text(MetadataField::Title, meta.track_title()) {
+        tag.set_title(track_title);
+    }
+
+    // ID3v1 genres are from a fixed list, so only the first is written
+    let genres = Some(values(MetadataField::Genre, meta.genres())).filter(|g| !g.is_empty()).unwrap_or_else(|| album.default_genres.to_vec());
+    match tag_type {
+        TagType::ID3v1 => if let Some(genre) = genres.into_iter().next() {
+            tag.set_genre(genre);
+        },
+        _ => for genre in genres {
+            tag.push_item(TagItem::new(ItemKey::Genre, ItemValue::Text(genre)));
+        }
+    }
+
+    // ReplayGain is written as TXXX frames for ID3v2 and as fields for Vorbis comments, ID3v1 has no equivalent
+    if let Some(replay_gain) = track.replay_gain.filter(|_| tag_type != TagType::ID3v1) {
+        tag.insert_text(ItemKey::ReplayGainTrackGain, replay_gain.track_gain_text());
+        tag.insert_text(ItemKey::ReplayGainTrackPeak, replay_gain.track_peak_text());
+        tag.insert_text(ItemKey::ReplayGainAlbumGain, replay_gain.album_gain_text());
+        tag.insert_text(ItemKey::ReplayGainAlbumPeak, replay_gain.album_peak_text());
+    }
+
+    // TBPM frames for ID3v2, BPM fields for Vorbis comments, and the key as mapped, e.g. TKEY or INITIALKEY
+    if let Some(tempo_key) = track.tempo_key.filter(|_| tag_type != TagType::ID3v1) {
+        if let Some(bpm) = tempo_key.bpm {
+            tag.insert_text(ItemKey::BPM, bpm.to_string());
+        }
+        if let Some(key) = &tempo_key.key {
+            tag_fields.write(INITIAL_KEY_FIELD, &mut tag, key.to_string());
+        }
+    }
+
+    // Custom fields, with the keys they are mapped to for the tag type, ID3v1 has none
+    for (name, value) in meta.tag_fields() {
+        tag_fields.write(name, &mut tag, value.to_owned());
     }
 
-    if let Some(genre) = meta.genre().or_else(|| default_genre.to_owned()) {
-        tag.set_genre(genre.to_string());
+    // TXXX frames for ID3v2, fields for Vorbis comments, ID3v1 has no room for them
+    if let Some(origin) = origin.filter(|_| tag_type != TagType::ID3v1) {
+        origin.add_to_tag(&mut tag);
     }
 
-    if let Some(cover_image) = cover_image {
+    if let Some(cover_image) = &album.cover_image {
         let mut buffer = cover_image.as_bytes();
         match Picture::from_reader(&mut buffer) {
             Ok(mut picture) => {
@@ -0,0 +1,97 @@
+use std::path::{Component, Path, PathBuf};
+
+use colored::Colorize;
+
+use crate::{cleaner::{fix_files, revert_files}, options::Options};
+
+/// Fix the tags of the files of a directory, returning false if it is not a directory
+pub fn process_tags(path: &Path, backup_path: &Path, options: &Options) -> bool {
+    println!("Fixing tags of files in {}, backing up to {}...\n",
+        path.to_string_lossy().bright_yellow().bold(),
+        backup_path.to_string_lossy().bright_yellow().bold()
+    );
+
+    if !path.is_dir() {
+        println!("{} {}\n", "ERROR".bright_red().bold(), "Not a valid directory".to_string().red());
+        return false
+    }
+
+    // Backups inside the directory would be found among its files, and fixed or reverted as if they were its own
+    if is_within(backup_path, path) {
+        println!("{} {}\n", "ERROR".bright_red().bold(), "Backup directory must not be inside the directory".to_string().red());
+        return false
+    }
+
+    fix_files(path, backup_path, options);
+
+    println!("Finished.");
+    true
+}
+
+/// Restore the tags of the files of a directory, returning false if it or the backup directory is not a directory
+pub fn revert_tags(path: &Path, backup_path: &Path) -> bool {
+    println!("Reverting tags of files in {} from {}...\n",
+        path.to_string_lossy().bright_yellow().bold(),
+        backup_path.to_string_lossy().bright_yellow().bold()
+    );
+
+    if !path.is_dir() || !backup_path.is_dir() {
+        println!("{} {}\n", "ERROR".bright_red().bold(), "Not a valid directory".to_string().red());
+        return false
+    }
+
+    revert_files(path, backup_path);
+
+    println!("Finished.");
+    true
+}
+
+/// Whether a path is a directory or inside it, once both are made absolute, following links as far as they exist
+fn is_within(path: &Path, directory: &Path) -> bool {
+    match (resolve_path(path), resolve_path(directory)) {
+        (Some(path), Some(directory)) => path.starts_with(directory),
+        _ => false
+    }
+}
+
+fn resolve_path(path: &Path) -> Option<PathBuf> {
+    let absolute_path = std::path::absolute(path).ok()?;
+    let existing_path = absolute_path.ancestors().find(|p| p.exists())?;
+    let mut resolved_path = existing_path.canonicalize().ok()?;
+
+    // Directories that do not exist yet are not links, so their parents are found by name
+    for component in absolute_path.strip_prefix(existing_path).ok()?.components() {
+        match component {
+            Component::ParentDir => { resolved_path.pop(); },
+            Component::CurDir => {},
+            component => resolved_path.push(component)
+        }
+    }
+
+    Some(resolved_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn backups_are_not_written_inside_the_directory() {
+        let dir = Builder::new().prefix("cleaner").tempdir().unwrap();
+        let path = dir.path().join("Music");
+        fs::create_dir(&path).unwrap();
+
+        assert!(!process_tags(&path, &path, &Options::default()));
+        assert!(!process_tags(&path, &path.join("Backup"), &Options::default()));
+        assert!(!process_tags(&path, &dir.path().join("Other/../Music/Backup"), &Options::default()));
+        assert!(!path.join("Backup").exists());
+
+        assert!(is_within(&path.join("Backup"), &path));
+        assert!(!is_within(&dir.path().join("Music Backup"), &path));
+        assert!(!is_within(&dir.path().join("Backup"), &path));
+    }
+}
@@ -0,0 +1,194 @@
+use std::path::PathBuf;
+
+use lofty::{ItemKey, TaggedFileExt};
+
+use crate::{audio_file::AudioFile, decoder::decode_audio, error::CleanerResult, spectrum::{Stft, Downsampler, mix_to_mono, to_pitch_class}};
+
+const SAMPLE_RATE: u32 = 11025;
+
+// Tempo is estimated from the periodicity of the onset strength (spectral flux) of short frames
+const ONSET_FRAME_SIZE: usize = 1024;
+const ONSET_HOP_SIZE: usize = 256;
+
+const MIN_BPM: f32 = 60.0;
+const MAX_BPM: f32 = 200.0;
+
+/// Tempo that ambiguous (half or double time) estimates are biased towards
+const PREFERRED_BPM: f32 = 120.0;
+
+// Key is estimated from the chroma of long frames, for good frequency resolution
+const CHROMA_FRAME_SIZE: usize = 8192;
+const CHROMA_HOP_SIZE: usize = CHROMA_FRAME_SIZE / 2;
+
+const MIN_CHROMA_FREQUENCY: f32 = 55.0;
+const MAX_CHROMA_FREQUENCY: f32 = 2000.0;
+
+// Krumhansl-Kessler key profiles, starting from the tonic
+const MAJOR_PROFILE: [f32; 12] = [6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88];
+const MINOR_PROFILE: [f32; 12] = [6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17];
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "Eb", "E", "F", "F#", "G", "Ab", "A", "Bb", "B"];
+
+pub struct TempoKey {
+    pub bpm: Option<u32>,
+    /// Key in the usual notation, e.g. "F#" or "Am"
+    pub key: Option<String>,
+}
+
+impl TempoKey {
+
+    pub fn is_complete(&self) -> bool {
+        self.bpm.is_some() && self.key.is_some()
+    }
+
+    /// Fill in any missing values from another estimate
+    pub fn or(self, other: TempoKey) -> TempoKey {
+        TempoKey {
+            bpm: self.bpm.or(other.bpm),
+            key: self.key.or(other.key)
+        }
+    }
+}
+
+/// Get the tempo and key from the existing tags of a track
+pub fn get_tempo_key(audio_file: &AudioFile) -> TempoKey {
+    let tag = audio_file.get_meta().tagged_file().primary_tag();
+
+    let bpm = tag
+        .and_then(|t| t.get_string(&ItemKey::BPM))
+        .and_then(|s| s.trim().parse::<f32>().ok())
+        .filter(|bpm| *bpm > 0.0)
+        .map(|bpm| bpm.round() as u32);
+
+    let key = tag
+        .and_then(|t| t.get_string(&ItemKey::InitialKey).or_else(|| t.get_string(&ItemKey::Unknown("INITIALKEY".to_string()))))
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty());
+
+    TempoKey { bpm, key }
+}
+
+/// Estimate the tempo and key of a track from its decoded audio
+pub fn analyse_tempo_key(path: &PathBuf) -> CleanerResult<TempoKey> {
+    let mut downsampler = None::<Downsampler>;
+    let mut onset_stft = Stft::new(ONSET_FRAME_SIZE, ONSET_HOP_SIZE);
+    let mut chroma_stft = Stft::new(CHROMA_FRAME_SIZE, CHROMA_HOP_SIZE);
+    let mut mono = Vec::new();
+    let mut downsampled = Vec::new();
+    let mut previous_spectrum = Vec::<f32>::new();
+    let mut onsets = Vec::<f32>::new();
+    let mut chroma = [0.0f32; 12];
+
+    decode_audio(path, |sample_rate, channels, samples| {
+        mono.clear();
+        downsampled.clear();
+        mix_to_mono(samples, channels, &mut mono);
+        downsampler
+            .get_or_insert_with(|| Downsampler::new(sample_rate, SAMPLE_RATE))
+            .process(&mono, &mut downsampled);
+
+        onset_stft.push(&downsampled, |magnitudes| {
+            let spectrum = magnitudes.iter().map(|m| (1.0 + m).ln()).collect::<Vec<f32>>();
+            let flux = spectrum
+                .iter()
+                .zip(previous_spectrum.iter())
+                .map(|(current, previous)| (current - previous).max(0.0))
+                .sum::<f32>();
+            onsets.push(flux);
+            previous_spectrum = spectrum;
+        });
+
+        chroma_stft.push(&downsampled, |magnitudes| {
+            add_chroma(magnitudes, &mut chroma);
+        });
+    })?;
+
+    Ok(TempoKey {
+        bpm: estimate_bpm(&onsets),
+        key: estimate_key(&chroma)
+    })
+}
+
+/// Find the most likely beat period from the autocorrelation of the onset strength
+fn estimate_bpm(onsets: &[f32]) -> Option<u32> {
+    let frame_rate = SAMPLE_RATE as f32 / ONSET_HOP_SIZE as f32;
+    let min_lag = (frame_rate * 60.0 / MAX_BPM) as usize;
+    let max_lag = (frame_rate * 60.0 / MIN_BPM) as usize;
+
+    if onsets.len() <= max_lag * 2 {
+        return None;
+    }
+
+    let mean = onsets.iter().sum::<f32>() / onsets.len() as f32;
+    let centred = onsets.iter().map(|o| o - mean).collect::<Vec<f32>>();
+
+    let autocorrelation = |lag: usize| centred
+        .iter()
+        .zip(&centred[lag..])
+        .map(|(a, b)| a * b)
+        .sum::<f32>() / (centred.len() - lag) as f32;
+
+    let scores = (min_lag - 1..=max_lag + 1)
+        .map(|lag| {
+            // Weight towards the preferred tempo, by distance in octaves, to resolve half and double time
+            let bpm = frame_rate * 60.0 / lag as f32;
+            let octaves = (bpm / PREFERRED_BPM).log2();
+            (lag, autocorrelation(lag) * (-0.5 * octaves * octaves).exp())
+        })
+        .collect::<Vec<(usize, f32)>>();
+
+    let best = (1..scores.len() - 1).max_by(|a, b| scores[*a].1.total_cmp(&scores[*b].1))?;
+    if scores[best].1 <= 0.0 {
+        return None;
+    }
+
+    // Interpolate between neighbouring lags for a more precise period
+    let (previous, current, next) = (scores[best - 1].1, scores[best].1, scores[best + 1].1);
+    let denominator = previous - 2.0 * current + next;
+    let offset = if denominator != 0.0 { 0.5 * (previous - next) / denominator } else { 0.0 };
+    let lag = scores[best].0 as f32 + offset.clamp(-0.5, 0.5);
+
+    Some((frame_rate * 60.0 / lag).round() as u32)
+}
+
+fn add_chroma(magnitudes: &[f32], chroma: &mut [f32; 12]) {
+    let bin_width = SAMPLE_RATE as f32 / CHROMA_FRAME_SIZE as f32;
+    for (bin, magnitude) in magnitudes.iter().enumerate() {
+        let frequency = bin as f32 * bin_width;
+        if (MIN_CHROMA_FREQUENCY..=MAX_CHROMA_FREQUENCY).contains(&frequency) {
+            chroma[to_pitch_class(frequency)] += magnitude * magnitude;
+        }
+    }
+}
+
+/// Find the major or minor key whose profile best correlates with the overall chroma
+fn estimate_key(chroma: &[f32; 12]) -> Option<String> {
+    if chroma.iter().all(|c| *c == 0.0) {
+        return None;
+    }
+
+    (0..12)
+        .flat_map(|tonic| [(tonic, false), (tonic, true)])
+        .map(|(tonic, minor)| {
+            let profile = if minor { &MINOR_PROFILE } else { &MAJOR_PROFILE };
+            let rotated = (0..12).map(|i| profile[(i + 12 - tonic) % 12]).collect::<Vec<f32>>();
+            (tonic, minor, correlation(chroma, &rotated))
+        })
+        .max_by(|a, b| a.2.total_cmp(&b.2))
+        .map(|(tonic, minor, _)| format!("{}{}", NOTE_NAMES[tonic], if minor { "m" } else { "" }))
+}
+
+fn correlation(a: &[f32], b: &[f32]) -> f32 {
+    let mean_a = a.iter().sum::<f32>() / a.len() as f32;
+    let mean_b = b.iter().sum::<f32>() / b.len() as f32;
+
+    let (covariance, variance_a, variance_b) = a
+        .iter()
+        .zip(b)
+        .fold((0.0, 0.0, 0.0), |(c, va, vb), (x, y)| {
+            let (dx, dy) = (x - mean_a, y - mean_b);
+            (c + dx * dy, va + dx * dx, vb + dy * dy)
+        });
+
+    covariance / (variance_a * variance_b).sqrt().max(f32::EPSILON)
+}
@@ -0,0 +1,23 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum TempoKeyMode {
+    /// Do not write tempo and key tags
+    Off,
+    /// Estimate the tempo and key of every track and write new tags
+    Analyse,
+    /// Keep existing tempo and key values, only estimating those that are missing
+    Keep,
+}
+
+impl Display for TempoKeyMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TempoKeyMode::Off => f.write_str("off"),
+            TempoKeyMode::Analyse => f.write_str("analyse"),
+            TempoKeyMode::Keep => f.write_str("keep"),
+        }
+    }
+}
@@ -0,0 +1,28 @@
+use std::path::PathBuf;
+
+use crate::{media_file::MediaFile, error::CleanerError};
+
+/// An audio file whose tags or properties could not be read, with the reason
+pub struct UnreadableFile {
+    path: PathBuf,
+    error: CleanerError
+}
+
+impl UnreadableFile {
+    pub fn new(path: PathBuf, error: CleanerError) -> UnreadableFile {
+        UnreadableFile {
+            path,
+            error
+        }
+    }
+
+    pub fn error(&self) -> &CleanerError {
+        &self.error
+    }
+}
+
+impl MediaFile for UnreadableFile {
+    fn path(&self) -> &PathBuf {
+        &self.path
+    }
+}